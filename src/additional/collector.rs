@@ -0,0 +1,429 @@
+use custom_logger::*;
+use futures::stream::FuturesUnordered;
+use futures::stream::StreamExt;
+use mirror_catalog_index::*;
+use mirror_copy::*;
+use std::fs;
+use std::path::Path;
+
+use crate::api::schema::Report;
+use crate::cluster::resources::{get_manifest_digests, get_mirror_repo};
+use crate::config::destination::DestinationTemplate;
+use crate::config::load::*;
+use crate::error::handler::{registry_error, MirrorError};
+use crate::journal::run_state::*;
+use crate::operator::collector::*;
+use crate::reference::parser::Reference;
+use crate::release::collector::{get_all_assosciated_manifests, get_manifest_lists};
+use crate::scheduler::registry::RegistryScheduler;
+use crate::storage::Storage;
+use crate::stream::registry::{Repository, StreamCopy};
+
+// collect all additional images
+pub async fn additional_mirror_to_disk<T: RegistryInterface>(
+    reg_con: T,
+    scheduler: &RegistryScheduler,
+    log: &Logging,
+    storage: &dyn Storage,
+    state: &mut RunState,
+    architectures: &[String],
+    images: Vec<Image>,
+) -> Result<Report, MirrorError> {
+    log.hi("additional collector mode: mirrorToDisk");
+    let dir = storage.local_dir();
+
+    let mut futs = FuturesUnordered::new();
+    let batch_size = scheduler.max_in_flight();
+    let mut blob_tracker: Vec<String> = vec![];
+    let mut report = Report::default();
+    // the journal as it was loaded, its blobs are not fetched again
+    let recorded = state.clone();
+
+    for img in images.iter() {
+        let ir = parse_additional_image(log, img.name.clone())?;
+        let add_dir =
+            get_additional_manifest_json_dir(dir.clone(), &ir.namespace, &ir.name, &ir.version);
+        // skip images completed in a previous (interrupted) run
+        if state.is_image_done(&img.name) && Path::new(&add_dir).exists() {
+            log.debug(&format!("checkpoint found for {}", img.name));
+            continue;
+        }
+        let url = get_image_manifest_url(ir.clone());
+        log.info(&format!(
+            "  checking manifest {:#?}",
+            ir.namespace.clone() + "/" + &ir.name
+        ));
+        let manifest = scheduler
+            .run(log, &url, |url, token| reg_con.get_manifest(url, token))
+            .await
+            .map_err(|err| registry_error(&url, err))?;
+        log.trace(&format!("manifest {:#?}", manifest));
+
+        fs::create_dir_all(add_dir.clone())?;
+        log.debug(&format!("additional image manifest path {:#?}", add_dir));
+
+        let mut fslayers: Vec<FsLayer> = Vec::new();
+        let manifest_list = parse_json_manifestlist(manifest.clone());
+        if manifest_list.is_ok() {
+            let ml = manifest_list.unwrap().clone();
+            log.trace(&format!("manifest list detected {:#?}", ml));
+            fs::write(add_dir.clone() + "/manifest-list.json", manifest.clone())?;
+            // loop through each manifest and fetch it by digest
+            for mf in ml.manifests.iter() {
+                let mut sub_ir = ir.clone();
+                sub_ir.version = mf.digest.clone().ok_or_else(|| {
+                    MirrorError::ManifestParse(format!(
+                        "manifest list entry without digest for {}",
+                        img.name
+                    ))
+                })?;
+                let platform = mf.platform.clone().ok_or_else(|| {
+                    MirrorError::ManifestParse(format!(
+                        "manifest list entry without platform for {}",
+                        img.name
+                    ))
+                })?;
+                if !is_architecture_mirrored(architectures, &platform.architecture) {
+                    log.debug(&format!(
+                        "skipping architecture {} for {}",
+                        platform.architecture, img.name
+                    ));
+                    continue;
+                }
+                let sub_manifest_url = get_image_manifest_url(sub_ir);
+                log.trace(&format!("sub manifest url {:#?}", sub_manifest_url.clone()));
+                let local_manifest = scheduler
+                    .run(log, &sub_manifest_url, |url, token| {
+                        reg_con.get_manifest(url, token)
+                    })
+                    .await
+                    .map_err(|err| registry_error(&sub_manifest_url, err))?;
+                fs::write(
+                    add_dir.clone() + "/manifest-" + &platform.architecture + ".json",
+                    local_manifest.clone(),
+                )?;
+                let op_manifest =
+                    parse_json_manifest_operator(local_manifest.clone()).map_err(|err| {
+                        MirrorError::ManifestParse(format!("{} {}", sub_manifest_url, err))
+                    })?;
+                fslayers.append(&mut get_operator_fslayers(
+                    op_manifest,
+                    img.name.clone(),
+                    &mut blob_tracker,
+                )?);
+            }
+        } else {
+            fs::write(add_dir.clone() + "/manifest.json", manifest.clone())?;
+            let op_manifest = parse_json_manifest_operator(manifest.clone())
+                .map_err(|err| MirrorError::ManifestParse(format!("{} {}", url, err)))?;
+            log.trace(&format!("op_manifest {:#?}", op_manifest));
+            fslayers.append(&mut get_operator_fslayers(
+                op_manifest,
+                img.name.clone(),
+                &mut blob_tracker,
+            )?);
+        }
+        report.images += 1;
+        report.blobs += fslayers.len();
+        report.bytes += fslayers.iter().map(|l| l.size.unwrap_or(0)).sum::<i64>();
+
+        if state.metadata_only {
+            continue;
+        }
+        let blobs_url = get_blobs_url(ir.clone());
+        // batch the calls
+        futs.push(get_blobs_tracked(
+            &reg_con,
+            scheduler,
+            log,
+            storage,
+            blobs_url,
+            img.name.clone(),
+            fslayers,
+            &recorded,
+        ));
+        if futs.len() >= batch_size {
+            let (image, blobs, response) = futs.next().await.unwrap();
+            let response = response?;
+            state.checkpoint(image, blobs)?;
+            log.debug(&format!(
+                "completed batch of {} {:#?}",
+                batch_size, response
+            ));
+        }
+    }
+    // wait for the remaining to finish.
+    while let Some((image, blobs, response)) = futs.next().await {
+        let response = response?;
+        state.checkpoint(image, blobs)?;
+        log.debug(&format!("completed rest of batch {:#?}", response));
+    }
+    Ok(report)
+}
+
+pub async fn additional_disk_to_mirror<T: RegistryInterface>(
+    reg_con: T,
+    scheduler: &RegistryScheduler,
+    log: &Logging,
+    dir: String,
+    destination_url: String,
+    template: &DestinationTemplate,
+    images: Vec<Image>,
+) -> Result<Report, MirrorError> {
+    log.hi("additional collector mode: diskToMirror");
+    let mut report = Report::default();
+    // manifest lists are pushed with the scheduler once the manifests they list were pushed
+    let manifests = get_manifest_digests(&dir)?;
+    let mut copy = StreamCopy::new(&(dir.clone() + "blobs-store/"), scheduler);
+    for img in images.iter() {
+        let ir = parse_additional_image(log, img.name.clone())?;
+        let add_dir =
+            get_additional_manifest_json_dir(dir.clone(), &ir.namespace, &ir.name, &ir.version);
+        log.debug(&format!("additional image directory {}", add_dir.clone()));
+        let sub_component =
+            template.get_sub_component(None, &(ir.namespace.clone() + "/" + &ir.name));
+        let mm_list = get_all_assosciated_manifests(log, add_dir.clone());
+        // using map and collect are not async
+        for mm in mm_list.iter() {
+            let manifest = get_additional_manifest(mm.to_string())?;
+            log.trace(&format!("manifest struct {:#?}", manifest));
+            reg_con
+                .push_image(
+                    log,
+                    dir.clone(),
+                    sub_component.clone(),
+                    destination_url.clone(),
+                    String::from(""),
+                    manifest.clone(),
+                )
+                .await
+                .map_err(|err| {
+                    MirrorError::RegistryHttp(format!("{} {:?}", destination_url, err))
+                })?;
+            report.images += 1;
+        }
+        // a list pulled by tag is pushed with the tag, otherwise by its digest
+        let tag = match Reference::parse(&img.name)?.digest {
+            Some(_) => None,
+            None => Some(ir.version.clone()),
+        };
+        let dest = Repository::new(&get_mirror_repo(&destination_url, &sub_component))?;
+        for list in get_manifest_lists(log, add_dir) {
+            copy.push_manifest_list(
+                log,
+                &dest,
+                &fs::read_to_string(&list)?,
+                tag.as_deref(),
+                &manifests,
+            )
+            .await?;
+            report.images += 1;
+        }
+    }
+    Ok(report)
+}
+
+// parse_additional_image - parse an image reference that can carry a tag or a digest
+// i.e. quay.io/ns/name:tag, quay.io/ns/sub/name@sha256:abc (tag defaults to latest)
+pub fn parse_additional_image(log: &Logging, img: String) -> Result<ImageReference, MirrorError> {
+    let ir = Reference::parse(&img)?.to_image_reference();
+    log.trace(&format!("image reference {:#?}", ir));
+    Ok(ir)
+}
+
+// utility functions - get_additional_manifest_json_dir
+pub fn get_additional_manifest_json_dir(
+    dir: String,
+    namespace: &str,
+    name: &str,
+    version: &str,
+) -> String {
+    // ./working-dir
+    let mut file = dir.clone();
+    file.push_str(&"additional/");
+    file.push_str(&(namespace.to_owned() + "/"));
+    file.push_str(&(name.to_owned() + "/"));
+    file.push_str(&version);
+    file
+}
+
+fn get_additional_manifest(dir: String) -> Result<Manifest, MirrorError> {
+    let data = fs::read_to_string(&dir)?;
+    let manifest = parse_json_manifest_operator(data)
+        .map_err(|err| MirrorError::ManifestParse(format!("{} {}", dir, err)))?;
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use crate::auth::token_cache::TokenCache;
+    use crate::config::registries::SourceMirrors;
+    use crate::scheduler::registry::SchedulerConfig;
+    use crate::storage::local::LocalStorage;
+    use async_trait::async_trait;
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    #[test]
+    fn parse_additional_image_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let ir = parse_additional_image(log, String::from("quay.io/ns/name:v1.0")).unwrap();
+        assert_eq!(ir.registry, String::from("quay.io"));
+        assert_eq!(ir.namespace, String::from("ns"));
+        assert_eq!(ir.name, String::from("name"));
+        assert_eq!(ir.version, String::from("v1.0"));
+
+        let ir = parse_additional_image(
+            log,
+            String::from("registry.redhat.io/ubi9/sub/ubi@sha256:5e03f571c5993f0853a910b7c0cab44ec0e451b94a9677ed82e921b54a4b735a"),
+        )
+        .unwrap();
+        assert_eq!(ir.namespace, String::from("ubi9/sub"));
+        assert_eq!(ir.name, String::from("ubi"));
+        assert_eq!(
+            ir.version,
+            String::from("sha256:5e03f571c5993f0853a910b7c0cab44ec0e451b94a9677ed82e921b54a4b735a")
+        );
+
+        let ir = parse_additional_image(log, String::from("quay.io/ns/name")).unwrap();
+        assert_eq!(ir.version, String::from("latest"));
+
+        let ir = parse_additional_image(log, String::from("localhost:5000/name:v1")).unwrap();
+        assert_eq!(ir.registry, String::from("localhost:5000"));
+        assert_eq!(ir.namespace, String::from(""));
+        assert!(parse_additional_image(log, String::from("quay.io/ns/name@sha256:abc")).is_err());
+    }
+
+    #[test]
+    fn mirror_to_disk_pass() {
+        let log = &Logging {
+            log_level: Level::DEBUG,
+        };
+
+        // we set up a mock server for the auth-credentials
+        let mut server = mockito::Server::new();
+        let url = server.url();
+
+        // Create a mock
+        server
+            .mock("GET", "/auth")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                "{
+                    \"token\": \"test\",
+                    \"access_token\": \"aebcdef1234567890\",
+                    \"expires_in\":300,
+                    \"issued_at\":\"2023-10-20T13:23:31Z\"
+                }",
+            )
+            .create();
+
+        #[derive(Clone)]
+        struct Fake {}
+
+        #[async_trait]
+        impl RegistryInterface for Fake {
+            async fn get_manifest(
+                &self,
+                url: String,
+                _token: String,
+            ) -> Result<String, Box<dyn std::error::Error>> {
+                let mut content = String::from("");
+                if url.contains("manifests/v1.0") {
+                    content =
+                        fs::read_to_string("test-artifacts/simulate-api-call/manifest-list.json")
+                            .expect("should read test manifest-list file");
+                }
+                if url.contains("5e03f571c5993f0853a910b7c0cab44ec0e451b94a9677ed82e921b54a4b735a")
+                {
+                    content =
+                        fs::read_to_string("test-artifacts/simulate-api-call/manifest-amd64.json")
+                            .expect("should read test manifest-amd64 file");
+                }
+                Ok(content)
+            }
+
+            async fn get_blobs(
+                &self,
+                log: &Logging,
+                _dir: String,
+                _url: String,
+                _token: String,
+                _layers: Vec<FsLayer>,
+            ) -> Result<String, Box<dyn std::error::Error>> {
+                log.info("testing logging in fake test");
+                Ok(String::from("test"))
+            }
+
+            async fn push_image(
+                &self,
+                log: &Logging,
+                _dir: String,
+                _subdir: String,
+                _url: String,
+                _token: String,
+                _manifest: Manifest,
+            ) -> Result<String, mirror_copy::MirrorError> {
+                log.info("testing logging in fake test");
+                Ok(String::from("test"))
+            }
+        }
+
+        let fake = Fake {};
+        let imgs = vec![Image {
+            name: String::from(url.replace("http://", "") + "/test/test-additional:v1.0"),
+        }];
+        let mut state = RunState::default();
+        let scheduler = RegistryScheduler::new(
+            SchedulerConfig::default(),
+            &[],
+            SourceMirrors::default(),
+            TokenCache::anonymous(),
+        );
+        let res = aw!(additional_mirror_to_disk(
+            fake.clone(),
+            &scheduler,
+            log,
+            &LocalStorage::new("./test-artifacts/additional-test/"),
+            &mut state,
+            &[String::from("amd64")],
+            imgs.clone()
+        ));
+        assert_eq!(res.unwrap().images, 1);
+        let base = "./test-artifacts/additional-test/additional/test/test-additional/v1.0";
+        assert!(fs::metadata(base.to_string() + "/manifest-list.json").is_ok());
+        assert!(fs::metadata(base.to_string() + "/manifest-amd64.json").is_ok());
+
+        // the list is pushed with its tag after the amd64 manifest
+        let list = server
+            .mock("PUT", "/v2/test/test/test-additional/manifests/v1.0")
+            .match_body(
+                fs::read_to_string(base.to_string() + "/manifest-list.json")
+                    .unwrap()
+                    .as_str(),
+            )
+            .with_status(201)
+            .create();
+        let res = aw!(additional_disk_to_mirror(
+            fake,
+            &scheduler,
+            log,
+            String::from("./test-artifacts/additional-test/"),
+            String::from("docker://") + &server.host_with_port() + "/test",
+            &DestinationTemplate::default(),
+            imgs
+        ));
+        assert_eq!(res.unwrap().images, 2);
+        list.assert();
+        rm_rf::remove("./test-artifacts/additional-test").expect("should delete test dir");
+    }
+}
@@ -1,6 +1,12 @@
 // module schema
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::load::{ImageSetConfig, SourceRegistry};
+use crate::config::registries::SourceMirrors;
+use crate::diff::metadata_cache::parse_archive_size;
+use crate::scheduler::registry::SchedulerConfig;
 
 /// rust-container-tool cli struct
 #[derive(Parser, Debug)]
@@ -18,6 +24,17 @@ pub struct Cli {
     #[arg(short, long, value_name = "diff-tar", default_value = "false")]
     pub diff_tar: Option<bool>,
 
+    /// used only in conjuction with --diff-tar, split the archive into volumes of
+    /// at most archive-size bytes (mirror_000001.tar, ...), the size takes an M or G
+    /// suffix i.e. 4G, 0 creates a single tar.gz
+    #[arg(
+        long,
+        value_name = "archive-size",
+        default_value = "0",
+        value_parser = parse_archive_size
+    )]
+    pub archive_size: u64,
+
     /// used only in conjuction with --diff-tar with format yyyy/mm/dd (will be ignored otherwise)
     #[arg(long, value_name = "date", default_value = "")]
     pub date: Option<String>,
@@ -26,7 +43,7 @@ pub struct Cli {
     #[arg(value_enum, long, value_name = "loglevel", default_value = "info")]
     pub loglevel: Option<String>,
 
-    /// set the destination. Valid prefix are docker:// or file://
+    /// set the destination. Valid prefix are docker://, oci:// (OCI image layout) or file://
     #[arg(
         value_enum,
         long,
@@ -35,6 +52,21 @@ pub struct Cli {
     )]
     pub destination: String,
 
+    /// set the workspace (working-dir) backend and root. Valid prefix are file:// (or a plain
+    /// path) and s3://bucket/prefix (credentials and endpoint from the AWS_* environment)
+    #[arg(
+        long,
+        value_name = "workspace",
+        default_value = "file://./working-dir/"
+    )]
+    pub workspace: String,
+
+    /// set the source for diskToMirror. Valid prefix is archive:// (a mirror-diff.tar.gz or
+    /// a directory with mirror_*.tar volumes), if not set the local working-dir is used.
+    /// docker:// copies the images directly from the source registries (mirrorToMirror)
+    #[arg(long, value_name = "from")]
+    pub from: Option<String>,
+
     /// set the skip flag. Valid arguments are none, release, operators, additional,
     /// release-operators
     #[arg(value_enum, long, value_name = "skip", default_value = "none")]
@@ -59,6 +91,143 @@ pub struct Cli {
         default_value = "false"
     )]
     pub skip_gen_declconfig: bool,
+
+    /// resume an interrupted mirrorToDisk run from the last checkpoint in working-dir
+    #[arg(long, value_name = "resume", default_value = "false")]
+    pub resume: bool,
+
+    /// discard the checkpoint in working-dir and start a new mirrorToDisk run
+    #[arg(long, value_name = "restart", default_value = "false")]
+    pub restart: bool,
+
+    /// resolve all images without downloading blobs, writes working-dir/dry-run/mapping.txt
+    /// (source=destination) and working-dir/dry-run/summary.json with the compressed sizes
+    #[arg(long, value_name = "dry-run", default_value = "false")]
+    pub dry_run: bool,
+
+    /// maximum concurrent requests per registry, the registries section of the config
+    /// can override it per registry
+    #[arg(long, value_name = "max-concurrency", default_value = "8")]
+    pub max_concurrency: usize,
+
+    /// maximum requests per second per registry, 0 disables rate limiting
+    #[arg(long, value_name = "rate-limit", default_value = "0")]
+    pub rate_limit: f64,
+
+    /// retries with exponential backoff for throttled (429) or unavailable (503) requests
+    #[arg(long, value_name = "max-retries", default_value = "5")]
+    pub max_retries: u32,
+
+    /// containers registries.conf with source registry rewrites and mirrors ([[registry]]
+    /// and [[registry.mirror]] entries), added after the sourceRegistries of the config
+    #[arg(long, value_name = "registries-conf")]
+    pub registries_conf: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+/// subcommands
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// verify every blob referenced by the manifests in working-dir
+    Verify {
+        /// re-fetch missing, truncated or corrupt blobs (requires --config)
+        #[arg(long, value_name = "repair", default_value = "false")]
+        repair: bool,
+    },
+    /// remove the images of a DeleteImageSetConfiguration from working-dir, blobs still
+    /// referenced by other mirrored images are kept
+    Delete {
+        /// path to the DeleteImageSetConfiguration
+        #[arg(long, value_name = "delete-config")]
+        delete_config: String,
+
+        /// also delete the manifests from the docker:// destination registry
+        #[arg(long, value_name = "registry", default_value = "false")]
+        registry: bool,
+    },
+    /// remove the blobs in blobs-store that are not referenced by any manifest in working-dir
+    Gc {
+        /// report the blobs and bytes that would be reclaimed without removing them
+        #[arg(long, value_name = "dry-run", default_value = "false")]
+        dry_run: bool,
+    },
+}
+
+// skip enums
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Skip {
+    Release,
+    Operators,
+    Additional,
+    ReleaseOperators,
+    None,
+}
+
+impl Skip {
+    // convert the --skip flag value to the Skip enum
+    pub fn from_flag(value: &str) -> Option<Skip> {
+        match value {
+            "none" => Some(Skip::None),
+            "release" => Some(Skip::Release),
+            "operators" => Some(Skip::Operators),
+            "additional" => Some(Skip::Additional),
+            "release-operators" => Some(Skip::ReleaseOperators),
+            _ => None,
+        }
+    }
+
+    pub fn release(&self) -> bool {
+        *self == Skip::Release || *self == Skip::ReleaseOperators
+    }
+
+    pub fn operators(&self) -> bool {
+        *self == Skip::Operators || *self == Skip::ReleaseOperators
+    }
+
+    pub fn additional(&self) -> bool {
+        *self == Skip::Additional
+    }
+}
+
+// command line settings of the mirrorToDisk collectors, shared by verify --repair,
+// dry-run and mirrorToMirror
+#[derive(Debug, Clone)]
+pub struct MirrorOptions {
+    pub skip: Skip,
+    pub skip_manifest_check: bool,
+    pub skip_gen: bool,
+    // defaults for registries without settings in the config
+    pub scheduler: SchedulerConfig,
+    // source registries read from --registries-conf
+    pub source_registries: Vec<SourceRegistry>,
+}
+
+impl MirrorOptions {
+    // the source mirrors of the config followed by the ones of --registries-conf
+    pub fn get_source_mirrors(&self, isc_config: &ImageSetConfig) -> SourceMirrors {
+        let mut registries = isc_config.source_registries.clone();
+        registries.extend(self.source_registries.iter().cloned());
+        SourceMirrors::new(registries)
+    }
+}
+
+// summary returned by each collector
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    pub images: usize,
+    pub blobs: usize,
+    pub bytes: i64,
+}
+
+impl Report {
+    // add the counts of another report to this one
+    pub fn add(&mut self, other: Report) {
+        self.images += other.images;
+        self.blobs += other.blobs;
+        self.bytes += other.bytes;
+    }
 }
 
 /*
@@ -272,17 +441,6 @@ pub struct RegistryRedhatIo {
     pub email: Option<String>,
 }
 
-// skip enums
-#[derive(Eq, PartialEq)]
-pub enum Skip {
-    RELEASE,
-    OPERATORS,
-    ADDITIONAL,
-    RELOPS,
-    NONE,
-}
-
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CatalogImage {
     #[serde(rename = "name")]
@@ -475,3 +633,26 @@ pub trait RegistryInterface {
     ) -> Result<String, MirrorError>;
 }
 */
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+
+    #[test]
+    fn skip_from_flag_pass() {
+        let skip = Skip::from_flag("release-operators").unwrap();
+        assert!(skip.release());
+        assert!(skip.operators());
+        assert!(!skip.additional());
+        let skip = Skip::from_flag("additional").unwrap();
+        assert!(!skip.release());
+        assert!(skip.additional());
+        assert_eq!(Skip::from_flag("none"), Some(Skip::None));
+    }
+
+    #[test]
+    fn skip_from_flag_fail() {
+        assert_eq!(Skip::from_flag("nada"), None);
+    }
+}
@@ -0,0 +1,405 @@
+use custom_logger::*;
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::handler::MirrorError;
+use crate::scheduler::registry::get_registry;
+use crate::stream::registry::get_registry_url;
+
+// registries that do not report the token lifetime issue tokens valid for 60 seconds
+const DEFAULT_EXPIRES_IN: u64 = 60;
+// tokens are refreshed this long before they expire
+const EXPIRY_MARGIN: u64 = 10;
+// registries without a challenge are checked again after an hour, no token is kept longer
+const ANONYMOUS_EXPIRES_IN: u64 = 3600;
+
+// token scope actions, the collectors and sources pull, destinations are pushed to
+pub const PULL: &str = "pull";
+pub const PUSH: &str = "pull,push";
+pub const DELETE: &str = "pull,push,delete";
+
+// bearer challenge returned by the registry for GET /v2/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+    pub realm: String,
+    pub service: String,
+}
+
+#[derive(Debug, Clone)]
+struct CachedToken {
+    token: String,
+    expires: Instant,
+}
+
+// tokens keyed by registry, repository and scope actions, each related image gets a token
+// for its own registry and repository instead of the token of the catalog
+#[derive(Debug, Default)]
+pub struct TokenCache {
+    client: Client,
+    // without requests every registry is accessed anonymously (tests)
+    anonymous: bool,
+    // None when the registry allows anonymous access
+    challenges: Mutex<HashMap<String, Option<Challenge>>>,
+    tokens: Mutex<HashMap<(String, String, String), CachedToken>>,
+}
+
+impl TokenCache {
+    pub fn new() -> TokenCache {
+        TokenCache::default()
+    }
+
+    pub fn anonymous() -> TokenCache {
+        TokenCache {
+            anonymous: true,
+            ..TokenCache::new()
+        }
+    }
+
+    // the token for a repository and actions (PULL, PUSH or DELETE), requested once and
+    // reused until it expires
+    // a failed request is logged and the repository is accessed anonymously
+    pub async fn get(
+        &self,
+        log: &Logging,
+        registry: &str,
+        repository: &str,
+        actions: &str,
+    ) -> String {
+        if self.anonymous {
+            return String::new();
+        }
+        let key = (
+            registry.to_string(),
+            repository.to_string(),
+            actions.to_string(),
+        );
+        if let Some(cached) = self.tokens.lock().unwrap().get(&key) {
+            if cached.expires > Instant::now() {
+                return cached.token.clone();
+            }
+        }
+        match self.fetch(log, registry, repository, actions).await {
+            Ok((token, expires_in)) => {
+                let lifetime = expires_in
+                    .min(ANONYMOUS_EXPIRES_IN)
+                    .saturating_sub(EXPIRY_MARGIN)
+                    .max(1);
+                self.tokens.lock().unwrap().insert(
+                    key,
+                    CachedToken {
+                        token: token.clone(),
+                        expires: Instant::now() + Duration::from_secs(lifetime),
+                    },
+                );
+                token
+            }
+            Err(err) => {
+                log.error(&format!(
+                    "unable to get token for {}/{} {}",
+                    registry, repository, err
+                ));
+                String::new()
+            }
+        }
+    }
+
+    // drop the tokens of a repository, the next request fetches a new one (401 responses)
+    pub fn invalidate(&self, registry: &str, repository: &str) {
+        self.tokens
+            .lock()
+            .unwrap()
+            .retain(|(r, repo, _), _| r != registry || repo != repository);
+    }
+
+    async fn get_challenge(&self, registry: &str) -> Result<Option<Challenge>, MirrorError> {
+        if let Some(challenge) = self.challenges.lock().unwrap().get(registry) {
+            return Ok(challenge.clone());
+        }
+        let url = get_registry_url(registry) + "/v2/";
+        let res = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|err| MirrorError::RegistryHttp(format!("{} {}", url, err)))?;
+        let challenge = match res.status() {
+            StatusCode::UNAUTHORIZED => {
+                let header = res
+                    .headers()
+                    .get("WWW-Authenticate")
+                    .and_then(|h| h.to_str().ok())
+                    .unwrap_or_default();
+                let challenge = parse_challenge(header).ok_or_else(|| {
+                    MirrorError::Auth(format!("{} unsupported challenge {}", url, header))
+                })?;
+                Some(challenge)
+            }
+            _ => None,
+        };
+        self.challenges
+            .lock()
+            .unwrap()
+            .insert(registry.to_string(), challenge.clone());
+        Ok(challenge)
+    }
+
+    // request a token with the repository scope, returns the token and its lifetime
+    async fn fetch(
+        &self,
+        log: &Logging,
+        registry: &str,
+        repository: &str,
+        actions: &str,
+    ) -> Result<(String, u64), MirrorError> {
+        let challenge = match self.get_challenge(registry).await? {
+            Some(challenge) => challenge,
+            None => return Ok((String::new(), ANONYMOUS_EXPIRES_IN)),
+        };
+        let scope = format!("repository:{}:{}", repository, actions);
+        log.debug(&format!(
+            "requesting token for {} scope {}",
+            registry, scope
+        ));
+        let mut req = self
+            .client
+            .get(&challenge.realm)
+            .query(&[("service", &challenge.service), ("scope", &scope)]);
+        if let Some(auth) = get_credentials(registry) {
+            req = req.header("Authorization", String::from("Basic ") + &auth);
+        }
+        let res = req
+            .send()
+            .await
+            .map_err(|err| MirrorError::RegistryHttp(format!("{} {}", challenge.realm, err)))?;
+        match res.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                return Err(MirrorError::Auth(format!(
+                    "{} returned {} for {}",
+                    challenge.realm,
+                    res.status(),
+                    scope
+                )))
+            }
+            status if !status.is_success() => {
+                return Err(MirrorError::RegistryHttp(format!(
+                    "{} returned {}",
+                    challenge.realm, status
+                )))
+            }
+            _ => {}
+        }
+        let body = res
+            .text()
+            .await
+            .map_err(|err| MirrorError::RegistryHttp(format!("{} {}", challenge.realm, err)))?;
+        let value: Value = serde_json::from_str(&body)?;
+        let token = value["token"]
+            .as_str()
+            .or_else(|| value["access_token"].as_str())
+            .ok_or_else(|| MirrorError::Auth(format!("{} returned no token", challenge.realm)))?;
+        let expires_in = value["expires_in"].as_u64().unwrap_or(DEFAULT_EXPIRES_IN);
+        Ok((token.to_string(), expires_in))
+    }
+}
+
+// parse Bearer realm="https://auth.example.io/token",service="example.io"
+pub fn parse_challenge(header: &str) -> Option<Challenge> {
+    let params = header.strip_prefix("Bearer ")?;
+    let mut realm = None;
+    let mut service = String::new();
+    for param in params.split(",") {
+        let (key, value) = match param.trim().split_once("=") {
+            Some(val) => val,
+            None => continue,
+        };
+        let value = value.trim_matches('"').to_string();
+        match key {
+            "realm" => realm = Some(value),
+            "service" => service = value,
+            _ => {}
+        }
+    }
+    Some(Challenge {
+        realm: realm?,
+        service,
+    })
+}
+
+// the auth files searched for credentials, in the order podman and docker use them
+// $REGISTRY_AUTH_FILE, $XDG_RUNTIME_DIR/containers/auth.json,
+// $XDG_CONFIG_HOME/containers/auth.json (~/.config) and $DOCKER_CONFIG/config.json (~/.docker)
+fn get_auth_files(env: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let mut files = vec![];
+    if let Some(file) = env("REGISTRY_AUTH_FILE") {
+        files.push(file);
+    }
+    if let Some(dir) = env("XDG_RUNTIME_DIR") {
+        files.push(dir + "/containers/auth.json");
+    }
+    let home = env("HOME");
+    match (env("XDG_CONFIG_HOME"), home.clone()) {
+        (Some(dir), _) => files.push(dir + "/containers/auth.json"),
+        (None, Some(home)) => files.push(home + "/.config/containers/auth.json"),
+        _ => {}
+    }
+    match (env("DOCKER_CONFIG"), home) {
+        (Some(dir), _) => files.push(dir + "/config.json"),
+        (None, Some(home)) => files.push(home + "/.docker/config.json"),
+        _ => {}
+    }
+    files
+}
+
+// docker stores the docker.io credentials under https://index.docker.io/v1/
+fn is_same_registry(key: &str, registry: &str) -> bool {
+    let docker_hub = ["docker.io", "index.docker.io", "registry-1.docker.io"];
+    let host = get_registry(key);
+    host == registry || (docker_hub.contains(&registry) && docker_hub.contains(&host.as_str()))
+}
+
+// base64 user:password of a registry from an auth file, an entry for the registry is
+// preferred over an entry for one of its namespaces
+fn get_auth(file: &str, registry: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(&fs::read_to_string(file).ok()?).ok()?;
+    let auths = value["auths"].as_object()?;
+    auths
+        .get(registry)
+        .into_iter()
+        .chain(
+            auths
+                .iter()
+                .filter(|(key, _)| is_same_registry(key, registry))
+                .map(|(_, auth)| auth),
+        )
+        .find_map(|auth| auth["auth"].as_str())
+        .map(|auth| auth.to_string())
+}
+
+// the credentials of a registry from the first auth file that has them
+fn get_credentials(registry: &str) -> Option<String> {
+    get_auth_files(|key| std::env::var(key).ok())
+        .iter()
+        .find_map(|file| get_auth(file, registry))
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use mockito::Matcher;
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    #[test]
+    fn parse_challenge_pass() {
+        let res = parse_challenge(
+            "Bearer realm=\"https://auth.example.io/token\",service=\"example.io\",scope=\"repository:ns/name:pull\"",
+        );
+        assert_eq!(
+            res,
+            Some(Challenge {
+                realm: String::from("https://auth.example.io/token"),
+                service: String::from("example.io"),
+            })
+        );
+        assert_eq!(parse_challenge("Basic realm=\"example\""), None);
+    }
+
+    #[test]
+    fn token_cache_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let mut server = mockito::Server::new();
+        let registry = server.url().replace("http://", "");
+        server
+            .mock("GET", "/v2/")
+            .with_status(401)
+            .with_header(
+                "WWW-Authenticate",
+                &format!("Bearer realm=\"{}/token\",service=\"test\"", server.url()),
+            )
+            .expect(1)
+            .create();
+        let token = server
+            .mock("GET", "/token")
+            .match_query(Matcher::UrlEncoded(
+                "scope".into(),
+                "repository:ns/name:pull".into(),
+            ))
+            .with_status(200)
+            .with_body("{\"token\": \"abcd\", \"expires_in\": 300}")
+            .expect(2)
+            .create();
+        server
+            .mock("GET", "/token")
+            .match_query(Matcher::UrlEncoded(
+                "scope".into(),
+                "repository:ns/other:pull".into(),
+            ))
+            .with_status(200)
+            .with_body("{\"access_token\": \"efgh\"}")
+            .create();
+        let push = server
+            .mock("GET", "/token")
+            .match_query(Matcher::UrlEncoded(
+                "scope".into(),
+                "repository:ns/name:pull,push".into(),
+            ))
+            .with_status(200)
+            .with_body("{\"token\": \"ijkl\"}")
+            .expect(1)
+            .create();
+
+        let cache = TokenCache::new();
+        assert_eq!(aw!(cache.get(log, &registry, "ns/name", PULL)), "abcd");
+        // cached until it expires
+        assert_eq!(aw!(cache.get(log, &registry, "ns/name", PULL)), "abcd");
+        assert_eq!(aw!(cache.get(log, &registry, "ns/other", PULL)), "efgh");
+        // the push scope gets its own token
+        assert_eq!(aw!(cache.get(log, &registry, "ns/name", PUSH)), "ijkl");
+        // refreshed after a 401
+        cache.invalidate(&registry, "ns/name");
+        assert_eq!(aw!(cache.get(log, &registry, "ns/name", PULL)), "abcd");
+        token.assert();
+        push.assert();
+    }
+
+    #[test]
+    fn get_credentials_pass() {
+        let dir = String::from("./test-artifacts/auth-test");
+        let env = |key: &str| match key {
+            "XDG_RUNTIME_DIR" => Some(dir.clone() + "/run"),
+            "HOME" => Some(dir.clone() + "/home"),
+            _ => None,
+        };
+        let files = get_auth_files(env);
+        assert_eq!(
+            files,
+            vec![
+                dir.clone() + "/run/containers/auth.json",
+                dir.clone() + "/home/.config/containers/auth.json",
+                dir.clone() + "/home/.docker/config.json",
+            ]
+        );
+        fs::create_dir_all(dir.clone() + "/home/.docker").expect("should create test dir");
+        fs::write(
+            &files[2],
+            r#"{"auths": {"https://index.docker.io/v1/": {"auth": "aHViOnB3"}, "quay.io/ns": {"auth": "bnM6cHc="}, "quay.io": {"auth": "cXVheTpwdw=="}}}"#,
+        )
+        .expect("should write auth file");
+        // the missing podman auth files are skipped
+        let find = |registry: &str| files.iter().find_map(|f| get_auth(f, registry));
+        assert_eq!(find("docker.io"), Some(String::from("aHViOnB3")));
+        assert_eq!(find("quay.io"), Some(String::from("cXVheTpwdw==")));
+        assert_eq!(find("registry.redhat.io"), None);
+    }
+}
@@ -0,0 +1,639 @@
+use custom_logger::*;
+use mirror_catalog_index::get_cache_dir;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::additional::collector::{get_additional_manifest_json_dir, parse_additional_image};
+use crate::config::destination::{DestinationTemplate, SubComponents, RELEASE_REPOSITORY};
+use crate::config::load::*;
+use crate::error::handler::MirrorError;
+use crate::operator::catalog::get_catalog_dir;
+use crate::operator::collector::{get_catalog_sub_component, get_registry_details};
+use crate::reference::parser::Reference;
+use crate::release::cincinnati::{load_resolved_releases, resolve_releases};
+use crate::release::collector::{convert_release_image_index, parse_json_release_imagereference};
+use crate::verify::blobs::compute_sha256;
+
+const IDMS_NAME: &str = "idms-oc-mirror";
+const ITMS_NAME: &str = "itms-oc-mirror";
+const CATALOG_NAMESPACE: &str = "openshift-marketplace";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ResourceMetaData {
+    #[serde(rename = "name")]
+    pub name: String,
+
+    #[serde(rename = "namespace", skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+// ImageDigestMirrorSet and ImageTagMirrorSet only differ in the spec field
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MirrorSet {
+    #[serde(rename = "apiVersion")]
+    pub api_version: String,
+
+    #[serde(rename = "kind")]
+    pub kind: String,
+
+    #[serde(rename = "metadata")]
+    pub metadata: ResourceMetaData,
+
+    #[serde(rename = "spec")]
+    pub spec: MirrorSetSpec,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MirrorSetSpec {
+    #[serde(rename = "imageDigestMirrors", skip_serializing_if = "Option::is_none")]
+    pub image_digest_mirrors: Option<Vec<ImageMirror>>,
+
+    #[serde(rename = "imageTagMirrors", skip_serializing_if = "Option::is_none")]
+    pub image_tag_mirrors: Option<Vec<ImageMirror>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageMirror {
+    #[serde(rename = "source")]
+    pub source: String,
+
+    #[serde(rename = "mirrors")]
+    pub mirrors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CatalogSource {
+    #[serde(rename = "apiVersion")]
+    pub api_version: String,
+
+    #[serde(rename = "kind")]
+    pub kind: String,
+
+    #[serde(rename = "metadata")]
+    pub metadata: ResourceMetaData,
+
+    #[serde(rename = "spec")]
+    pub spec: CatalogSourceSpec,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CatalogSourceSpec {
+    #[serde(rename = "sourceType")]
+    pub source_type: String,
+
+    #[serde(rename = "image")]
+    pub image: String,
+}
+
+// source repository to mirror repositories, split by digest and tag references
+#[derive(Debug, Default, Clone)]
+pub struct SourceMappings {
+    pub digests: BTreeMap<String, BTreeSet<String>>,
+    pub tags: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl SourceMappings {
+    pub fn add(&mut self, image: &str, mirror: String) -> Result<(), MirrorError> {
+        let (source, by_digest) = get_source_repo(image)?;
+        let map = if by_digest {
+            &mut self.digests
+        } else {
+            &mut self.tags
+        };
+        map.entry(source).or_default().insert(mirror);
+        Ok(())
+    }
+}
+
+// strip the tag and digest from an image reference, references with a digest are by digest
+// i.e. quay.io/ns/name:v1@sha256:abc -> (quay.io/ns/name, true), ubuntu:22.04 -> (docker.io/library/ubuntu, false)
+pub fn get_source_repo(image: &str) -> Result<(String, bool), MirrorError> {
+    let reference = Reference::parse(image)?;
+    Ok((reference.repo(), reference.digest.is_some()))
+}
+
+// the destination repository for a pushed sub component (without the docker:// prefix)
+pub fn get_mirror_repo(destination: &str, sub_component: &str) -> String {
+    let dest = destination
+        .trim_start_matches("docker://")
+        .trim_end_matches("/");
+    let sub = sub_component.trim_matches('/');
+    if sub.is_empty() {
+        return dest.to_string();
+    }
+    dest.to_string() + "/" + sub
+}
+
+// collect the source images of the mirrored release, operator and additional images
+// with the sub component (repository below the destination) they are pushed to
+// source repositories the template maps to the same sub component are an error
+pub fn get_source_images(
+    log: &Logging,
+    dir: String,
+    mirror: &Mirror,
+) -> Result<Vec<(String, String)>, MirrorError> {
+    let mut images = vec![];
+    let template = mirror.get_destination_template()?;
+    let mut sub_components = SubComponents::default();
+
+    // release images are all pushed to the release repository
+    let release_repo = template.get_sub_component(None, RELEASE_REPOSITORY);
+    if mirror.release.is_some() {
+        sub_components.add("the release images", &release_repo)?;
+    }
+    let releases = load_resolved_releases(&dir, mirror.release.clone().unwrap_or_default())?;
+    for release in releases.iter() {
+        images.push((release.image.clone(), release_repo.clone()));
+        let ir = convert_release_image_index(log, release.image.clone())?;
+        let cache_dir = get_cache_dir(dir.clone(), ir.name.clone(), ir.version.clone());
+        let image_refs = WalkDir::new(&cache_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_name() == "image-references");
+        match image_refs {
+            Some(file) => {
+                let imgs = parse_json_release_imagereference(file.path().display().to_string())?;
+                for tag in imgs.spec.tags.iter() {
+                    images.push((tag.from.name.clone(), release_repo.clone()));
+                }
+            }
+            None => log.error(&format!(
+                "no release image-references found in {}",
+                cache_dir
+            )),
+        }
+    }
+
+    // operator images are mapped below the destination (and catalog prefix) by the template
+    for op in mirror.operators.clone().unwrap_or_default().iter() {
+        sub_components.add(
+            &Reference::parse(&op.catalog)?.repository,
+            &get_catalog_sub_component(&template, op)?,
+        )?;
+        let ir = get_registry_details(&op.catalog)?;
+        let config_dir = get_catalog_dir(dir.clone(), &ir.name, &ir.version) + "/configs";
+        if !Path::new(&config_dir).exists() {
+            log.error(&format!("no filtered catalog found for {}", op.catalog));
+            continue;
+        }
+        for entry in WalkDir::new(&config_dir).into_iter().filter_map(|e| e.ok()) {
+            let file = entry.path().display().to_string();
+            if !entry.path().is_file() || !file.ends_with(".json") {
+                continue;
+            }
+            let data = fs::read_to_string(&file)?;
+            for obj in serde_json::Deserializer::from_str(&data).into_iter::<Value>() {
+                let obj = obj?;
+                if obj["schema"].as_str().unwrap_or_default() != "olm.bundle" {
+                    continue;
+                }
+                for ri in obj["relatedImages"].as_array().cloned().unwrap_or_default() {
+                    let image = ri["image"].as_str().unwrap_or_default();
+                    if image.is_empty() {
+                        continue;
+                    }
+                    let reference = Reference::parse(image)?;
+                    let sub_component = template
+                        .get_sub_component(op.destination_prefix.as_deref(), &reference.repository);
+                    sub_components.add(&reference.repository, &sub_component)?;
+                    images.push((image.to_string(), sub_component));
+                }
+            }
+        }
+    }
+
+    for img in mirror.additional_images.clone().unwrap_or_default().iter() {
+        let reference = Reference::parse(&img.name)?;
+        let sub_component = template.get_sub_component(None, &reference.repository);
+        sub_components.add(&reference.repository, &sub_component)?;
+        images.push((img.name.clone(), sub_component));
+    }
+    Ok(images)
+}
+
+// a mirrored image in the working-dir
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirroredImage {
+    pub sub_component: String,
+    pub file: String,
+}
+
+// index the manifest files in the working-dir by the digest of their content
+pub fn get_manifest_digests(dir: &str) -> Result<HashMap<String, String>, MirrorError> {
+    let mut manifests = HashMap::new();
+    for entry in WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != "blobs-store")
+        .filter_map(|e| e.ok())
+    {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !entry.path().is_file() || !name.starts_with("manifest") || !name.ends_with(".json") {
+            continue;
+        }
+        let file = entry.path().display().to_string();
+        let (hash, _) = compute_sha256(&file)?;
+        manifests.insert(String::from("sha256:") + &hash, file);
+    }
+    Ok(manifests)
+}
+
+// the manifest file of an image, images by tag are only mirrored as additional images
+fn get_image_manifest_file(
+    log: &Logging,
+    dir: &str,
+    image: &str,
+    manifests: &HashMap<String, String>,
+) -> Option<String> {
+    if let Some((_, digest)) = image.split_once("@") {
+        return manifests.get(digest).cloned();
+    }
+    let ir = parse_additional_image(log, image.to_string()).ok()?;
+    let add_dir =
+        get_additional_manifest_json_dir(dir.to_string(), &ir.namespace, &ir.name, &ir.version);
+    ["/manifest-list.json", "/manifest.json"]
+        .iter()
+        .map(|f| add_dir.clone() + f)
+        .find(|f| Path::new(f).exists())
+}
+
+// copy of the config with the release channels resolved to release images
+pub async fn resolve_mirror(log: &Logging, mirror: &Mirror) -> Result<Mirror, MirrorError> {
+    let releases = match mirror.release.clone() {
+        Some(releases) => Some(resolve_releases(log, releases, &mirror.get_architectures()).await?),
+        None => None,
+    };
+    Ok(Mirror {
+        release: releases,
+        operators: mirror.operators.clone(),
+        additional_images: mirror.additional_images.clone(),
+        platform: mirror.platform.clone(),
+        destination_template: mirror.destination_template.clone(),
+    })
+}
+
+// copy of the config with the releases resolved by mirrorToDisk
+pub fn get_resolved_mirror(dir: &str, mirror: &Mirror) -> Result<Mirror, MirrorError> {
+    let releases = match mirror.release.clone() {
+        Some(releases) => Some(load_resolved_releases(dir, releases)?),
+        None => None,
+    };
+    Ok(Mirror {
+        release: releases,
+        operators: mirror.operators.clone(),
+        additional_images: mirror.additional_images.clone(),
+        platform: mirror.platform.clone(),
+        destination_template: mirror.destination_template.clone(),
+    })
+}
+
+// the images mirrored to the destination by original reference with their manifest file
+// as with diskToMirror the release payload itself is not copied, only its components
+pub async fn get_mirrored_images(
+    log: &Logging,
+    dir: String,
+    mirror: &Mirror,
+    manifests: &HashMap<String, String>,
+) -> Result<BTreeMap<String, MirroredImage>, MirrorError> {
+    let mirror = get_resolved_mirror(&dir, mirror)?;
+    let template = mirror.get_destination_template()?;
+    let payloads: Vec<String> = mirror
+        .release
+        .iter()
+        .flatten()
+        .map(|r| r.image.clone())
+        .collect();
+    let mut images = BTreeMap::new();
+    for (image, sub_component) in get_source_images(log, dir.clone(), &mirror)? {
+        if payloads.contains(&image) || images.contains_key(&image) {
+            continue;
+        }
+        match get_image_manifest_file(log, &dir, &image, manifests) {
+            Some(file) => {
+                images.insert(
+                    image,
+                    MirroredImage {
+                        sub_component,
+                        file,
+                    },
+                );
+            }
+            None => log.error(&format!("no manifest found for {} in {}", image, dir)),
+        }
+    }
+    // the filtered catalogs are pushed next to the operator images
+    for op in mirror.operators.iter().flatten() {
+        let ir = get_registry_details(&op.catalog)?;
+        let file = get_catalog_dir(dir.clone(), &ir.name, &ir.version) + "/manifest.json";
+        if !Path::new(&file).exists() {
+            log.error(&format!("no filtered catalog found for {}", op.catalog));
+            continue;
+        }
+        images.insert(
+            op.catalog.clone(),
+            MirroredImage {
+                sub_component: get_catalog_sub_component(&template, op)?,
+                file,
+            },
+        );
+    }
+    Ok(images)
+}
+
+// collect the source repositories of the mirrored images and their mirror repositories
+pub fn get_source_mappings(
+    log: &Logging,
+    dir: String,
+    destination: String,
+    mirror: &Mirror,
+) -> Result<SourceMappings, MirrorError> {
+    let mut mappings = SourceMappings::default();
+    for (image, sub_component) in get_source_images(log, dir, mirror)? {
+        mappings.add(&image, get_mirror_repo(&destination, &sub_component))?;
+    }
+    Ok(mappings)
+}
+
+// build the ImageDigestMirrorSet or ImageTagMirrorSet for the mappings
+pub fn get_mirror_set(mappings: &BTreeMap<String, BTreeSet<String>>, by_digest: bool) -> MirrorSet {
+    let mirrors: Vec<ImageMirror> = mappings
+        .iter()
+        .map(|(source, mirrors)| ImageMirror {
+            source: source.clone(),
+            mirrors: mirrors.iter().cloned().collect(),
+        })
+        .collect();
+    let (kind, name) = if by_digest {
+        ("ImageDigestMirrorSet", IDMS_NAME)
+    } else {
+        ("ImageTagMirrorSet", ITMS_NAME)
+    };
+    MirrorSet {
+        api_version: String::from("config.openshift.io/v1"),
+        kind: String::from(kind),
+        metadata: ResourceMetaData {
+            name: String::from(name),
+            namespace: None,
+        },
+        spec: MirrorSetSpec {
+            image_digest_mirrors: if by_digest {
+                Some(mirrors.clone())
+            } else {
+                None
+            },
+            image_tag_mirrors: if by_digest { None } else { Some(mirrors) },
+        },
+    }
+}
+
+// build the CatalogSource for a mirrored catalog
+pub fn get_catalog_source(
+    destination: &str,
+    template: &DestinationTemplate,
+    op: &Operator,
+) -> Result<CatalogSource, MirrorError> {
+    let ir = get_registry_details(&op.catalog)?;
+    // resource names must be valid dns labels i.e. cs-redhat-operator-index-v4-15
+    let name: String = ("cs-".to_string() + &ir.name + "-" + &ir.version)
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    Ok(CatalogSource {
+        api_version: String::from("operators.coreos.com/v1alpha1"),
+        kind: String::from("CatalogSource"),
+        metadata: ResourceMetaData {
+            name,
+            namespace: Some(String::from(CATALOG_NAMESPACE)),
+        },
+        spec: CatalogSourceSpec {
+            source_type: String::from("grpc"),
+            image: get_mirror_repo(destination, &get_catalog_sub_component(template, op)?)
+                + ":"
+                + &ir.version,
+        },
+    })
+}
+
+// write the IDMS, ITMS and CatalogSource yaml files pointing at the mirror
+pub fn generate_cluster_resources(
+    log: &Logging,
+    dir: String,
+    destination: String,
+    mirror: &Mirror,
+    out_dir: String,
+) -> Result<Vec<String>, MirrorError> {
+    let mappings = get_source_mappings(log, dir, destination.clone(), mirror)?;
+    fs::create_dir_all(&out_dir)?;
+    let mut files = vec![];
+    for (by_digest, map, name) in [
+        (true, &mappings.digests, IDMS_NAME),
+        (false, &mappings.tags, ITMS_NAME),
+    ] {
+        if map.is_empty() {
+            continue;
+        }
+        let file = out_dir.clone() + name + ".yaml";
+        fs::write(
+            &file,
+            serde_yaml::to_string(&get_mirror_set(map, by_digest))?,
+        )?;
+        files.push(file);
+    }
+    let template = mirror.get_destination_template()?;
+    for op in mirror.operators.clone().unwrap_or_default().iter() {
+        let cs = get_catalog_source(&destination, &template, op)?;
+        let file = out_dir.clone() + &cs.metadata.name + ".yaml";
+        fs::write(&file, serde_yaml::to_string(&cs)?)?;
+        files.push(file);
+    }
+    for file in files.iter() {
+        log.info(&format!("cluster resource written {}", file));
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+
+    #[test]
+    fn get_source_repo_pass() {
+        let sha = "sha256:5e03f571c5993f0853a910b7c0cab44ec0e451b94a9677ed82e921b54a4b735a";
+        assert_eq!(
+            get_source_repo(&format!("quay.io/ns/name@{}", sha)).unwrap(),
+            (String::from("quay.io/ns/name"), true)
+        );
+        // the tag is dropped with the digest
+        assert_eq!(
+            get_source_repo(&format!("quay.io/ns/name:v1@{}", sha)).unwrap(),
+            (String::from("quay.io/ns/name"), true)
+        );
+        assert_eq!(
+            get_source_repo("localhost:5000/ns/name:v1").unwrap(),
+            (String::from("localhost:5000/ns/name"), false)
+        );
+        assert_eq!(
+            get_source_repo("localhost:5000/ns/name").unwrap(),
+            (String::from("localhost:5000/ns/name"), false)
+        );
+        // references without a registry are on docker hub
+        assert_eq!(
+            get_source_repo("ubuntu:22.04").unwrap(),
+            (String::from("docker.io/library/ubuntu"), false)
+        );
+        assert!(get_source_repo("quay.io/ns/name@sha256:abc").is_err());
+        assert_eq!(
+            get_mirror_repo("docker://localhost:5000/test/", "ns/name"),
+            String::from("localhost:5000/test/ns/name")
+        );
+    }
+
+    #[test]
+    fn get_catalog_source_pass() {
+        let op = Operator {
+            catalog: String::from("registry.redhat.io/redhat/redhat-operator-index:v4.15"),
+            packages: None,
+            destination_prefix: Some(String::from("mirror/catalogs")),
+        };
+        let template = DestinationTemplate::parse("name").unwrap();
+        let cs = get_catalog_source("docker://localhost:5000/test", &template, &op).unwrap();
+        assert_eq!(cs.metadata.name, "cs-redhat-operator-index-v4-15");
+        assert_eq!(
+            cs.spec.image,
+            "localhost:5000/test/mirror/catalogs/redhat-operator-index:v4.15"
+        );
+    }
+
+    #[test]
+    fn get_source_images_fail() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let mut mirror = Mirror {
+            release: None,
+            operators: None,
+            additional_images: Some(vec![
+                Image {
+                    name: String::from("quay.io/a/foo:v1"),
+                },
+                Image {
+                    name: String::from("quay.io/b/foo:v1"),
+                },
+            ]),
+            platform: None,
+            destination_template: None,
+        };
+        let res = get_source_images(log, String::from("./test-artifacts/"), &mirror);
+        assert_eq!(res.unwrap().len(), 2);
+
+        // both repositories are pushed to foo with the name layout
+        mirror.destination_template = Some(String::from("name"));
+        let res = get_source_images(log, String::from("./test-artifacts/"), &mirror);
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+    }
+
+    #[test]
+    fn generate_cluster_resources_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let dir = String::from("./test-artifacts/cluster-test/");
+        let out_dir = dir.clone() + "cluster-resources/";
+        let cache_dir = get_cache_dir(
+            dir.clone(),
+            String::from("ocp-release"),
+            String::from("4.14.6-x86_64"),
+        );
+        fs::create_dir_all(cache_dir.clone() + "/release-manifests")
+            .expect("should create test dir");
+        fs::copy(
+            "test-artifacts/test-release-operator/v1.0/cache/ac202b/release-manifests/image-references",
+            cache_dir + "/release-manifests/image-references",
+        )
+        .expect("should copy image-references");
+        let config_dir =
+            get_catalog_dir(dir.clone(), "redhat-operator-index", "v4.15") + "/configs/albo";
+        fs::create_dir_all(&config_dir).expect("should create test dir");
+        fs::write(
+            config_dir + "/catalog.json",
+            r#"{"schema": "olm.package", "name": "albo"}
+{"schema": "olm.bundle", "name": "albo.v1.0.0", "relatedImages": [{"name": "controller", "image": "registry.redhat.io/albo/controller-rhel8@sha256:5e03f571c5993f0853a910b7c0cab44ec0e451b94a9677ed82e921b54a4b735a"}]}"#,
+        )
+        .expect("should write catalog");
+
+        let mirror = Mirror {
+            release: Some(vec![Release {
+                version: String::from("4.14.6"),
+                image: String::from("quay.io/openshift-release-dev/ocp-release:4.14.6-x86_64"),
+                channel: None,
+                min_version: None,
+                max_version: None,
+                shortest_path: false,
+                graph: None,
+            }]),
+            operators: Some(vec![Operator {
+                catalog: String::from("registry.redhat.io/redhat/redhat-operator-index:v4.15"),
+                packages: None,
+                destination_prefix: None,
+            }]),
+            additional_images: Some(vec![Image {
+                name: String::from("quay.io/ns/name:v1"),
+            }]),
+            platform: None,
+            destination_template: None,
+        };
+        let files = generate_cluster_resources(
+            log,
+            dir.clone(),
+            String::from("docker://localhost:5000/test"),
+            &mirror,
+            out_dir.clone(),
+        )
+        .unwrap();
+        assert_eq!(files.len(), 3);
+
+        let idms: MirrorSet = serde_yaml::from_str(
+            &fs::read_to_string(out_dir.clone() + "idms-oc-mirror.yaml").unwrap(),
+        )
+        .unwrap();
+        let digests = idms.spec.image_digest_mirrors.unwrap();
+        assert_eq!(digests.len(), 2);
+        assert_eq!(
+            digests[0].source,
+            "quay.io/openshift-release-dev/ocp-v4.0-art-dev"
+        );
+        assert_eq!(digests[0].mirrors, vec!["localhost:5000/test/ocp-release"]);
+        assert_eq!(
+            digests[1].source,
+            "registry.redhat.io/albo/controller-rhel8"
+        );
+        assert_eq!(
+            digests[1].mirrors,
+            vec!["localhost:5000/test/albo/controller-rhel8"]
+        );
+
+        let itms: MirrorSet = serde_yaml::from_str(
+            &fs::read_to_string(out_dir.clone() + "itms-oc-mirror.yaml").unwrap(),
+        )
+        .unwrap();
+        let tags = itms.spec.image_tag_mirrors.unwrap();
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0].source, "quay.io/ns/name");
+        assert_eq!(tags[1].source, "quay.io/openshift-release-dev/ocp-release");
+
+        let cs: CatalogSource = serde_yaml::from_str(
+            &fs::read_to_string(out_dir + "cs-redhat-operator-index-v4-15.yaml").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            cs.spec.image,
+            "localhost:5000/test/redhat/redhat-operator-index:v4.15"
+        );
+        rm_rf::remove(&dir).expect("should delete test dir");
+    }
+}
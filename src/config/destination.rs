@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use crate::error::handler::MirrorError;
+
+// the release payload and its components are pushed to a single repository
+pub const RELEASE_REPOSITORY: &str = "ocp-release";
+
+const DEST: &str = "{dest}";
+const PLACEHOLDERS: [&str; 3] = ["{namespace}", "{name}", "{flat}"];
+
+// named layouts, {flat} is the repository with '/' replaced by '-'
+//   nested  quay.io/ns/sub/name -> {dest}/ns/sub/name (default)
+//   name    quay.io/ns/sub/name -> {dest}/name
+//   flat    quay.io/ns/sub/name -> {dest}/ns-sub-name
+const LAYOUTS: [(&str, &str); 3] = [
+    ("nested", "{dest}/{namespace}/{name}"),
+    ("name", "{dest}/{name}"),
+    ("flat", "{dest}/{flat}"),
+];
+
+// maps a source repository to the repository it is pushed to below the destination
+#[derive(Debug, Clone, PartialEq)]
+pub struct DestinationTemplate {
+    template: String,
+}
+
+impl Default for DestinationTemplate {
+    fn default() -> Self {
+        DestinationTemplate {
+            template: String::from(LAYOUTS[0].1),
+        }
+    }
+}
+
+impl DestinationTemplate {
+    // a named layout or a template i.e. {dest}/mirror/{name}
+    pub fn parse(value: &str) -> Result<DestinationTemplate, MirrorError> {
+        let template = LAYOUTS
+            .iter()
+            .find(|(name, _)| *name == value)
+            .map(|(_, template)| *template)
+            .unwrap_or(value);
+        let invalid = |reason: &str| {
+            MirrorError::Config(format!("invalid destination template {} {}", value, reason))
+        };
+        if !template.starts_with("{dest}/") {
+            return Err(invalid("(must start with {dest})"));
+        }
+        let mut rest = template[DEST.len()..].to_string();
+        for placeholder in PLACEHOLDERS.iter() {
+            rest = rest.replace(placeholder, "");
+        }
+        if rest.contains("{") || rest.contains("}") {
+            return Err(invalid("(unknown placeholder)"));
+        }
+        if !template.contains("{name}") && !template.contains("{flat}") {
+            return Err(invalid("(must contain {name} or {flat})"));
+        }
+        Ok(DestinationTemplate {
+            template: template.to_string(),
+        })
+    }
+
+    // the sub component (repository below the destination) for a source repository
+    // (namespace and name) with an optional prefix i.e. a per catalog destinationPrefix
+    pub fn get_sub_component(&self, prefix: Option<&str>, repository: &str) -> String {
+        let (namespace, name) = repository.rsplit_once("/").unwrap_or(("", repository));
+        let rendered = self.template[DEST.len()..]
+            .replace("{namespace}", namespace)
+            .replace("{name}", name)
+            .replace("{flat}", &repository.replace("/", "-"));
+        prefix
+            .unwrap_or_default()
+            .split("/")
+            .chain(rendered.split("/"))
+            .filter(|component| !component.is_empty())
+            .collect::<Vec<&str>>()
+            .join("/")
+    }
+}
+
+// the sub components resolved by a template and the source repository of each, two source
+// repositories pushed to the same sub component (i.e. a/foo and b/foo with the name layout)
+// would overwrite each others tags
+#[derive(Debug, Default, Clone)]
+pub struct SubComponents {
+    sources: HashMap<String, String>,
+}
+
+impl SubComponents {
+    pub fn add(&mut self, source: &str, sub_component: &str) -> Result<(), MirrorError> {
+        match self.sources.get(sub_component) {
+            Some(other) if other != source => Err(MirrorError::Config(format!(
+                "destination template maps {} and {} to the same repository {}",
+                other, source, sub_component
+            ))),
+            Some(_) => Ok(()),
+            None => {
+                self.sources
+                    .insert(sub_component.to_string(), source.to_string());
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+
+    #[test]
+    fn get_sub_component_pass() {
+        let repository = "openshift4/sub/ose-kube-rbac-proxy";
+        let nested = DestinationTemplate::default();
+        assert_eq!(nested, DestinationTemplate::parse("nested").unwrap());
+        assert_eq!(
+            nested.get_sub_component(None, repository),
+            "openshift4/sub/ose-kube-rbac-proxy"
+        );
+        // names without a namespace don't leave an empty path component
+        assert_eq!(
+            nested.get_sub_component(None, RELEASE_REPOSITORY),
+            RELEASE_REPOSITORY
+        );
+        assert_eq!(
+            nested.get_sub_component(Some("/redhat-operators/"), repository),
+            "redhat-operators/openshift4/sub/ose-kube-rbac-proxy"
+        );
+
+        let name = DestinationTemplate::parse("name").unwrap();
+        assert_eq!(
+            name.get_sub_component(Some("redhat"), repository),
+            "redhat/ose-kube-rbac-proxy"
+        );
+        let flat = DestinationTemplate::parse("flat").unwrap();
+        assert_eq!(
+            flat.get_sub_component(None, repository),
+            "openshift4-sub-ose-kube-rbac-proxy"
+        );
+        let custom = DestinationTemplate::parse("{dest}/mirror/{namespace}-{name}").unwrap();
+        assert_eq!(custom.get_sub_component(None, "ns/name"), "mirror/ns-name");
+    }
+
+    #[test]
+    fn sub_components_fail() {
+        let name = DestinationTemplate::parse("name").unwrap();
+        let mut sub_components = SubComponents::default();
+        for repository in ["a/foo", "a/bar", "a/foo"] {
+            let sub_component = name.get_sub_component(None, repository);
+            assert!(sub_components.add(repository, &sub_component).is_ok());
+        }
+        let res = sub_components.add("b/foo", &name.get_sub_component(None, "b/foo"));
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+
+        // the nested layout keeps them apart
+        let nested = DestinationTemplate::default();
+        let mut sub_components = SubComponents::default();
+        for repository in ["a/foo", "b/foo"] {
+            let sub_component = nested.get_sub_component(None, repository);
+            assert!(sub_components.add(repository, &sub_component).is_ok());
+        }
+    }
+
+    #[test]
+    fn parse_destination_template_fail() {
+        for template in [
+            "",
+            "nested-layout",
+            "{namespace}/{name}",
+            "registry/{dest}/{name}",
+            "{dest}/{namespace}",
+            "{dest}/{tag}/{name}",
+            "{dest}/{name",
+            "{dest}/{dest}/{name}",
+        ] {
+            let res = DestinationTemplate::parse(template);
+            assert!(res.is_err(), "{} should not parse", template);
+            assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+        }
+    }
+}
@@ -3,6 +3,9 @@ use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 
+use crate::config::destination::DestinationTemplate;
+use crate::error::handler::MirrorError;
+
 /// config schema
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ImageSetConfig {
@@ -12,8 +15,48 @@ pub struct ImageSetConfig {
     #[serde(rename = "apiVersion")]
     pub api_version: String,
 
-    #[serde(rename = "mirror")]
+    // a DeleteImageSetConfiguration lists the images to delete with the same schema
+    #[serde(rename = "mirror", alias = "delete")]
     pub mirror: Mirror,
+
+    // request scheduling per registry, registries not listed use the command line settings
+    #[serde(rename = "registries", default)]
+    pub registries: Vec<RegistrySettings>,
+
+    // source registry rewrites and mirror fallbacks (registries.conf [[registry]] entries)
+    #[serde(rename = "sourceRegistries", default)]
+    pub source_registries: Vec<SourceRegistry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SourceRegistry {
+    // registry or repository prefix of the references i.e. quay.io/openshift-release-dev
+    #[serde(rename = "prefix")]
+    pub prefix: String,
+
+    // replaces the prefix, the prefix itself is used when not set
+    #[serde(rename = "location")]
+    pub location: Option<String>,
+
+    // tried in order before the location
+    #[serde(rename = "mirrors", default)]
+    pub mirrors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegistrySettings {
+    #[serde(rename = "name")]
+    pub name: String,
+
+    #[serde(rename = "maxConcurrency")]
+    pub max_concurrency: Option<usize>,
+
+    // requests per second
+    #[serde(rename = "rateLimit")]
+    pub rate_limit: Option<f64>,
+
+    #[serde(rename = "maxRetries")]
+    pub max_retries: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -26,6 +69,43 @@ pub struct Mirror {
 
     #[serde(rename = "additionalImages")]
     pub additional_images: Option<Vec<Image>>,
+
+    #[serde(rename = "platform")]
+    pub platform: Option<Platform>,
+
+    // layout of the repositories below the destination, nested (default), name, flat
+    // or a template i.e. {dest}/{namespace}/{name}
+    #[serde(rename = "destinationTemplate")]
+    pub destination_template: Option<String>,
+}
+
+// architectures to mirror for manifest lists and multi-arch release payloads
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Platform {
+    #[serde(rename = "architectures", default)]
+    pub architectures: Vec<String>,
+}
+
+impl Mirror {
+    // an empty list mirrors every architecture
+    pub fn get_architectures(&self) -> Vec<String> {
+        self.platform
+            .as_ref()
+            .map(|p| p.architectures.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn get_destination_template(&self) -> Result<DestinationTemplate, MirrorError> {
+        match self.destination_template.as_deref() {
+            Some(template) => DestinationTemplate::parse(template),
+            None => Ok(DestinationTemplate::default()),
+        }
+    }
+}
+
+// check if the architecture of a manifest list entry should be mirrored
+pub fn is_architecture_mirrored(architectures: &[String], arch: &str) -> bool {
+    architectures.is_empty() || architectures.iter().any(|a| a == arch)
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -41,6 +121,10 @@ pub struct Operator {
 
     #[serde(rename = "packages")]
     pub packages: Option<Vec<Package>>,
+
+    // pushed below destination/prefix, for registries that restrict the nesting depth
+    #[serde(rename = "destinationPrefix")]
+    pub destination_prefix: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -48,8 +132,36 @@ pub struct Package {
     #[serde(rename = "name")]
     pub name: String,
 
-    #[serde(rename = "bundles")]
+    // explicit bundles, when empty the bundles are resolved from the channels
+    #[serde(rename = "bundles", default)]
     pub bundles: Vec<Bundle>,
+
+    // channels to mirror, the default channel is used if not set
+    #[serde(rename = "channels")]
+    pub channels: Option<Vec<Channel>>,
+
+    #[serde(rename = "minVersion")]
+    pub min_version: Option<String>,
+
+    #[serde(rename = "maxVersion")]
+    pub max_version: Option<String>,
+
+    // only the bundles on the shortest upgrade path from minVersion are mirrored
+    #[serde(rename = "shortestPath", default)]
+    pub shortest_path: bool,
+}
+
+// minVersion and maxVersion override the package settings for this channel
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Channel {
+    #[serde(rename = "name")]
+    pub name: String,
+
+    #[serde(rename = "minVersion")]
+    pub min_version: Option<String>,
+
+    #[serde(rename = "maxVersion")]
+    pub max_version: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -58,24 +170,46 @@ pub struct Bundle {
     pub name: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Release {
-    #[serde(rename = "version")]
+    #[serde(rename = "version", default)]
     pub version: String,
 
     #[serde(rename = "image")]
     pub image: String,
+
+    // with a channel every release on the upgrade path is mirrored
+    #[serde(rename = "channel")]
+    pub channel: Option<String>,
+
+    #[serde(rename = "minVersion")]
+    pub min_version: Option<String>,
+
+    #[serde(rename = "maxVersion")]
+    pub max_version: Option<String>,
+
+    #[serde(rename = "shortestPath", default)]
+    pub shortest_path: bool,
+
+    // local cincinnati graph json file or http(s) endpoint
+    #[serde(rename = "graph")]
+    pub graph: Option<String>,
 }
 
 // read the 'image set config' file
-pub fn load_config(dir: String) -> Result<String, Box<dyn std::error::Error>> {
+pub fn load_config(dir: String) -> Result<String, MirrorError> {
     // Create a path to the desired file
     let path = Path::new(&dir);
     let display = path.display();
 
     // Open the path in read-only mode, returns `io::Result<File>`
     let mut file = match File::open(&path) {
-        Err(why) => panic!("couldn't open {}: {}", display, why),
+        Err(why) => {
+            return Err(MirrorError::Config(format!(
+                "couldn't open {}: {}",
+                display, why
+            )))
+        }
         Ok(file) => file,
     };
 
@@ -86,10 +220,10 @@ pub fn load_config(dir: String) -> Result<String, Box<dyn std::error::Error>> {
 }
 
 // parse the 'image set config' file
-pub fn parse_yaml_config(data: String) -> Result<ImageSetConfig, serde_yaml::Error> {
+pub fn parse_yaml_config(data: String) -> Result<ImageSetConfig, MirrorError> {
     // Parse the string of data into serde_json::ImageSetConfig.
-    let res = serde_yaml::from_str::<ImageSetConfig>(&data);
-    res
+    let res = serde_yaml::from_str::<ImageSetConfig>(&data)?;
+    Ok(res)
 }
 
 #[cfg(test)]
@@ -104,10 +238,10 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn test_load_config_fail() {
         let res = load_config(String::from("./nada.yaml"));
         assert!(res.is_err());
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
     }
 
     // finally test that the parser is working correctly
@@ -117,4 +251,104 @@ mod tests {
         let res = parse_yaml_config(data.unwrap().to_string());
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn test_isc_parser_architectures() {
+        let data = String::from(
+            "kind: ImageSetConfiguration
+apiVersion: mirror.openshift/v3alpha1
+mirror:
+  platform:
+    architectures:
+    - amd64
+    - arm64
+  additionalImages:
+  - name: quay.io/ns/name:v1
+",
+        );
+        let res = parse_yaml_config(data).unwrap();
+        let archs = res.mirror.get_architectures();
+        assert_eq!(archs, vec!["amd64", "arm64"]);
+        assert!(is_architecture_mirrored(&archs, "arm64"));
+        assert!(!is_architecture_mirrored(&archs, "s390x"));
+        // without a platform every architecture is mirrored
+        assert!(is_architecture_mirrored(&[], "s390x"));
+    }
+
+    #[test]
+    fn test_isc_parser_delete() {
+        let data = String::from(
+            "kind: DeleteImageSetConfiguration
+apiVersion: mirror.openshift/v3alpha1
+delete:
+  additionalImages:
+  - name: quay.io/ns/name:v1
+",
+        );
+        let res = parse_yaml_config(data).unwrap();
+        assert_eq!(res.kind, "DeleteImageSetConfiguration");
+        assert_eq!(
+            res.mirror.additional_images.unwrap()[0].name,
+            "quay.io/ns/name:v1"
+        );
+    }
+
+    #[test]
+    fn test_isc_parser_source_registries() {
+        let data = String::from(
+            "kind: ImageSetConfiguration
+apiVersion: mirror.openshift/v3alpha1
+mirror:
+  additionalImages:
+  - name: quay.io/ns/name:v1
+sourceRegistries:
+- prefix: quay.io
+  location: proxy.internal/quay
+  mirrors:
+  - cache.internal/quay
+",
+        );
+        let res = parse_yaml_config(data).unwrap();
+        assert_eq!(
+            res.source_registries,
+            vec![SourceRegistry {
+                prefix: String::from("quay.io"),
+                location: Some(String::from("proxy.internal/quay")),
+                mirrors: vec![String::from("cache.internal/quay")],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_isc_parser_destination() {
+        let data = String::from(
+            "kind: ImageSetConfiguration
+apiVersion: mirror.openshift/v3alpha1
+mirror:
+  destinationTemplate: flat
+  operators:
+  - catalog: registry.redhat.io/redhat/redhat-operator-index:v4.15
+    destinationPrefix: redhat
+    packages:
+    - name: albo
+",
+        );
+        let res = parse_yaml_config(data).unwrap();
+        let template = res.mirror.get_destination_template().unwrap();
+        let op = &res.mirror.operators.unwrap()[0];
+        assert_eq!(
+            template.get_sub_component(op.destination_prefix.as_deref(), "albo/controller-rhel8"),
+            "redhat/albo-controller-rhel8"
+        );
+
+        let mirror = Mirror {
+            release: None,
+            operators: None,
+            additional_images: None,
+            platform: None,
+            destination_template: Some(String::from("{namespace}/{name}")),
+        };
+        let res = mirror.get_destination_template();
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+    }
 }
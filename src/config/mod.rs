@@ -1 +1,3 @@
+pub mod destination;
 pub mod load;
+pub mod registries;
@@ -0,0 +1,234 @@
+use serde_derive::Deserialize;
+use std::fs;
+
+use crate::config::load::SourceRegistry;
+use crate::error::handler::MirrorError;
+
+// resolves the registries to pull a reference from, the references in the config,
+// the working-dir layout and the mapping are never rewritten
+#[derive(Debug, Clone, Default)]
+pub struct SourceMirrors {
+    // longest prefix first
+    registries: Vec<SourceRegistry>,
+}
+
+impl SourceMirrors {
+    pub fn new(registries: Vec<SourceRegistry>) -> SourceMirrors {
+        let mut registries = registries;
+        registries.sort_by(|a, b| b.prefix.len().cmp(&a.prefix.len()));
+        SourceMirrors { registries }
+    }
+
+    // the repositories to pull a repository (registry/namespace/name) from in order,
+    // the mirrors followed by the location of the longest matching prefix
+    pub fn get_source_repos(&self, repo: &str) -> Vec<String> {
+        let reg = match self
+            .registries
+            .iter()
+            .find(|r| matches_prefix(repo, &r.prefix))
+        {
+            Some(reg) => reg,
+            None => return vec![repo.to_string()],
+        };
+        let location = reg.location.as_deref().unwrap_or(&reg.prefix);
+        reg.mirrors
+            .iter()
+            .map(|m| m.as_str())
+            .chain([location])
+            .map(|to| to.to_string() + &repo[reg.prefix.len()..])
+            .collect()
+    }
+
+    // the urls to request in order for a manifest or blobs url built from the original
+    // reference i.e. https://quay.io/v2/ns/name/manifests/v1
+    pub fn get_source_urls(&self, url: &str) -> Vec<String> {
+        let (scheme, rest) = url.split_once("://").unwrap_or(("https", url));
+        let (registry, path) = match rest.split_once("/v2/") {
+            Some(val) => val,
+            None => return vec![url.to_string()],
+        };
+        let split = ["/manifests/", "/blobs/"]
+            .iter()
+            .find_map(|sep| path.find(sep))
+            .unwrap_or(path.len());
+        let (repo, suffix) = path.split_at(split);
+        self.get_source_repos(&(registry.to_string() + "/" + repo))
+            .iter()
+            .map(|source| {
+                let (registry, repo) = source.split_once("/").unwrap_or((source, ""));
+                format!("{}://{}/v2/{}{}", scheme, registry, repo, suffix)
+            })
+            .collect()
+    }
+}
+
+// a prefix matches the whole registry or repository path i.e. quay.io/ns matches
+// quay.io/ns/name but not quay.io/nsx/name
+fn matches_prefix(repo: &str, prefix: &str) -> bool {
+    !prefix.is_empty()
+        && repo.starts_with(prefix)
+        && (repo.len() == prefix.len() || repo[prefix.len()..].starts_with("/"))
+}
+
+// read the [[registry]] and [[registry.mirror]] entries of a containers registries.conf
+pub fn load_registries_conf(file: &str) -> Result<Vec<SourceRegistry>, MirrorError> {
+    let data = fs::read_to_string(file)
+        .map_err(|err| MirrorError::Config(format!("registries.conf {} {}", file, err)))?;
+    parse_registries_conf(&data)
+}
+
+// the [[registry]] tables of registries.conf, only the prefix, location and mirror
+// locations are used, other keys and tables (unqualified-search-registries, insecure,
+// blocked ...) are ignored
+#[derive(Debug, Default, Deserialize)]
+struct RegistriesConf {
+    #[serde(default)]
+    registry: Vec<RegistryTable>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RegistryTable {
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    location: String,
+    #[serde(default)]
+    mirror: Vec<MirrorTable>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MirrorTable {
+    #[serde(default)]
+    location: String,
+}
+
+pub fn parse_registries_conf(data: &str) -> Result<Vec<SourceRegistry>, MirrorError> {
+    let conf: RegistriesConf = toml::from_str(data)
+        .map_err(|err| MirrorError::Config(format!("registries.conf {}", err)))?;
+    Ok(conf
+        .registry
+        .into_iter()
+        .map(|reg| SourceRegistry {
+            // without a prefix the entry matches its location
+            prefix: match reg.prefix.is_empty() {
+                true => reg.location.clone(),
+                false => reg.prefix,
+            },
+            location: Some(reg.location).filter(|l| !l.is_empty()),
+            mirrors: reg
+                .mirror
+                .into_iter()
+                .map(|m| m.location)
+                .filter(|m| !m.is_empty())
+                .collect(),
+        })
+        .filter(|r| !r.prefix.is_empty())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+
+    #[test]
+    fn parse_registries_conf_pass() {
+        let data = r#"
+unqualified-search-registries = [
+  "registry.redhat.io",
+]
+
+[[registry]]
+prefix = "quay.io/openshift-release-dev"
+location = "proxy.internal/ocp" # release payloads
+
+[[registry.mirror]]
+location = "cache.internal/ocp"
+
+[[registry]]
+location = "registry.redhat.io"
+insecure = false
+
+[[registry.mirror]]
+location = "proxy.internal/redhat"
+"#;
+        let res = parse_registries_conf(data).unwrap();
+        assert_eq!(
+            res,
+            vec![
+                SourceRegistry {
+                    prefix: String::from("quay.io/openshift-release-dev"),
+                    location: Some(String::from("proxy.internal/ocp")),
+                    mirrors: vec![String::from("cache.internal/ocp")],
+                },
+                SourceRegistry {
+                    prefix: String::from("registry.redhat.io"),
+                    location: Some(String::from("registry.redhat.io")),
+                    mirrors: vec![String::from("proxy.internal/redhat")],
+                },
+            ]
+        );
+        assert!(parse_registries_conf("[[registry.mirror]]\nlocation = \"x\"").is_err());
+
+        // inline mirror tables, literal strings and multi-line values
+        let data = r#"
+[[registry]]
+prefix = 'quay.io'
+location = """
+proxy.internal/quay"""
+mirror = [
+  { location = "cache.internal/quay", insecure = true },
+  { location = 'cache2.internal/quay' },
+]
+"#;
+        let res = parse_registries_conf(data).unwrap();
+        assert_eq!(
+            res,
+            vec![SourceRegistry {
+                prefix: String::from("quay.io"),
+                location: Some(String::from("proxy.internal/quay")),
+                mirrors: vec![
+                    String::from("cache.internal/quay"),
+                    String::from("cache2.internal/quay")
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn get_source_urls_pass() {
+        let sources = SourceMirrors::new(vec![
+            SourceRegistry {
+                prefix: String::from("quay.io"),
+                location: Some(String::from("proxy.internal/quay")),
+                mirrors: vec![],
+            },
+            SourceRegistry {
+                prefix: String::from("quay.io/openshift-release-dev"),
+                location: None,
+                mirrors: vec![String::from("cache.internal/ocp")],
+            },
+        ]);
+        assert_eq!(
+            sources.get_source_urls("https://quay.io/v2/ns/name/manifests/v1"),
+            vec!["https://proxy.internal/v2/quay/ns/name/manifests/v1"]
+        );
+        // the longest prefix wins, the mirror is tried before the location
+        assert_eq!(
+            sources.get_source_urls("https://quay.io/v2/openshift-release-dev/ocp-release/blobs/"),
+            vec![
+                "https://cache.internal/v2/ocp/ocp-release/blobs/",
+                "https://quay.io/v2/openshift-release-dev/ocp-release/blobs/"
+            ]
+        );
+        // prefixes match whole path components
+        assert_eq!(
+            sources.get_source_urls("https://quay.io.example.com/v2/ns/name/manifests/v1"),
+            vec!["https://quay.io.example.com/v2/ns/name/manifests/v1"]
+        );
+        assert_eq!(
+            sources.get_source_repos("registry.redhat.io/redhat/redhat-operator-index"),
+            vec!["registry.redhat.io/redhat/redhat-operator-index"]
+        );
+    }
+}
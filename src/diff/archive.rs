@@ -0,0 +1,412 @@
+use custom_logger::*;
+use flate2::read::GzDecoder;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use tempdir::TempDir;
+use walkdir::WalkDir;
+
+use crate::config::load::parse_yaml_config;
+use crate::diff::metadata_cache::{
+    get_dir_manifests, get_volume_name, BlobIndexEntry, VolumeManifest,
+};
+use crate::error::handler::MirrorError;
+use crate::release::cincinnati::RESOLVED_RELEASES_FILE;
+use crate::verify::blobs::{check_blob, get_blob_path, BlobStatus};
+
+// unpack a mirror-diff tar (as created by create_diff_tar) into a staging working-dir
+// returns the imagesetconfig embedded in the archive
+pub fn unpack_diff_tar(
+    log: &Logging,
+    tar_file: String,
+    staging_dir: String,
+) -> Result<String, MirrorError> {
+    if !Path::new(&tar_file).exists() {
+        return Err(MirrorError::Config(format!(
+            "archive {} not found",
+            tar_file
+        )));
+    }
+    let tmp_dir = TempDir::new("tmp-diff-ingest")?;
+    // volumes hold disjoint files so they can be unpacked in any order
+    for file in get_archive_files(&tar_file)? {
+        log.debug(&format!("unpacking {}", file));
+        if file.ends_with(".gz") {
+            tar::Archive::new(GzDecoder::new(File::open(&file)?)).unpack(tmp_dir.path())?;
+        } else {
+            tar::Archive::new(File::open(&file)?).unpack(tmp_dir.path())?;
+        }
+    }
+    check_volumes(tmp_dir.path())?;
+
+    let isc_file = tmp_dir.path().join("metadata/isc.yaml");
+    if !isc_file.exists() {
+        return Err(MirrorError::ManifestParse(format!(
+            "archive {} has no metadata/isc.yaml",
+            tar_file
+        )));
+    }
+    let config = fs::read_to_string(isc_file)?;
+
+    let blobs_dir = staging_dir.clone() + "blobs-store/";
+    for e in WalkDir::new(tmp_dir.path())
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if !e.path().is_file() {
+            continue;
+        }
+        let rel = e
+            .path()
+            .strip_prefix(tmp_dir.path())
+            .map_err(|err| MirrorError::Io(err.to_string()))?;
+        let mut parts = rel.components();
+        let first = match parts.next() {
+            Some(val) => val.as_os_str().to_string_lossy().to_string(),
+            None => continue,
+        };
+        let to = match first.as_str() {
+            // the resolved releases go to the root of the working-dir
+            "metadata" if parts.as_path() == Path::new(RESOLVED_RELEASES_FILE) => {
+                Path::new(&staging_dir).join(RESOLVED_RELEASES_FILE)
+            }
+            "metadata" => continue,
+            "blobs" => Path::new(&blobs_dir).join(parts.as_path()),
+            // component directories are stored relative to the working-dir
+            _ => Path::new(&staging_dir).join(rel),
+        };
+        log.trace(&format!("unpack {} to {}", rel.display(), to.display()));
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(e.path(), &to)?;
+    }
+
+    // every blob in the index must be in the archive and match its digest
+    let index_file = tmp_dir.path().join("metadata/blobs-index.json");
+    if index_file.exists() {
+        let index: Vec<BlobIndexEntry> = serde_json::from_str(&fs::read_to_string(index_file)?)?;
+        for entry in index.iter() {
+            match check_blob(&blobs_dir, &entry.digest, Some(entry.size as i64))? {
+                BlobStatus::Valid => {}
+                status => {
+                    return Err(MirrorError::ManifestParse(format!(
+                        "blob {} in archive {} is {:?}",
+                        entry.digest, tar_file, status
+                    )))
+                }
+            }
+        }
+        log.info(&format!("archive contains {} valid blobs", index.len()));
+    }
+    tmp_dir.close()?;
+    Ok(config)
+}
+
+// a directory holds the numbered volumes of a split archive
+fn get_archive_files(tar_file: &str) -> Result<Vec<String>, MirrorError> {
+    if !Path::new(tar_file).is_dir() {
+        return Ok(vec![tar_file.to_string()]);
+    }
+    let mut files = vec![];
+    for entry in fs::read_dir(tar_file)? {
+        let name = entry?.path().display().to_string();
+        let file_name = name.split("/").last().unwrap_or_default();
+        if file_name.starts_with("mirror_") && file_name.ends_with(".tar") {
+            files.push(name);
+        }
+    }
+    if files.is_empty() {
+        return Err(MirrorError::Config(format!(
+            "no mirror_*.tar volumes found in {}",
+            tar_file
+        )));
+    }
+    Ok(files)
+}
+
+// every volume of a split archive must be present
+fn check_volumes(dir: &Path) -> Result<(), MirrorError> {
+    if !dir.join("metadata").exists() {
+        return Ok(());
+    }
+    let mut found = vec![];
+    let mut total = 0;
+    for entry in fs::read_dir(dir.join("metadata"))? {
+        let path = entry?.path();
+        let file_name = path.display().to_string();
+        if !file_name.contains("/volume-") {
+            continue;
+        }
+        let manifest: VolumeManifest = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        total = manifest.total;
+        found.push(manifest.volume);
+    }
+    found.sort();
+    if found.len() != total {
+        let missing: Vec<String> = (1..=total)
+            .filter(|v| !found.contains(v))
+            .map(get_volume_name)
+            .collect();
+        return Err(MirrorError::ManifestParse(format!(
+            "archive is incomplete, missing volumes {:?}",
+            missing
+        )));
+    }
+    Ok(())
+}
+
+// the archive must have been created with the same imagesetconfig
+pub fn check_archive_config(archive_config: String, config: String) -> Result<(), MirrorError> {
+    let archive_isc = parse_yaml_config(archive_config)?;
+    let isc = parse_yaml_config(config)?;
+    if serde_yaml::to_string(&archive_isc)? != serde_yaml::to_string(&isc)? {
+        return Err(MirrorError::Config(String::from(
+            "imagesetconfig does not match metadata/isc.yaml in the archive",
+        )));
+    }
+    Ok(())
+}
+
+// merge the staging working-dir into the local working-dir
+// blobs already present are kept, manifests are replaced
+pub fn merge_working_dir(
+    log: &Logging,
+    staging_dir: String,
+    working_dir: String,
+) -> Result<usize, MirrorError> {
+    let mut count = 0;
+    for e in WalkDir::new(&staging_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if !e.path().is_file() {
+            continue;
+        }
+        let rel = e
+            .path()
+            .strip_prefix(&staging_dir)
+            .map_err(|err| MirrorError::Io(err.to_string()))?;
+        let to = Path::new(&working_dir).join(rel);
+        if rel.starts_with("blobs-store") && to.exists() {
+            continue;
+        }
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(e.path(), &to)?;
+        count += 1;
+    }
+    log.debug(&format!("merged {} files into {}", count, working_dir));
+    Ok(count)
+}
+
+// an archive only carries blobs that were not shipped before, copy the blobs the
+// staged manifests need from the working-dir (merged from previous archives)
+pub fn fill_staging_blobs(
+    log: &Logging,
+    staging_dir: String,
+    working_dir: String,
+) -> Result<usize, MirrorError> {
+    let mut count = 0;
+    for e in WalkDir::new(&staging_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = e.path().display().to_string();
+        if !e.path().is_dir() || path.contains("blobs-store") {
+            continue;
+        }
+        for (file, manifest) in get_dir_manifests(&path)? {
+            let mut layers = manifest.layers.unwrap_or_default();
+            if let Some(config) = manifest.config {
+                layers.push(config);
+            }
+            for layer in layers.iter() {
+                let to = get_blob_path(&(staging_dir.clone() + "blobs-store/"), &layer.digest);
+                if Path::new(&to).exists() {
+                    continue;
+                }
+                let from = get_blob_path(&(working_dir.clone() + "blobs-store/"), &layer.digest);
+                if !Path::new(&from).exists() {
+                    return Err(MirrorError::ManifestParse(format!(
+                        "blob {} referenced by {} is not in the archive or {}",
+                        layer.digest, file, working_dir
+                    )));
+                }
+                if let Some(parent) = Path::new(&to).parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(&from, &to)?;
+                count += 1;
+            }
+        }
+    }
+    log.debug(&format!("copied {} previously shipped blobs", count));
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use crate::diff::metadata_cache::create_diff_tar;
+    use crate::storage::local::LocalStorage;
+    use sha2::{Digest, Sha256};
+    use std::collections::HashSet;
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    // the blobs in test-artifacts are placeholders, so build a blob with a valid digest
+    // and a manifest that references it, returns the hex digest of the blob
+    fn create_test_source(src_dir: &str, data: &str) -> String {
+        let hash = hex::encode(Sha256::digest(data.as_bytes()));
+        let mnfst_dir = src_dir.to_string() + "albo/stable-v1";
+        fs::create_dir_all(&mnfst_dir).expect("should create test dir");
+        fs::create_dir_all(src_dir.to_string() + "blobs-store/" + &hash[..2])
+            .expect("should create test blobs dir");
+        fs::write(
+            src_dir.to_string() + "blobs-store/" + &hash[..2] + "/" + &hash,
+            data,
+        )
+        .expect("should write test blob");
+        let mut manifest = fs::read_to_string(
+            "test-artifacts/test-index-operator/v1.0/operators/albo/aws-load-balancer-controller-rhel8/stable-v1/manifest-amd64.json",
+        )
+        .expect("should read test manifest");
+        for digest in [
+            "ab23d850616c11ac4041387983b3dd271728cdb601faef00e8c500b17e306077",
+            "28ff5ee6facbc15dc879cb26daf949072ec01118d3463efd1f991d9b92e175ef",
+            "1b594048db9380f9a8dd2e45e16a2e12d39df51f6f61d9be4c9a2986cbc2828b",
+        ] {
+            manifest = manifest.replace(digest, &hash);
+        }
+        fs::write(mnfst_dir.clone() + "/manifest.json", manifest).expect("should write manifest");
+        hash
+    }
+
+    #[test]
+    fn unpack_and_merge_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let tar_file = String::from("test-artifacts/ingest-test.tar.gz");
+        let src_dir = String::from("test-artifacts/ingest-src/");
+        let staging_dir = String::from("./test-artifacts/ingest-staging/");
+        let working_dir = String::from("./test-artifacts/ingest-working-dir/");
+
+        let hash = create_test_source(&src_dir, "ingest test blob");
+        let mnfst_dir = src_dir.clone() + "albo/stable-v1";
+        fs::write(src_dir.clone() + RESOLVED_RELEASES_FILE, "[]")
+            .expect("should write resolved releases");
+
+        aw!(create_diff_tar(
+            log,
+            tar_file.clone(),
+            &LocalStorage::new(&src_dir),
+            vec![&mnfst_dir],
+            String::from("imagesetconfig"),
+            0,
+            &HashSet::new(),
+        ))
+        .unwrap();
+
+        let res = unpack_diff_tar(log, tar_file.clone(), staging_dir.clone());
+        assert_eq!(res.unwrap(), "imagesetconfig");
+        let manifest = String::from("albo/stable-v1/manifest.json");
+        let blob = String::from("blobs-store/") + &hash[..2] + "/" + &hash;
+        assert!(Path::new(&(staging_dir.clone() + &manifest)).exists());
+        assert!(Path::new(&(staging_dir.clone() + &blob)).exists());
+
+        let res = merge_working_dir(log, staging_dir.clone(), working_dir.clone());
+        assert!(res.unwrap() > 0);
+        assert!(Path::new(&(working_dir.clone() + &manifest)).exists());
+        assert!(Path::new(&(working_dir.clone() + &blob)).exists());
+        assert!(Path::new(&(working_dir.clone() + RESOLVED_RELEASES_FILE)).exists());
+
+        fs::remove_file(&tar_file).expect("should delete tar");
+        rm_rf::remove(&src_dir).expect("should delete src dir");
+        rm_rf::remove(&staging_dir).expect("should delete staging dir");
+        rm_rf::remove(&working_dir).expect("should delete working dir");
+    }
+
+    #[test]
+    fn unpack_volumes_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let src_dir = String::from("test-artifacts/volumes-src/");
+        let out_dir = String::from("test-artifacts/volumes-out/");
+        let staging_dir = String::from("./test-artifacts/volumes-staging/");
+        let hash = create_test_source(&src_dir, &"a".repeat(32 * 1024));
+        let mnfst_dir = src_dir.clone() + "albo/stable-v1";
+        fs::create_dir_all(&out_dir).expect("should create out dir");
+        let storage = LocalStorage::new(&src_dir);
+
+        // the blob fits in a volume of its own but not next to the metadata
+        let archive_size = 36 * 1024;
+        let (files, blobs) = aw!(create_diff_tar(
+            log,
+            out_dir.clone() + "mirror-diff.tar.gz",
+            &storage,
+            vec![&mnfst_dir],
+            String::from("imagesetconfig"),
+            archive_size,
+            &HashSet::new(),
+        ))
+        .unwrap();
+        assert_eq!(
+            files,
+            vec![
+                out_dir.clone() + &get_volume_name(1),
+                out_dir.clone() + &get_volume_name(2)
+            ]
+        );
+        assert_eq!(blobs.len(), 1);
+        for file in files.iter() {
+            assert!(fs::metadata(file).unwrap().len() <= archive_size);
+        }
+
+        // neither the metadata nor the blob fit in a smaller volume
+        for archive_size in [4 * 1024, 16 * 1024] {
+            let res = aw!(create_diff_tar(
+                log,
+                out_dir.clone() + "mirror-diff.tar.gz",
+                &storage,
+                vec![&mnfst_dir],
+                String::from("imagesetconfig"),
+                archive_size,
+                &HashSet::new(),
+            ));
+            assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+        }
+
+        let res = unpack_diff_tar(log, out_dir.clone(), staging_dir.clone());
+        assert_eq!(res.unwrap(), "imagesetconfig");
+        let blob = String::from("blobs-store/") + &hash[..2] + "/" + &hash;
+        assert!(Path::new(&(staging_dir.clone() + &blob)).exists());
+
+        // a missing volume is reported
+        fs::remove_file(out_dir.clone() + &get_volume_name(1)).expect("should delete volume");
+        let res = unpack_diff_tar(log, out_dir.clone(), staging_dir.clone());
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::DATAERR);
+
+        rm_rf::remove(&src_dir).expect("should delete src dir");
+        rm_rf::remove(&out_dir).expect("should delete out dir");
+        rm_rf::remove(&staging_dir).expect("should delete staging dir");
+    }
+
+    #[test]
+    fn check_archive_config_fail() {
+        let config = fs::read_to_string("imagesetconfig.yaml").expect("should read config");
+        let res = check_archive_config(config.clone(), config.clone());
+        assert!(res.is_ok());
+        let other = config.replace("4.15.8", "4.15.9");
+        let res = check_archive_config(config, other);
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+    }
+}
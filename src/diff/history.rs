@@ -0,0 +1,201 @@
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use custom_logger::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::diff::metadata_cache::get_metadata_dirs_incremental;
+use crate::error::handler::MirrorError;
+use crate::verify::blobs::compute_sha256;
+
+const HISTORY_DATE_FORMAT: &str = "%Y/%m/%d %H:%M:%S";
+
+// every archive created and the manifest and blob digests it shipped
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct History {
+    #[serde(rename = "archives")]
+    pub archives: Vec<HistoryEntry>,
+
+    #[serde(skip)]
+    pub file: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    // the tar.gz or the volumes of a split archive
+    #[serde(rename = "files", default)]
+    pub files: Vec<String>,
+
+    #[serde(rename = "created")]
+    pub created: String,
+
+    #[serde(rename = "manifests")]
+    pub manifests: BTreeSet<String>,
+
+    #[serde(rename = "blobs")]
+    pub blobs: BTreeSet<String>,
+}
+
+// component directories and manifest digests that were not shipped yet
+#[derive(Debug, Default, Clone)]
+pub struct DiffDelta {
+    pub dirs: Vec<String>,
+    pub manifests: BTreeSet<String>,
+}
+
+impl History {
+    // load the history file in the working-dir (empty if it does not exist)
+    pub fn load(dir: String) -> Result<History, MirrorError> {
+        let file = dir + "history.json";
+        let mut history = History::default();
+        if Path::new(&file).exists() {
+            history = serde_json::from_str(&fs::read_to_string(&file)?)?;
+        }
+        history.file = file;
+        Ok(history)
+    }
+
+    // all digests shipped, with a date only the archives created before that date count
+    pub fn get_shipped(&self, before: Option<NaiveDateTime>) -> HashSet<String> {
+        let mut shipped = HashSet::new();
+        for entry in self.archives.iter() {
+            if let Some(date) = before {
+                match NaiveDateTime::parse_from_str(&entry.created, HISTORY_DATE_FORMAT) {
+                    Ok(created) if created < date => {}
+                    _ => continue,
+                }
+            }
+            shipped.extend(entry.manifests.iter().cloned());
+            shipped.extend(entry.blobs.iter().cloned());
+        }
+        shipped
+    }
+
+    // record a created archive and persist the history
+    pub fn add(
+        &mut self,
+        files: Vec<String>,
+        manifests: BTreeSet<String>,
+        blobs: BTreeSet<String>,
+    ) -> Result<(), MirrorError> {
+        self.archives.push(HistoryEntry {
+            files,
+            created: Utc::now().format(HISTORY_DATE_FORMAT).to_string(),
+            manifests,
+            blobs,
+        });
+        let tmp = self.file.clone() + ".tmp";
+        fs::write(&tmp, serde_json::to_string_pretty(&self)?)?;
+        fs::rename(&tmp, &self.file)?;
+        Ok(())
+    }
+}
+
+// parse the --date flag (yyyy/mm/dd)
+pub fn parse_history_date(date: String) -> Result<NaiveDateTime, MirrorError> {
+    let day = NaiveDate::parse_from_str(&date, "%Y/%m/%d").map_err(|_| {
+        MirrorError::Config(format!("date {} expected to be in yyyy/mm/dd format", date))
+    })?;
+    Ok(day.and_hms_opt(0, 0, 0).unwrap_or_default())
+}
+
+// compare the digest of every manifest in working-dir against the shipped digests
+// a component directory is included as soon as one of its manifests changed
+pub fn get_diff_delta(
+    log: &Logging,
+    dir: String,
+    shipped: &HashSet<String>,
+) -> Result<DiffDelta, MirrorError> {
+    let mut delta = DiffDelta::default();
+    let mut dirs: Vec<String> = get_metadata_dirs_incremental(log, dir)
+        .into_iter()
+        .collect();
+    dirs.sort();
+    for d in dirs {
+        let mut changed = false;
+        for entry in fs::read_dir(&d)? {
+            let path = entry?.path();
+            let file_name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            if !path.is_file()
+                || !file_name.starts_with("manifest")
+                || !file_name.ends_with(".json")
+            {
+                continue;
+            }
+            let (hash, _) = compute_sha256(&path.display().to_string())?;
+            let digest = String::from("sha256:") + &hash;
+            if !shipped.contains(&digest) {
+                log.debug(&format!("manifest {} changed ({})", path.display(), digest));
+                delta.manifests.insert(digest);
+                changed = true;
+            }
+        }
+        if changed {
+            delta.dirs.push(d);
+        }
+    }
+    Ok(delta)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+
+    #[test]
+    fn get_diff_delta_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let dir = String::from(
+            "test-artifacts/test-index-operator/v1.0/operators/albo/aws-load-balancer-controller-rhel8/stable-v1",
+        );
+        let delta = get_diff_delta(log, dir.clone(), &HashSet::new()).unwrap();
+        assert_eq!(delta.dirs, vec![dir.clone()]);
+        assert_eq!(delta.manifests.len(), 2);
+
+        // nothing left to ship once the manifests are in the history
+        let shipped: HashSet<String> = delta.manifests.into_iter().collect();
+        let delta = get_diff_delta(log, dir, &shipped).unwrap();
+        assert!(delta.dirs.is_empty());
+    }
+
+    #[test]
+    fn history_pass() {
+        let dir = String::from("./test-artifacts/history-test/");
+        fs::create_dir_all(&dir).expect("should create test dir");
+        let mut history = History::load(dir.clone()).unwrap();
+        history
+            .add(
+                vec![
+                    String::from("mirror_000001.tar"),
+                    String::from("mirror_000002.tar"),
+                ],
+                BTreeSet::from([String::from("sha256:1234")]),
+                BTreeSet::from([String::from("sha256:abcd")]),
+            )
+            .unwrap();
+
+        let history = History::load(dir.clone()).unwrap();
+        assert_eq!(history.archives[0].files.len(), 2);
+        let shipped = history.get_shipped(None);
+        assert!(shipped.contains("sha256:1234"));
+        assert!(shipped.contains("sha256:abcd"));
+
+        // archives created on or after the date are shipped again
+        let date = parse_history_date(String::from("2023/08/01")).unwrap();
+        assert_eq!(history.get_shipped(Some(date)).len(), 0);
+        rm_rf::remove(&dir).expect("should delete test dir");
+    }
+
+    #[test]
+    fn parse_history_date_fail() {
+        let res = parse_history_date(String::from("/08/01"));
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+    }
+}
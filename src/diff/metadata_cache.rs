@@ -1,55 +1,34 @@
-use chrono::NaiveDateTime;
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use std::collections::HashSet;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fs;
 use std::fs::File;
-use std::path::Path;
-use std::time::SystemTime;
+use std::path::{Path, PathBuf};
 use tempdir::TempDir;
 use walkdir::WalkDir;
 
 use custom_logger::*;
-use mirror_catalog_index::*;
+use mirror_copy::Manifest;
 
-pub fn get_metadata_dirs_by_date(log: &Logging, dir: String, date: String) -> HashSet<String> {
-    let mut valid_dirs = HashSet::new();
-    let new_date = date + &String::from(" 00:00:00");
-    log.info(&format!("date {:#?}", new_date));
-    let date_only = NaiveDateTime::parse_from_str(&new_date, "%Y/%m/%d %H:%M:%S");
-    match date_only {
-        Ok(val) => val,
-        Err(_) => {
-            log.error("date expected to be in yyyy/mm/dd format");
-            //std::process::exit(exitcode::USAGE);
-            panic!("date format is incorrect, unable to continue")
-        }
-    };
+use crate::error::handler::MirrorError;
+use crate::operator::collector::ManifestList;
+use crate::release::cincinnati::RESOLVED_RELEASES_FILE;
+use crate::storage::{get_blob_key, Storage};
 
-    let date_unix = NaiveDateTime::and_utc(&date_only.unwrap());
-    log.info(&format!("date unix {:#?}", date_unix));
-    for e in WalkDir::new(dir.clone().to_string())
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
+pub fn get_metadata_dirs_incremental(log: &Logging, dir: String) -> HashSet<String> {
+    let mut valid_dirs = HashSet::new();
+    for e in WalkDir::new(dir.clone()).into_iter().filter_map(|e| e.ok()) {
         if e.path().is_dir() {
             let dir = e.path().display().to_string();
-            let metadata = fs::metadata(&dir).unwrap();
-            let created = metadata
-                .created()
-                .unwrap()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap();
-            if (dir.contains("operators") || dir.contains("release"))
+            if (dir.contains("operators")
+                || dir.contains("release")
+                || dir.contains("additional")
+                || dir.ends_with("/catalog"))
                 && (Path::new(&(dir.clone() + &"/manifest.json".to_string())).exists()
                     || Path::new(&(dir.clone() + &"/manifest-list.json".to_string())).exists())
-                && created.as_secs() > date_unix.timestamp() as u64
             {
-                log.hi(&format!(
-                    "timestamp {:#?} for dir {:#?} ",
-                    created.as_secs(),
-                    dir
-                ));
+                log.debug(&format!("valid metadata directories {:#?}", dir));
                 valid_dirs.insert(dir);
             }
         }
@@ -57,94 +36,377 @@ pub fn get_metadata_dirs_by_date(log: &Logging, dir: String, date: String) -> Ha
     valid_dirs
 }
 
-pub fn get_metadata_dirs_incremental(log: &Logging, dir: String) -> HashSet<String> {
-    let mut valid_dirs = HashSet::new();
-    for e in WalkDir::new(dir.clone()).into_iter().filter_map(|e| e.ok()) {
-        if e.path().is_dir() {
-            let dir = e.path().display().to_string();
-            if (dir.contains("operators") || dir.contains("release"))
-                && (Path::new(&(dir.clone() + &"/manifest.json".to_string())).exists()
-                    || Path::new(&(dir.clone() + &"/manifest-list.json".to_string())).exists())
-            {
-                log.debug(&format!("valid metadata directories {:#?}", dir));
-                valid_dirs.insert(dir);
+// entry in the blob index written to metadata/blobs-index.json
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlobIndexEntry {
+    #[serde(rename = "digest")]
+    pub digest: String,
+
+    #[serde(rename = "size")]
+    pub size: u64,
+
+    #[serde(rename = "manifests")]
+    pub manifests: Vec<String>,
+}
+
+// manifest written into each volume of a split archive
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VolumeManifest {
+    #[serde(rename = "volume")]
+    pub volume: usize,
+
+    #[serde(rename = "total")]
+    pub total: usize,
+
+    #[serde(rename = "blobs")]
+    pub blobs: Vec<String>,
+}
+
+// get all manifests in a component directory, manifest lists are resolved per mirrored architecture
+pub fn get_dir_manifests(dir: &str) -> Result<Vec<(String, Manifest)>, MirrorError> {
+    let mut files = vec![];
+    let list_file = dir.trim_end_matches("/").to_string() + "/manifest-list.json";
+    if Path::new(&list_file).exists() {
+        let list: ManifestList = serde_json::from_str(&fs::read_to_string(&list_file)?)?;
+        for mf in list.manifests.iter() {
+            let arch = match &mf.platform {
+                Some(platform) => platform.architecture.clone(),
+                None => continue,
+            };
+            // architectures not in platform.architectures are not mirrored
+            let file = dir.trim_end_matches("/").to_string() + "/manifest-" + &arch + ".json";
+            if Path::new(&file).exists() {
+                files.push(file);
             }
         }
+        if files.is_empty() {
+            return Err(MirrorError::ManifestParse(format!(
+                "no architecture manifest referenced in {} found",
+                list_file
+            )));
+        }
+    } else {
+        let file = dir.trim_end_matches("/").to_string() + "/manifest.json";
+        if Path::new(&file).exists() {
+            files.push(file);
+        }
     }
-    valid_dirs
+    let mut manifests = vec![];
+    for file in files {
+        let manifest: Manifest = serde_json::from_str(&fs::read_to_string(&file)?)?;
+        manifests.push((file, manifest));
+    }
+    Ok(manifests)
 }
 
-pub fn create_diff_tar(
+// blobs are read through the workspace storage, returns the archive files created and
+// the blobs they ship
+pub async fn create_diff_tar(
     log: &Logging,
     tar_file: String,
-    _base_dir: String,
+    storage: &dyn Storage,
     dirs: Vec<&std::string::String>,
     config: String,
-) -> Result<bool, Box<dyn std::error::Error>> {
+    archive_size: u64,
+    shipped: &HashSet<String>,
+) -> Result<(Vec<String>, BTreeSet<String>), MirrorError> {
     let tmp_dir = TempDir::new("tmp-diff-tar")?;
     // working-dir/blobs-store
     fs::create_dir_all(tmp_dir.path().join("metadata"))?;
     fs::create_dir_all(tmp_dir.path().join("blobs"))?;
+    // digest -> manifests referencing it, a blob shared by several images is copied once
+    let mut blobs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let root = storage.local_dir();
     for x in dirs {
         // open the manifest file/s (could be more than one - multiarch)
         log.info(&format!("component directory {:#?}", x.to_string()));
-        fs::create_dir_all(tmp_dir.path().join(x.to_string()))?;
+        fs::create_dir_all(tmp_dir.path().join(get_archive_path(&root, x)?))?;
         log.trace(&format!("each dir vector {}", x));
 
-        for entry in fs::read_dir(x.to_string())? {
-            let entry = entry?;
-            let path = entry.path();
-            let from = path.clone().into_os_string().into_string().unwrap();
-            let to = tmp_dir.path().join(from.clone());
-            fs::copy(from.clone(), to).unwrap();
-            // parse the file contents, read it into a Manifest struct
-            let s = fs::read_to_string(from.clone())?;
-            if !from.contains("list") & !from.contains("catalog") {
-                log.trace(&format!("from {}", from));
-                let _mnfst = parse_json_manifest(s.clone()).unwrap();
-                /*
-                for layer in mnfst.iter() {
-                    let digest = layer.digest.split(":").nth(1).unwrap();
-                    log.hi(&format!("layer to copy {:#?}", digest));
-                    let to_dir = String::from("blobs/") + digest;
-                    let from = from_base.clone() + "/" + &digest[..2] + &String::from("/") + digest;
-                    let to = tmp_dir.path().join(&to_dir);
-                    log.trace(&format!("copy from {:#?} to {:#?}", from, to));
-                    fs::copy(from, to).unwrap();
-                }
-                if mnfst.config.is_some() {
-                    let mnfst_config = mnfst.config.unwrap();
-                    let cfg_digest = mnfst_config.digest.split(":").nth(1).unwrap();
-                    log.lo(&format!("config to copy {:#?}", cfg_digest));
-                    let from = from_base.clone()
-                        + "/"
-                        + &cfg_digest[..2]
-                        + &String::from("/")
-                        + cfg_digest;
-                    let to_dir = String::from("blobs/") + cfg_digest;
-                    let to = tmp_dir.path().join(&to_dir);
-                    log.trace(&format!("copy from {:#?} to {:#?}", from, to));
-                    fs::copy(from, to).unwrap();
-                    log.trace("config copied to tmp dir");
-                }
-                */
+        // the filtered catalog ships its declarative configs, diskToMirror reads the
+        // bundles of each package from them
+        let depth = if x.ends_with("/catalog") {
+            usize::MAX
+        } else {
+            1
+        };
+        for entry in WalkDir::new(x).max_depth(depth) {
+            let path = entry
+                .map_err(|err| MirrorError::Io(err.to_string()))?
+                .into_path();
+            if !path.is_file() {
+                continue;
+            }
+            let from = path.display().to_string();
+            let to = tmp_dir.path().join(get_archive_path(&root, &from)?);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(from.clone(), to)?;
+        }
+
+        for (file, mnfst) in get_dir_manifests(x)? {
+            log.trace(&format!("from {}", file));
+            let mut layers = mnfst.layers.unwrap_or_default();
+            if let Some(cfg) = mnfst.config {
+                layers.push(cfg);
+            }
+            for layer in layers.iter() {
+                blobs
+                    .entry(layer.digest.clone())
+                    .or_default()
+                    .push(file.clone());
             }
         }
     }
 
+    let mut index = vec![];
+    for (digest, manifests) in blobs.into_iter() {
+        // blobs shipped in a previous archive are already on the disconnected side
+        if shipped.contains(&digest) {
+            log.trace(&format!("blob {} already shipped", digest));
+            continue;
+        }
+        let hash = digest.split(":").last().unwrap_or_default().to_string();
+        if hash.len() < 2 {
+            return Err(MirrorError::ManifestParse(format!(
+                "invalid blob digest {}",
+                digest
+            )));
+        }
+        let from = storage.fetch(&get_blob_key(&digest)).await.map_err(|_| {
+            MirrorError::Io(format!(
+                "blob {} referenced by {} not found in {}",
+                digest,
+                manifests[0],
+                storage.root()
+            ))
+        })?;
+        let to_dir = tmp_dir.path().join("blobs").join(&hash[..2]);
+        fs::create_dir_all(&to_dir)?;
+        log.hi(&format!("blob to copy {:#?}", digest));
+        let size = fs::copy(&from, to_dir.join(&hash))?;
+        index.push(BlobIndexEntry {
+            digest,
+            size,
+            manifests,
+        });
+    }
+    log.info(&format!("blobs added to tar {}", index.len()));
+    fs::write(
+        tmp_dir.path().join("metadata/blobs-index.json"),
+        serde_json::to_string_pretty(&index)?,
+    )?;
+
     log.trace("building tar ball ....");
+    // diskToMirror reads the releases resolved from the upgrade graph
+    let releases = storage.local_dir() + RESOLVED_RELEASES_FILE;
+    if Path::new(&releases).exists() {
+        fs::copy(
+            &releases,
+            tmp_dir.path().join("metadata").join(RESOLVED_RELEASES_FILE),
+        )?;
+    }
     // finally copy over the current imagesetconfig used
-    fs::write(tmp_dir.path().join("metadata/isc.yaml"), config.clone())
-        .expect("should write isc.yaml file");
+    fs::write(tmp_dir.path().join("metadata/isc.yaml"), config.clone())?;
     log.trace(&format!("imagesetconfig written {}", config));
+    // split into volumes next to the tar file when a size is set
+    if archive_size > 0 {
+        let out_dir = Path::new(&tar_file)
+            .parent()
+            .map(|p| p.display().to_string())
+            .unwrap_or_default();
+        let volumes = create_volumes(log, tmp_dir.path(), out_dir, archive_size)?;
+        log.info(&format!("created {} volumes", volumes.len()));
+        tmp_dir.close()?;
+        return Ok((volumes, index.into_iter().map(|e| e.digest).collect()));
+    }
     // create the tar
-    let tar_gz = File::create(tar_file.clone()).unwrap();
+    let tar_gz = File::create(tar_file.clone())?;
     let enc = GzEncoder::new(tar_gz, Compression::default());
     let mut tar = tar::Builder::new(enc);
     // add all the contents to the tar
-    tar.append_dir_all(".", tmp_dir.path()).unwrap();
-    tmp_dir.close().unwrap();
-    Ok(true)
+    tar.append_dir_all(".", tmp_dir.path())?;
+    tar.into_inner()?.finish()?;
+    tmp_dir.close()?;
+    Ok((
+        vec![tar_file],
+        index.into_iter().map(|e| e.digest).collect(),
+    ))
+}
+
+// component directories are stored relative to the working-dir, they unpack
+// into the working-dir on the disconnected side as they are
+fn get_archive_path(root: &str, path: &str) -> Result<String, MirrorError> {
+    path.trim_start_matches("./")
+        .strip_prefix(root.trim_start_matches("./"))
+        .map(|rel| rel.trim_start_matches("/").to_string())
+        .ok_or_else(|| MirrorError::Config(format!("{} is not in the working-dir {}", path, root)))
+}
+
+// parse the --archive-size flag, a byte count with an optional M (MiB) or G (GiB) suffix
+pub fn parse_archive_size(value: &str) -> Result<u64, MirrorError> {
+    let upper = value.trim().to_ascii_uppercase();
+    let (number, unit) = if let Some(number) = upper.strip_suffix("G") {
+        (number, 1024 * 1024 * 1024)
+    } else if let Some(number) = upper.strip_suffix("M") {
+        (number, 1024 * 1024)
+    } else {
+        (upper.as_str(), 1)
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit))
+        .ok_or_else(|| {
+            MirrorError::Config(format!(
+                "archive size {} expected to be a number of bytes with an optional M or G suffix",
+                value
+            ))
+        })
+}
+
+// get the name of a volume i.e. mirror_000001.tar
+pub fn get_volume_name(volume: usize) -> String {
+    format!("mirror_{:06}.tar", volume)
+}
+
+// space a file takes up in a tar (header plus data padded to 512 byte blocks)
+// names longer than 100 bytes are written in an extra GNU long name entry
+fn get_tar_size(name: &Path, size: u64) -> u64 {
+    let padded = |size: u64| ((size + 511) / 512) * 512;
+    let name_len = name.as_os_str().len() as u64;
+    let long_name = if name_len > 100 {
+        512 + padded(name_len + 1)
+    } else {
+        0
+    };
+    long_name + 512 + padded(size)
+}
+
+// size of a volume with the tar size of its files and the volume manifest listing its
+// blobs, the manifest has a line per blob and a tar ends with two empty blocks
+fn get_volume_size(files: u64, blobs: &[PathBuf]) -> u64 {
+    let listed: u64 = blobs
+        .iter()
+        .map(|rel| rel.file_name().unwrap_or_default().len() as u64 + 16)
+        .sum();
+    files + get_tar_size(Path::new(&get_volume_manifest_name(0)), 128 + listed) + 1024
+}
+
+// write the staged diff into numbered volumes of at most archive_size bytes
+// the first volume carries the metadata and manifests, blobs are spread over all volumes
+// blobs are not split, an archive_size that can't hold the metadata or a blob is an error
+fn create_volumes(
+    log: &Logging,
+    src: &Path,
+    out_dir: String,
+    archive_size: u64,
+) -> Result<Vec<String>, MirrorError> {
+    let mut files = vec![];
+    let mut blobs = vec![];
+    for e in WalkDir::new(src)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if !e.path().is_file() {
+            continue;
+        }
+        let rel = e
+            .path()
+            .strip_prefix(src)
+            .map_err(|err| MirrorError::Io(err.to_string()))?
+            .to_path_buf();
+        let size = get_tar_size(&rel, e.metadata().map(|m| m.len()).unwrap_or(0));
+        if rel.starts_with("blobs") {
+            blobs.push((rel, size));
+        } else {
+            files.push((rel, size));
+        }
+    }
+
+    let mut current: u64 = files.iter().map(|(_, size)| size).sum();
+    let metadata_size = get_volume_size(current, &[]);
+    if metadata_size > archive_size {
+        return Err(MirrorError::Config(format!(
+            "archive size {} is too small for the metadata of the archive ({} bytes)",
+            archive_size, metadata_size
+        )));
+    }
+    let mut volumes: Vec<Vec<PathBuf>> = vec![files.into_iter().map(|(rel, _)| rel).collect()];
+    let mut volume_blobs: Vec<PathBuf> = vec![];
+    for (rel, size) in blobs {
+        volume_blobs.push(rel.clone());
+        if get_volume_size(current + size, &volume_blobs) > archive_size {
+            let blob_size = get_volume_size(size, &[rel.clone()]);
+            if blob_size > archive_size {
+                return Err(MirrorError::Config(format!(
+                    "archive size {} is too small for blob {} ({} bytes)",
+                    archive_size,
+                    rel.display(),
+                    blob_size
+                )));
+            }
+            volumes.push(vec![]);
+            volume_blobs = vec![rel.clone()];
+            current = 0;
+        }
+        current += size;
+        volumes.last_mut().unwrap().push(rel);
+    }
+
+    let total = volumes.len();
+    let mut names = vec![];
+    for (i, contents) in volumes.iter().enumerate() {
+        let volume = i + 1;
+        let manifest = VolumeManifest {
+            volume,
+            total,
+            blobs: contents
+                .iter()
+                .filter(|rel| rel.starts_with("blobs"))
+                .map(|rel| {
+                    String::from("sha256:") + &rel.file_name().unwrap_or_default().to_string_lossy()
+                })
+                .collect(),
+        };
+        let manifest_file = get_volume_manifest_name(volume);
+        fs::write(
+            src.join(&manifest_file),
+            serde_json::to_string_pretty(&manifest)?,
+        )?;
+
+        let name = Path::new(&out_dir)
+            .join(get_volume_name(volume))
+            .display()
+            .to_string();
+        log.debug(&format!(
+            "volume {} with {} blobs",
+            name,
+            manifest.blobs.len()
+        ));
+        let mut tar = tar::Builder::new(File::create(&name)?);
+        for rel in contents.iter() {
+            tar.append_path_with_name(src.join(rel), rel)?;
+        }
+        tar.append_path_with_name(src.join(&manifest_file), &manifest_file)?;
+        tar.finish()?;
+        let size = fs::metadata(&name)?.len();
+        if size > archive_size {
+            return Err(MirrorError::Io(format!(
+                "volume {} is {} bytes, larger than the archive size {}",
+                name, size, archive_size
+            )));
+        }
+        names.push(name);
+    }
+    Ok(names)
+}
+
+// get the name of the manifest inside a volume i.e. metadata/volume-000001.json
+pub fn get_volume_manifest_name(volume: usize) -> String {
+    format!("metadata/volume-{:06}.json", volume)
 }
 
 /*
@@ -160,25 +422,16 @@ mod tests {
 
     // this brings everything from parent's scope into this scope
     use super::*;
+    use crate::storage::local::LocalStorage;
 
-    #[test]
-    fn get_metadata_dirs_incremental_pass() {
-        let log = &Logging {
-            log_level: Level::INFO,
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
         };
-        let mut hs = HashSet::new();
-        hs.insert(String::from(
-            "test-artifacts/test-index-operator/v1.0/operators/albo/aws-load-balancer-controller-rhel8/stable-v1",
-        ));
-        let res = get_metadata_dirs_incremental(
-            log,
-            String::from("test-artifacts/test-index-operator/v1.0/operators/albo/aws-load-balancer-controller-rhel8/stable-v1"),
-        );
-        assert_eq!(res, hs);
     }
 
     #[test]
-    fn get_metadata_dirs_by_date_pass() {
+    fn get_metadata_dirs_incremental_pass() {
         let log = &Logging {
             log_level: Level::INFO,
         };
@@ -186,27 +439,13 @@ mod tests {
         hs.insert(String::from(
             "test-artifacts/test-index-operator/v1.0/operators/albo/aws-load-balancer-controller-rhel8/stable-v1",
         ));
-        let res = get_metadata_dirs_by_date(
+        let res = get_metadata_dirs_incremental(
             log,
             String::from("test-artifacts/test-index-operator/v1.0/operators/albo/aws-load-balancer-controller-rhel8/stable-v1"),
-            String::from("2023/08/01"),
         );
         assert_eq!(res, hs);
     }
 
-    #[test]
-    #[should_panic]
-    fn get_metadata_dirs_by_date_fail() {
-        let log = &Logging {
-            log_level: Level::INFO,
-        };
-        let mut hs = HashSet::new();
-        hs.insert(String::from("test-artifacts/operators"));
-        let res =
-            get_metadata_dirs_by_date(log, String::from("test-artifacts"), String::from("/08/01"));
-        assert_eq!(res, hs);
-    }
-
     #[test]
     fn create_diff_tar_pass() {
         let log = &Logging {
@@ -215,16 +454,66 @@ mod tests {
         let mnfst_dir =
             &"test-artifacts/test-index-operator/v1.0/operators/albo/aws-load-balancer-controller-rhel8/stable-v1/".to_string();
         let files = vec![mnfst_dir];
-        let res = create_diff_tar(
+        let storage = LocalStorage::new("test-artifacts/");
+        let res = aw!(create_diff_tar(
             log,
             String::from("test-diff.tar.gz"),
-            String::from("test-artifacts/blobs-store/"),
+            &storage,
             files.clone(),
             String::from("imagesetconfig"),
-        );
+            0,
+            &HashSet::new(),
+        ));
         let exists = fs::metadata("test-diff.tar.gz").is_ok();
         assert_eq!(exists, true);
+
+        // the tar holds every referenced blob and the blob index
+        let tar_gz = File::open("test-diff.tar.gz").expect("should open tar");
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(tar_gz));
+        let entries: Vec<String> = archive
+            .entries()
+            .expect("should read tar entries")
+            .map(|e| {
+                let path = e.unwrap().path().unwrap().display().to_string();
+                path.trim_start_matches("./").to_string()
+            })
+            .collect();
         fs::remove_file("test-diff.tar.gz").expect("should delete file");
         log.info(&format!("return value {:#?}", res));
+        assert!(res.is_ok());
+        assert!(entries.contains(&String::from("metadata/blobs-index.json")));
+        // the component directory is stored relative to the working-dir
+        assert!(entries.contains(&String::from(
+            "test-index-operator/v1.0/operators/albo/aws-load-balancer-controller-rhel8/stable-v1/manifest-list.json"
+        )));
+        assert!(entries.contains(&String::from(
+            "blobs/1b/1b594048db9380f9a8dd2e45e16a2e12d39df51f6f61d9be4c9a2986cbc2828b"
+        )));
+        assert!(entries.contains(&String::from(
+            "blobs/ab/ab23d850616c11ac4041387983b3dd271728cdb601faef00e8c500b17e306077"
+        )));
+        assert_eq!(res.unwrap().0, vec![String::from("test-diff.tar.gz")]);
+    }
+
+    #[test]
+    fn parse_archive_size_pass() {
+        assert_eq!(parse_archive_size("0").unwrap(), 0);
+        assert_eq!(parse_archive_size("1048576").unwrap(), 1024 * 1024);
+        assert_eq!(parse_archive_size("700M").unwrap(), 700 * 1024 * 1024);
+        assert_eq!(parse_archive_size("4g").unwrap(), 4 * 1024 * 1024 * 1024);
+        for value in ["", "G", "4T", "-1", "4.5G", "99999999999999G"] {
+            let res = parse_archive_size(value);
+            assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+        }
+    }
+
+    #[test]
+    fn get_dir_manifests_pass() {
+        let res = get_dir_manifests(
+            "test-artifacts/test-index-operator/v1.0/operators/albo/aws-load-balancer-controller-rhel8/stable-v1",
+        )
+        .unwrap();
+        assert_eq!(res.len(), 1);
+        assert!(res[0].0.ends_with("manifest-amd64.json"));
     }
 }
@@ -1 +1,3 @@
+pub mod archive;
+pub mod history;
 pub mod metadata_cache;
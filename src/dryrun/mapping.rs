@@ -0,0 +1,207 @@
+use custom_logger::*;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use crate::cluster::resources::{get_manifest_digests, get_mirror_repo, get_mirrored_images};
+use crate::config::load::Mirror;
+use crate::error::handler::MirrorError;
+use crate::reference::parser::Reference;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DryRunImage {
+    #[serde(rename = "source")]
+    pub source: String,
+
+    #[serde(rename = "destination")]
+    pub destination: String,
+
+    #[serde(rename = "blobs")]
+    pub blobs: usize,
+
+    // compressed size of the config and layers
+    #[serde(rename = "size")]
+    pub size: i64,
+}
+
+// written to summary.json, blobs shared by several images only count once in the totals
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DryRunSummary {
+    #[serde(rename = "images")]
+    pub images: Vec<DryRunImage>,
+
+    #[serde(rename = "totalImages")]
+    pub total_images: usize,
+
+    #[serde(rename = "totalBlobs")]
+    pub total_blobs: usize,
+
+    #[serde(rename = "totalSize")]
+    pub total_size: i64,
+}
+
+// the config and layer digests with their size, for a manifest list the blobs of the
+// child manifests found in manifests (digest -> file)
+pub fn get_image_blobs(
+    data: &str,
+    manifests: &HashMap<String, String>,
+) -> Result<BTreeMap<String, i64>, MirrorError> {
+    let value: Value = serde_json::from_str(data)?;
+    let mut blobs = BTreeMap::new();
+    if let Some(children) = value["manifests"].as_array() {
+        for child in children.iter() {
+            let digest = child["digest"].as_str().unwrap_or_default();
+            if let Some(file) = manifests.get(digest) {
+                blobs.append(&mut get_image_blobs(&fs::read_to_string(file)?, manifests)?);
+            }
+        }
+        return Ok(blobs);
+    }
+    let mut layers = value["layers"].as_array().cloned().unwrap_or_default();
+    layers.push(value["config"].clone());
+    for layer in layers.iter() {
+        if let Some(digest) = layer["digest"].as_str() {
+            blobs.insert(digest.to_string(), layer["size"].as_i64().unwrap_or(0));
+        }
+    }
+    Ok(blobs)
+}
+
+// the destination reference of an image i.e. localhost:5000/test/ns/name:v1
+// images with a digest are pushed by digest only
+pub fn get_destination_image(
+    destination: &str,
+    sub_component: &str,
+    image: &str,
+) -> Result<String, MirrorError> {
+    let reference = Reference::parse(image)?;
+    let separator = match reference.digest {
+        Some(_) => "@",
+        None => ":",
+    };
+    Ok(get_mirror_repo(destination, sub_component) + separator + &reference.version())
+}
+
+// write mapping.txt (source=destination) and summary.json for the images resolved
+// by the collectors, nothing is copied
+pub async fn write_dry_run(
+    log: &Logging,
+    dir: String,
+    destination: String,
+    mirror: &Mirror,
+    out_dir: String,
+) -> Result<DryRunSummary, MirrorError> {
+    let manifests = get_manifest_digests(&dir)?;
+    let images = get_mirrored_images(log, dir.clone(), mirror, &manifests).await?;
+    let mut summary = DryRunSummary::default();
+    let mut all_blobs = BTreeMap::new();
+    let mut mapping = String::new();
+    for (image, mi) in images.iter() {
+        let mut blobs = get_image_blobs(&fs::read_to_string(&mi.file)?, &manifests)?;
+        let dest = get_destination_image(&destination, &mi.sub_component, image)?;
+        mapping.push_str(&format!("{}={}\n", image, dest));
+        summary.images.push(DryRunImage {
+            source: image.clone(),
+            destination: dest,
+            blobs: blobs.len(),
+            size: blobs.values().sum(),
+        });
+        all_blobs.append(&mut blobs);
+    }
+    summary.total_images = summary.images.len();
+    summary.total_blobs = all_blobs.len();
+    summary.total_size = all_blobs.values().sum();
+
+    fs::create_dir_all(&out_dir)?;
+    fs::write(out_dir.clone() + "mapping.txt", mapping)?;
+    fs::write(
+        out_dir.clone() + "summary.json",
+        serde_json::to_string_pretty(&summary)?,
+    )?;
+    log.info(&format!(
+        "dry-run {} images, {} blobs ({} bytes) written to {}",
+        summary.total_images, summary.total_blobs, summary.total_size, out_dir
+    ));
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use crate::additional::collector::get_additional_manifest_json_dir;
+    use crate::config::load::Image;
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    #[test]
+    fn write_dry_run_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let dir = String::from("./test-artifacts/dry-run-test/");
+        let manifest = r#"{"schemaVersion": 2, "config": {"digest": "sha256:c0", "size": 10}, "layers": [{"digest": "sha256:a1", "size": 100}, {"digest": "sha256:a2", "size": 200}]}"#;
+        let other = r#"{"schemaVersion": 2, "config": {"digest": "sha256:c1", "size": 20}, "layers": [{"digest": "sha256:a1", "size": 100}]}"#;
+        for (name, data) in [("name", manifest), ("other", other)] {
+            let add_dir = get_additional_manifest_json_dir(dir.clone(), "ns", name, "v1");
+            fs::create_dir_all(&add_dir).expect("should create test dir");
+            fs::write(add_dir + "/manifest.json", data).expect("should write manifest");
+        }
+        let mirror = Mirror {
+            release: None,
+            operators: None,
+            additional_images: Some(vec![
+                Image {
+                    name: String::from("quay.io/ns/name:v1"),
+                },
+                Image {
+                    name: String::from("quay.io/ns/other:v1"),
+                },
+            ]),
+            platform: None,
+            destination_template: None,
+        };
+        let summary = aw!(write_dry_run(
+            log,
+            dir.clone(),
+            String::from("docker://localhost:5000/test"),
+            &mirror,
+            dir.clone() + "dry-run/",
+        ))
+        .unwrap();
+        assert_eq!(summary.images[0].size, 310);
+        assert_eq!(summary.images[1].blobs, 2);
+        // the shared layer only counts once
+        assert_eq!(summary.total_blobs, 4);
+        assert_eq!(summary.total_size, 330);
+        assert_eq!(
+            fs::read_to_string(dir.clone() + "dry-run/mapping.txt").unwrap(),
+            "quay.io/ns/name:v1=localhost:5000/test/ns/name:v1\nquay.io/ns/other:v1=localhost:5000/test/ns/other:v1\n"
+        );
+        rm_rf::remove(&dir).expect("should delete test dir");
+    }
+
+    #[test]
+    fn get_destination_image_pass() {
+        let sha = "sha256:5e03f571c5993f0853a910b7c0cab44ec0e451b94a9677ed82e921b54a4b735a";
+        assert_eq!(
+            get_destination_image(
+                "docker://localhost:5000/test",
+                "ocp-release",
+                &format!("quay.io/openshift-release-dev/ocp-v4.0-art-dev:v1@{}", sha)
+            )
+            .unwrap(),
+            format!("localhost:5000/test/ocp-release@{}", sha)
+        );
+        assert_eq!(
+            get_destination_image("docker://localhost:5000/test", "library/ubuntu", "ubuntu")
+                .unwrap(),
+            "localhost:5000/test/library/ubuntu:latest"
+        );
+    }
+}
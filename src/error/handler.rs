@@ -1,42 +1,239 @@
 use std::error::Error;
 use std::fmt;
+use std::time::Duration;
 
 #[derive(Debug)]
-pub struct MirrorError {
-    details: String,
+pub enum MirrorError {
+    Config(String),
+    Auth(String),
+    RegistryHttp(String),
+    ManifestParse(String),
+    BlobDigestMismatch { expected: String, actual: String },
+    Io(String),
 }
 
-#[allow(dead_code)]
 impl MirrorError {
-    pub fn new(msg: &str) -> MirrorError {
-        MirrorError {
-            details: msg.to_string(),
+    // map each variant to a distinct process exit code
+    pub fn exit_code(&self) -> exitcode::ExitCode {
+        match self {
+            MirrorError::Config(_) => exitcode::CONFIG,
+            MirrorError::Auth(_) => exitcode::NOPERM,
+            MirrorError::RegistryHttp(_) => exitcode::UNAVAILABLE,
+            MirrorError::ManifestParse(_) => exitcode::DATAERR,
+            MirrorError::BlobDigestMismatch { .. } => exitcode::PROTOCOL,
+            MirrorError::Io(_) => exitcode::IOERR,
         }
     }
 }
 
 impl fmt::Display for MirrorError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.details)
+        match self {
+            MirrorError::Config(msg) => write!(f, "config error: {}", msg),
+            MirrorError::Auth(msg) => write!(f, "auth error: {}", msg),
+            MirrorError::RegistryHttp(msg) => write!(f, "registry error: {}", msg),
+            MirrorError::ManifestParse(msg) => write!(f, "manifest parse error: {}", msg),
+            MirrorError::BlobDigestMismatch { expected, actual } => write!(
+                f,
+                "blob digest mismatch: expected {} found {}",
+                expected, actual
+            ),
+            MirrorError::Io(msg) => write!(f, "io error: {}", msg),
+        }
+    }
+}
+
+impl Error for MirrorError {}
+
+impl From<std::io::Error> for MirrorError {
+    fn from(err: std::io::Error) -> MirrorError {
+        MirrorError::Io(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for MirrorError {
+    fn from(err: serde_json::Error) -> MirrorError {
+        MirrorError::ManifestParse(err.to_string())
+    }
+}
+
+impl From<serde_yaml::Error> for MirrorError {
+    fn from(err: serde_yaml::Error) -> MirrorError {
+        MirrorError::Config(err.to_string())
+    }
+}
+
+// a failed registry request, the status (None when there was no response) and the
+// Retry-After delay tell the scheduler whether to refresh the token or retry
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestError {
+    pub url: String,
+    pub status: Option<u16>,
+    pub retry_after: Option<Duration>,
+    pub msg: String,
+}
+
+impl RequestError {
+    pub fn from_response(url: &str, res: &reqwest::Response) -> RequestError {
+        let retry_after = res
+            .headers()
+            .get("Retry-After")
+            .and_then(|h| h.to_str().ok())
+            .and_then(parse_retry_after);
+        RequestError {
+            url: url.to_string(),
+            status: Some(res.status().as_u16()),
+            retry_after,
+            msg: res.status().to_string(),
+        }
+    }
+
+    // connection errors and timeouts
+    pub fn from_reqwest(url: &str, err: reqwest::Error) -> RequestError {
+        RequestError {
+            url: url.to_string(),
+            status: err.status().map(|s| s.as_u16()),
+            retry_after: None,
+            msg: err.to_string(),
+        }
+    }
+
+    pub fn is_unauthorized(&self) -> bool {
+        self.status == Some(401)
+    }
+
+    // throttled (429), unavailable (502, 503, 504) or no response at all
+    pub fn is_transient(&self) -> bool {
+        match self.status {
+            Some(status) => [429, 502, 503, 504].contains(&status),
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.status {
+            Some(_) => write!(f, "{} returned {}", self.url, self.msg),
+            None => write!(f, "{} {}", self.url, self.msg),
+        }
+    }
+}
+
+impl Error for RequestError {}
+
+impl From<RequestError> for MirrorError {
+    fn from(err: RequestError) -> MirrorError {
+        match err.status {
+            Some(401) | Some(403) => MirrorError::Auth(err.to_string()),
+            _ => MirrorError::RegistryHttp(err.to_string()),
+        }
+    }
+}
+
+// Retry-After is either a number of seconds or an http date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
     }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let seconds = (date.timestamp() - chrono::Utc::now().timestamp()).max(0);
+    Some(Duration::from_secs(seconds as u64))
 }
 
-impl Error for MirrorError {
-    fn description(&self) -> &str {
-        &self.details
+// classify an error returned by the registry client
+// unauthorized responses are reported as auth errors, errors of other RegistryInterface
+// implementations only have their message
+pub fn registry_error(url: &str, err: Box<dyn Error>) -> MirrorError {
+    if let Some(err) = err.downcast_ref::<RequestError>() {
+        return MirrorError::from(err.clone());
+    }
+    // i.e. a blob that does not match its digest or could not be stored
+    let err = match err.downcast::<MirrorError>() {
+        Ok(err) => return *err,
+        Err(err) => err,
+    };
+    let msg = err.to_string();
+    if msg.contains("401") || msg.to_lowercase().contains("unauthorized") {
+        MirrorError::Auth(format!("{} {}", url, msg))
+    } else {
+        MirrorError::RegistryHttp(format!("{} {}", url, msg))
     }
 }
 
 #[cfg(test)]
-#[allow(deprecated)]
 mod tests {
     // this brings everything from parent's scope into this scope
     use super::*;
 
     #[test]
     fn err_pass() {
-        let err = MirrorError::new(&format!("testing error {}", "123456".to_string()));
-        assert_eq!(err.to_string(), "testing error 123456");
-        assert_eq!(err.description(), "testing error 123456");
+        let err = MirrorError::Config(format!("testing error {}", "123456".to_string()));
+        assert_eq!(err.to_string(), "config error: testing error 123456");
+        assert_eq!(err.exit_code(), exitcode::CONFIG);
+        let err = MirrorError::BlobDigestMismatch {
+            expected: String::from("sha256:1234"),
+            actual: String::from("sha256:5678"),
+        };
+        assert_eq!(
+            err.to_string(),
+            "blob digest mismatch: expected sha256:1234 found sha256:5678"
+        );
+    }
+
+    #[test]
+    fn exit_codes_are_distinct_pass() {
+        let errs = vec![
+            MirrorError::Config(String::from("")),
+            MirrorError::Auth(String::from("")),
+            MirrorError::RegistryHttp(String::from("")),
+            MirrorError::ManifestParse(String::from("")),
+            MirrorError::BlobDigestMismatch {
+                expected: String::from(""),
+                actual: String::from(""),
+            },
+            MirrorError::Io(String::from("")),
+        ];
+        let mut codes: Vec<i32> = errs.iter().map(|e| e.exit_code()).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), 6);
+    }
+
+    #[test]
+    fn registry_error_pass() {
+        let err = registry_error("https://test", "401 Unauthorized".into());
+        assert_eq!(err.exit_code(), exitcode::NOPERM);
+        let err = registry_error("https://test", "connection refused".into());
+        assert_eq!(err.exit_code(), exitcode::UNAVAILABLE);
+        let err = RequestError {
+            url: String::from("https://test/v2/ns/name/manifests/v1"),
+            status: Some(403),
+            retry_after: None,
+            msg: String::from("403 Forbidden"),
+        };
+        assert!(!err.is_transient());
+        let err = registry_error("https://test", Box::new(err));
+        assert_eq!(
+            err.to_string(),
+            "auth error: https://test/v2/ns/name/manifests/v1 returned 403 Forbidden"
+        );
+        let err = MirrorError::BlobDigestMismatch {
+            expected: String::from("sha256:1234"),
+            actual: String::from("sha256:5678"),
+        };
+        let err = registry_error("https://test", Box::new(err));
+        assert_eq!(err.exit_code(), exitcode::PROTOCOL);
+    }
+
+    #[test]
+    fn parse_retry_after_pass() {
+        assert_eq!(parse_retry_after("30"), Some(Duration::from_secs(30)));
+        // dates in the past retry right away
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
     }
 }
@@ -0,0 +1,367 @@
+use custom_logger::*;
+use mirror_copy::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::error::handler::MirrorError;
+use crate::scheduler::registry::RegistryScheduler;
+use crate::storage::{get_blob_key, Storage};
+use crate::verify::blobs::{get_blob_path, verify_blob_digest};
+
+// run-state journal persisted in the working-dir
+// records every image and blob that completed so an interrupted
+// mirrorToDisk run can resume from the last checkpoint
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct RunState {
+    #[serde(rename = "completed")]
+    pub completed: bool,
+
+    #[serde(rename = "images")]
+    pub images: HashSet<String>,
+
+    #[serde(rename = "blobs")]
+    pub blobs: HashSet<String>,
+
+    #[serde(skip)]
+    pub file: String,
+
+    #[serde(skip)]
+    pub resume: bool,
+
+    // mirrorToMirror and dry-run only resolve the image manifests, the image blobs are
+    // streamed from registry to registry (mirrorToMirror) or not needed at all (dry-run)
+    // the blobs-store only receives the release payload and catalog blobs the
+    // collectors untar
+    #[serde(skip)]
+    pub metadata_only: bool,
+}
+
+impl RunState {
+    // load (resume) or reset (restart) the journal in the working-dir
+    pub fn new(
+        log: &Logging,
+        dir: String,
+        resume: bool,
+        restart: bool,
+    ) -> Result<RunState, MirrorError> {
+        let file = dir + "run-state.json";
+        if restart && Path::new(&file).exists() {
+            log.info("restart set, discarding previous run-state journal");
+            fs::remove_file(&file)?;
+        }
+        let mut state = RunState::default();
+        if resume && Path::new(&file).exists() {
+            let data = fs::read_to_string(&file)?;
+            let previous: RunState = serde_json::from_str(&data)?;
+            if previous.completed {
+                log.info("previous run completed, nothing to resume");
+            } else {
+                log.info(&format!(
+                    "resuming from checkpoint ({} images, {} blobs completed)",
+                    previous.images.len(),
+                    previous.blobs.len()
+                ));
+                state = previous;
+            }
+        }
+        state.completed = false;
+        state.file = file;
+        state.resume = resume;
+        Ok(state)
+    }
+
+    pub fn is_image_done(&self, image: &str) -> bool {
+        self.images.contains(image)
+    }
+
+    pub fn is_blob_done(&self, digest: &str) -> bool {
+        self.blobs.contains(digest)
+    }
+
+    // record a completed image and its blobs and persist the journal
+    pub fn checkpoint(&mut self, image: String, blobs: Vec<String>) -> Result<(), MirrorError> {
+        self.images.insert(image);
+        for blob in blobs {
+            self.blobs.insert(blob);
+        }
+        self.save()
+    }
+
+    // mark the whole run as completed
+    pub fn complete(&mut self) -> Result<(), MirrorError> {
+        self.completed = true;
+        self.save()
+    }
+
+    // write to a temporary file first so a crash never leaves a truncated journal
+    // an in-memory journal (no file set) is never persisted
+    fn save(&self) -> Result<(), MirrorError> {
+        if self.file.is_empty() {
+            return Ok(());
+        }
+        if let Some(parent) = Path::new(&self.file).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = self.file.clone() + ".tmp";
+        fs::write(&tmp, serde_json::to_string(&self)?)?;
+        fs::rename(&tmp, &self.file)?;
+        Ok(())
+    }
+}
+
+// wraps get_blobs so that the caller knows which image and blobs completed
+// blobs recorded in the journal (resumed run) that are in the blobs-store and blobs a
+// remote workspace already has are skipped, each downloaded blob is verified against its
+// digest
+pub async fn get_blobs_tracked<T: RegistryInterface>(
+    reg_con: &T,
+    scheduler: &RegistryScheduler,
+    log: &Logging,
+    storage: &dyn Storage,
+    url: String,
+    image: String,
+    layers: Vec<FsLayer>,
+    recorded: &RunState,
+) -> (String, Vec<String>, Result<String, MirrorError>) {
+    let dir = storage.local_dir() + "blobs-store/";
+    let remote = storage.local_dir() != storage.root();
+    let digests: Vec<String> = layers.iter().map(|l| l.blob_sum.clone()).collect();
+    let mut pending = vec![];
+    for layer in layers.into_iter() {
+        if recorded.is_blob_done(&layer.blob_sum)
+            && Path::new(&get_blob_path(&dir, &layer.blob_sum)).exists()
+        {
+            continue;
+        }
+        if remote {
+            match storage.exists(&get_blob_key(&layer.blob_sum)).await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(err) => return (image, digests, Err(err)),
+            }
+        }
+        pending.push(layer);
+    }
+    if pending.len() < digests.len() {
+        log.debug(&format!(
+            "{} of {} blobs for {} found in checkpoint or workspace",
+            digests.len() - pending.len(),
+            digests.len(),
+            image
+        ));
+    }
+    let res = scheduler
+        .get_blobs(reg_con, log, &dir, &url, &pending)
+        .await
+        .and_then(|response| {
+            pending
+                .iter()
+                .try_for_each(|layer| verify_blob_digest(&dir, &layer.blob_sum))
+                .map(|_| response)
+        });
+    (image, digests, res)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use crate::auth::token_cache::TokenCache;
+    use crate::config::registries::SourceMirrors;
+    use crate::scheduler::registry::SchedulerConfig;
+    use crate::storage::local::LocalStorage;
+    use async_trait::async_trait;
+    use sha2::{Digest, Sha256};
+    use std::sync::Mutex;
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    #[test]
+    fn run_state_resume_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let dir = String::from("./test-artifacts/run-state-test/");
+        let mut state = RunState::new(log, dir.clone(), false, true).unwrap();
+        state
+            .checkpoint(
+                String::from("quay.io/test/test-image@sha256:1234"),
+                vec![String::from("sha256:abcd")],
+            )
+            .unwrap();
+
+        // a resumed run sees the previous checkpoint
+        let state = RunState::new(log, dir.clone(), true, false).unwrap();
+        assert!(state.is_image_done("quay.io/test/test-image@sha256:1234"));
+        assert!(state.is_blob_done("sha256:abcd"));
+
+        // a run without resume starts with an empty journal
+        let state = RunState::new(log, dir.clone(), false, false).unwrap();
+        assert!(!state.is_image_done("quay.io/test/test-image@sha256:1234"));
+        rm_rf::remove(&dir).expect("should delete test dir");
+    }
+
+    #[test]
+    fn run_state_completed_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let dir = String::from("./test-artifacts/run-state-completed-test/");
+        let mut state = RunState::new(log, dir.clone(), false, true).unwrap();
+        state
+            .checkpoint(String::from("quay.io/test/test-image:v1"), vec![])
+            .unwrap();
+        state.complete().unwrap();
+
+        // nothing to resume once a run completed
+        let state = RunState::new(log, dir.clone(), true, false).unwrap();
+        assert!(!state.is_image_done("quay.io/test/test-image:v1"));
+        rm_rf::remove(&dir).expect("should delete test dir");
+    }
+
+    #[test]
+    fn get_blobs_tracked_pass() {
+        // writes the blobs it is asked for, blobs without data are not written
+        struct Fake {
+            blobs: Vec<(String, String)>,
+            requested: Mutex<Vec<String>>,
+        }
+
+        #[async_trait]
+        impl RegistryInterface for Fake {
+            async fn get_manifest(
+                &self,
+                _url: String,
+                _token: String,
+            ) -> Result<String, Box<dyn std::error::Error>> {
+                Ok(String::new())
+            }
+
+            async fn get_blobs(
+                &self,
+                _log: &Logging,
+                dir: String,
+                _url: String,
+                _token: String,
+                layers: Vec<FsLayer>,
+            ) -> Result<String, Box<dyn std::error::Error>> {
+                for layer in layers.iter() {
+                    let (digest, data) = self
+                        .blobs
+                        .iter()
+                        .find(|(digest, _)| *digest == layer.blob_sum)
+                        .ok_or("unknown blob")?;
+                    self.requested.lock().unwrap().push(digest.clone());
+                    if data.is_empty() {
+                        continue;
+                    }
+                    let blob = get_blob_path(&dir, digest);
+                    fs::create_dir_all(Path::new(&blob).parent().unwrap())?;
+                    fs::write(&blob, data)?;
+                }
+                Ok(String::from("test"))
+            }
+
+            async fn push_image(
+                &self,
+                _log: &Logging,
+                _dir: String,
+                _subdir: String,
+                _url: String,
+                _token: String,
+                _manifest: Manifest,
+            ) -> Result<String, mirror_copy::MirrorError> {
+                Ok(String::from("test"))
+            }
+        }
+
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let root = String::from("./test-artifacts/run-state-blobs-test/");
+        let dir = root.clone() + "blobs-store/";
+        let blobs: Vec<(String, String)> = ["done", "gone", "new"]
+            .iter()
+            .map(|data| {
+                let digest = String::from("sha256:") + &hex::encode(Sha256::digest(data));
+                (digest, data.to_string())
+            })
+            .collect();
+        let blob = get_blob_path(&dir, &blobs[0].0);
+        fs::create_dir_all(Path::new(&blob).parent().unwrap()).expect("should create dir");
+        fs::write(&blob, "done").expect("should write blob");
+        let recorded = RunState {
+            blobs: HashSet::from([blobs[0].0.clone(), blobs[1].0.clone()]),
+            ..RunState::default()
+        };
+        let layers: Vec<FsLayer> = blobs
+            .iter()
+            .map(|(digest, _)| FsLayer {
+                blob_sum: digest.clone(),
+                original_ref: None,
+                size: None,
+            })
+            .collect();
+        let fake = Fake {
+            blobs: blobs.clone(),
+            requested: Mutex::new(vec![]),
+        };
+        let scheduler = RegistryScheduler::new(
+            SchedulerConfig::default(),
+            &[],
+            SourceMirrors::default(),
+            TokenCache::anonymous(),
+        );
+        let (image, digests, res) = aw!(get_blobs_tracked(
+            &fake,
+            &scheduler,
+            log,
+            &LocalStorage::new(&root),
+            String::from("https://quay.io/v2/ns/name/blobs/"),
+            String::from("quay.io/ns/name:v1"),
+            layers,
+            &recorded,
+        ));
+        assert!(res.is_ok());
+        assert_eq!(image, "quay.io/ns/name:v1");
+        // every blob is recorded in the checkpoint
+        assert_eq!(digests.len(), 3);
+        // the recorded blob in the blobs-store is skipped, a recorded blob that is
+        // missing is fetched again
+        let mut requested = fake.requested.lock().unwrap().clone();
+        requested.sort();
+        let mut expected = vec![blobs[1].0.clone(), blobs[2].0.clone()];
+        expected.sort();
+        assert_eq!(requested, expected);
+
+        // a blob that is not in the blobs-store after the download is an error
+        let lost = String::from("sha256:") + &hex::encode(Sha256::digest("lost"));
+        let fake = Fake {
+            blobs: vec![(lost.clone(), String::new())],
+            requested: Mutex::new(vec![]),
+        };
+        let layers = vec![FsLayer {
+            blob_sum: lost,
+            original_ref: None,
+            size: None,
+        }];
+        let (_, _, res) = aw!(get_blobs_tracked(
+            &fake,
+            &scheduler,
+            log,
+            &LocalStorage::new(&root),
+            String::from("https://quay.io/v2/ns/name/blobs/"),
+            String::from("quay.io/ns/name:v2"),
+            layers,
+            &RunState::default(),
+        ));
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::IOERR);
+        rm_rf::remove(&root).expect("should delete test dir");
+    }
+}
@@ -1,24 +1,59 @@
 // use modules
+use crate::additional::collector::*;
 use crate::operator::collector::*;
 use crate::release::collector::*;
 use clap::Parser;
 use custom_logger::*;
 use mirror_copy::ImplRegistryInterface;
-use std::collections::HashSet;
+use std::sync::Arc;
 use tokio;
 
 // define local modules
+mod additional;
 mod api;
+mod auth;
+mod cluster;
 mod config;
 mod diff;
+mod dryrun;
 mod error;
+mod journal;
+mod oci;
 mod operator;
+mod prune;
+mod reference;
 mod release;
+mod scheduler;
+mod storage;
+mod stream;
+mod verify;
 
 // use local modules
 use api::schema::*;
+use auth::token_cache::TokenCache;
+use cluster::resources::*;
 use config::load::*;
+use config::registries::*;
+use diff::archive::*;
+use diff::history::*;
 use diff::metadata_cache::*;
+use dryrun::mapping::*;
+use error::handler::MirrorError;
+use journal::run_state::*;
+use oci::layout::*;
+use prune::delete::*;
+use prune::gc::*;
+use scheduler::client::RegistryClient;
+use scheduler::registry::*;
+use storage::*;
+use stream::registry::*;
+use verify::blobs::*;
+
+// staging working-dir for the contents of a mirror-diff archive
+const ARCHIVE_STAGING_DIR: &str = "./working-dir-archive/";
+
+// generated IDMS, ITMS and CatalogSource yaml files
+const CLUSTER_RESOURCES_DIR: &str = "./cluster-resources/";
 
 // main entry point (use async)
 #[tokio::main]
@@ -28,6 +63,7 @@ async fn main() {
     let level = args.loglevel.unwrap().to_string();
     let skip_manifests = args.skip_manifest_check.unwrap().to_string();
     let skip_gen = args.skip_gen_declconfig;
+    let skip_flag = args.skip.unwrap().to_string();
 
     // convert to enum
     let res_log_level = match level.as_str() {
@@ -44,23 +80,119 @@ async fn main() {
 
     // check that destination is set correctly
     if args.destination == "" {
-        log.error("destination is mandatory use docker:// or file:// prefix");
+        log.error("destination is mandatory use docker://, oci:// or file:// prefix");
+        std::process::exit(exitcode::USAGE);
+    }
+
+    // convert the skip flag to enum
+    let skip = match Skip::from_flag(&skip_flag) {
+        Some(val) => val,
+        None => {
+            log.error(&format!(
+                "skip flag {} is invalid use none, release, operators, additional or release-operators",
+                skip_flag
+            ));
+            std::process::exit(exitcode::USAGE);
+        }
+    };
+
+    if args.resume && args.restart {
+        log.error("resume and restart are mutually exclusive");
         std::process::exit(exitcode::USAGE);
     }
 
+    let source_registries = match args.registries_conf.as_deref() {
+        Some(file) => match load_registries_conf(file) {
+            Ok(val) => val,
+            Err(err) => {
+                log.error(&format!("{}", err));
+                std::process::exit(err.exit_code());
+            }
+        },
+        None => vec![],
+    };
+
+    let opts = MirrorOptions {
+        skip,
+        skip_manifest_check: skip_manifests == "release",
+        skip_gen,
+        scheduler: SchedulerConfig {
+            max_concurrency: args.max_concurrency.max(1),
+            rate_limit: args.rate_limit,
+            max_retries: args.max_retries,
+        },
+        source_registries,
+    };
+
     log.info(&format!("rust-image-mirror {} ", cfg));
-    let mut current_cache: HashSet<String> = HashSet::new();
 
-    if args.diff_tar.unwrap() {
-        if args.date.clone().unwrap().len() == 0 {
-            current_cache = get_metadata_dirs_incremental(log, String::from("working-dir/"));
-            log.debug(&format!("current cache {:#?} ", current_cache.clone()));
+    // the collectors always work in a local dir, for remote workspaces it is a cache
+    let storage = match get_storage(&args.workspace) {
+        Ok(val) => val,
+        Err(err) => {
+            log.error(&format!("{}", err));
+            std::process::exit(err.exit_code());
+        }
+    };
+    let work_dir = storage.local_dir();
+
+    // subcommands
+    match args.command {
+        Some(Commands::Verify { repair }) => {
+            verify(log, cfg, &storage, repair, &opts).await;
+            return;
+        }
+        Some(Commands::Delete {
+            delete_config,
+            registry,
+        }) => {
+            if registry && !args.destination.starts_with("docker://") {
+                log.error("delete --registry is only valid with a docker:// destination");
+                std::process::exit(exitcode::USAGE);
+            }
+            let destination = registry.then(|| args.destination.clone());
+            delete(log, delete_config, storage.as_ref(), destination).await;
+            return;
+        }
+        Some(Commands::Gc { dry_run }) => {
+            gc(log, storage.as_ref(), dry_run).await;
+            return;
         }
+        None => {}
+    }
+    // with --from docker:// the images are copied from the source registries (mirrorToMirror)
+    let stream = args.from.as_deref() == Some("docker://");
+    if stream && !args.destination.starts_with("docker://") {
+        log.error("from docker:// is only valid with a docker:// destination");
+        std::process::exit(exitcode::USAGE);
+    }
+    let archive = args.from.is_some() && !stream;
+    if args.dry_run && archive {
+        log.error(
+            "dry-run resolves the images from the source registries, from archive:// is not valid",
+        );
+        std::process::exit(exitcode::USAGE);
     }
 
     // Parse the config serde_yaml::ImageSetConfiguration.
-    let config = load_config(cfg).unwrap();
-    let isc_config = parse_yaml_config(config.clone()).unwrap();
+    // with --from archive:// the images are pushed from a staging working-dir
+    // that only holds the contents of the archive
+    let (config, isc_config, dir) = match args.from.clone() {
+        Some(from) if archive => {
+            if !from.starts_with("archive://") || args.destination.contains("file://") {
+                log.error(
+                    "from is only valid with an archive:// or docker:// prefix and a docker:// or oci:// destination",
+                );
+                std::process::exit(exitcode::USAGE);
+            }
+            let (config, isc_config) = from_archive(log, from, cfg, storage.as_ref()).await;
+            (config, isc_config, String::from(ARCHIVE_STAGING_DIR))
+        }
+        _ => {
+            let (config, isc_config) = load_isc(log, cfg);
+            (config, isc_config, work_dir.clone())
+        }
+    };
 
     log.debug(&format!(
         "image set config releases {:#?}",
@@ -72,89 +204,515 @@ async fn main() {
         isc_config.mirror.operators
     ));
 
+    // the destination layout is validated before any image is pulled or pushed
+    let template = match isc_config.mirror.get_destination_template() {
+        Ok(val) => val,
+        Err(err) => {
+            log.error(&format!("{}", err));
+            std::process::exit(err.exit_code());
+        }
+    };
+
     // initialize the client request interface
     let reg_con = ImplRegistryInterface {};
 
+    // only the mirrored components are referenced in the layout and cluster resources
+    let mirrored = Mirror {
+        release: isc_config
+            .mirror
+            .release
+            .clone()
+            .filter(|_| !skip.release()),
+        operators: isc_config
+            .mirror
+            .operators
+            .clone()
+            .filter(|_| !skip.operators()),
+        additional_images: isc_config
+            .mirror
+            .additional_images
+            .clone()
+            .filter(|_| !skip.additional()),
+        platform: isc_config.mirror.platform.clone(),
+        destination_template: isc_config.mirror.destination_template.clone(),
+    };
+
+    // resolve all images and report what would be mirrored
+    if args.dry_run {
+        mirror_metadata(log, &storage, isc_config, &opts).await;
+        let res = write_dry_run(
+            log,
+            work_dir.clone(),
+            args.destination,
+            &mirrored,
+            work_dir.clone() + "dry-run/",
+        )
+        .await;
+        if let Err(err) = res {
+            log.error(&format!("{}", err));
+            std::process::exit(err.exit_code());
+        }
+        return;
+    }
+
+    // this is diskToMirror into an OCI image layout
+    if args.destination.starts_with("oci://") {
+        if !archive {
+            check_sync(log, sync_from_storage(log, storage.as_ref(), true).await);
+        }
+        let res = write_oci_layout(log, dir.clone(), args.destination, &mirrored).await;
+        check_result(log, res);
+        if archive {
+            if let Err(err) = rm_rf::remove(&dir) {
+                log.error(&format!("unable to remove staging dir {} {:?}", dir, err));
+            }
+        }
+        return;
+    }
+
     // this is mirrorToDisk
     if args.destination.contains("file://") {
-        // check for release image
-        let skip_manifest_check = skip_manifests == "release";
-        if isc_config.mirror.release.is_some() {
-            release_mirror_to_disk(
+        // metadata (journal, history, caches) of earlier runs, blobs are fetched when needed
+        check_sync(log, sync_from_storage(log, storage.as_ref(), false).await);
+        let mut state = match RunState::new(log, work_dir.clone(), args.resume, args.restart) {
+            Ok(val) => val,
+            Err(err) => {
+                log.error(&format!("{}", err));
+                std::process::exit(err.exit_code());
+            }
+        };
+        mirror_to_disk(log, &storage, isc_config, &opts, &mut state).await;
+
+        // all collectors completed, nothing left to resume
+        if let Err(err) = state.complete() {
+            log.error(&format!("{}", err));
+            std::process::exit(err.exit_code());
+        }
+
+        // if flag diff-tar is set create a diff tar.gz
+        if args.diff_tar.unwrap() {
+            create_diff(
+                log,
+                config,
+                storage.as_ref(),
+                args.date.unwrap(),
+                args.archive_size,
+            )
+            .await;
+        }
+        check_sync(log, sync_to_storage(log, storage.as_ref()).await);
+    } else if stream {
+        // this is mirrorToMirror, the collectors resolve the images and only stage the
+        // metadata (release payloads and catalogs) the blobs are streamed to the destination
+        let destination = args.destination;
+        let scheduler = RegistryScheduler::new(
+            opts.scheduler.clone(),
+            &isc_config.registries,
+            opts.get_source_mirrors(&isc_config),
+            TokenCache::new(),
+        );
+        mirror_metadata(log, &storage, isc_config, &opts).await;
+        let res = mirror_to_mirror(
+            log,
+            work_dir.clone(),
+            destination.clone(),
+            &mirrored,
+            &scheduler,
+        )
+        .await;
+        check_result(log, res);
+        generate_resources(log, work_dir.clone(), destination, &mirrored);
+    } else {
+        // this is diskToMirror
+        let destination = args.destination;
+        if !archive {
+            check_sync(log, sync_from_storage(log, storage.as_ref(), true).await);
+        }
+        // manifest lists and catalogs are pushed through the scheduler
+        let scheduler = RegistryScheduler::new(
+            opts.scheduler.clone(),
+            &isc_config.registries,
+            opts.get_source_mirrors(&isc_config),
+            TokenCache::new(),
+        );
+        // the destination repositories are checked before anything is pushed
+        if let Err(err) = get_source_images(log, dir.clone(), &mirrored) {
+            log.error(&format!("{}", err));
+            std::process::exit(err.exit_code());
+        }
+
+        if isc_config.mirror.release.is_some() && !skip.release() {
+            let res = release_disk_to_mirror(
                 reg_con.clone(),
+                &scheduler,
                 log,
-                String::from("./working-dir/"),
-                skip_manifest_check,
+                dir.clone(),
+                destination.clone(),
+                &template,
                 isc_config.mirror.release.unwrap(),
             )
             .await;
+            check_result(log, res);
         }
-        // check for operators
-        if isc_config.mirror.operators.is_some() {
-            operator_mirror_to_disk(
+
+        if isc_config.mirror.operators.is_some() && !skip.operators() {
+            let res = operator_disk_to_mirror(
                 reg_con.clone(),
+                &scheduler,
                 log,
-                String::from("./working-dir/"),
-                skip_gen,
+                dir.clone(),
+                destination.clone(),
+                &template,
                 isc_config.mirror.operators.unwrap(),
             )
             .await;
+            check_result(log, res);
         }
 
-        // TODO: call additionalImages collector
+        if isc_config.mirror.additional_images.is_some() && !skip.additional() {
+            let res = additional_disk_to_mirror(
+                reg_con.clone(),
+                &scheduler,
+                log,
+                dir.clone(),
+                destination.clone(),
+                &template,
+                isc_config.mirror.additional_images.unwrap(),
+            )
+            .await;
+            check_result(log, res);
+        }
 
-        // if flag diff-tar is set create a diff tar.gz
-        if args.diff_tar.unwrap() {
-            let mut new_cache = HashSet::new();
-            log.trace(&format!("new cache {:#?}", new_cache));
-            if args.date.clone().unwrap().len() > 0 {
-                new_cache = get_metadata_dirs_by_date(
-                    log,
-                    String::from("working-dir/"),
-                    args.date.unwrap(),
-                );
-            } else {
-                new_cache = get_metadata_dirs_incremental(log, String::from("working-dir/"));
-            }
-            let diff: Vec<_> = new_cache.difference(&current_cache).collect();
-            log.mid(&format!("difference {:#?}", diff));
-            if diff.len() > 0 {
-                log.info("creating mirror_diff.tar.gz");
-                let res = create_diff_tar(
-                    log,
-                    String::from("mirror-diff.tar.gz"),
-                    String::from("working-dir/blobs-store"),
-                    diff,
-                    config,
-                );
-                match res {
-                    Ok(_) => log.info("mirror-diff.tar.gz successfully created"),
-                    Err(err) => log.error(&format!("errror creating diff tar {:#?}", err)),
-                }
-            } else {
-                log.info("no difference found mirror_diff.tar.gz not created");
+        // metadata of earlier archives is merged into the working-dir
+        generate_resources(log, work_dir.clone(), destination, &mirrored);
+
+        if archive {
+            if let Err(err) = rm_rf::remove(&dir) {
+                log.error(&format!("unable to remove staging dir {} {:?}", dir, err));
             }
         }
-    } else {
-        // this is diskToMirror
-        let destination = args.destination;
+    }
+}
+
+// write the cluster resources for the mirrored images or exit with the exit code for the error
+fn generate_resources(log: &Logging, dir: String, destination: String, mirrored: &Mirror) {
+    if let Err(err) = generate_cluster_resources(
+        log,
+        dir,
+        destination,
+        mirrored,
+        String::from(CLUSTER_RESOURCES_DIR),
+    ) {
+        log.error(&format!("{}", err));
+        std::process::exit(err.exit_code());
+    }
+}
 
-        release_disk_to_mirror(
+// log the collector report or exit with the exit code for the error
+fn check_result(log: &Logging, res: Result<Report, MirrorError>) {
+    match res {
+        Ok(report) => log.info(&format!(
+            "completed {} images, {} blobs ({} bytes)",
+            report.images, report.blobs, report.bytes
+        )),
+        Err(err) => {
+            log.error(&format!("{}", err));
+            std::process::exit(err.exit_code());
+        }
+    }
+}
+
+// log the number of synced files or exit with the exit code for the error
+fn check_sync(log: &Logging, res: Result<usize, MirrorError>) {
+    match res {
+        Ok(count) if count > 0 => log.info(&format!("synced {} files with workspace", count)),
+        Ok(_) => {}
+        Err(err) => {
+            log.error(&format!("{}", err));
+            std::process::exit(err.exit_code());
+        }
+    }
+}
+
+// run all collectors in mirrorToDisk mode
+async fn mirror_to_disk(
+    log: &Logging,
+    storage: &Arc<dyn Storage>,
+    isc_config: ImageSetConfig,
+    opts: &MirrorOptions,
+    state: &mut RunState,
+) {
+    // the blobs are written through the workspace storage
+    let reg_con = RegistryClient::new(storage.clone());
+    let scheduler = RegistryScheduler::new(
+        opts.scheduler.clone(),
+        &isc_config.registries,
+        opts.get_source_mirrors(&isc_config),
+        TokenCache::new(),
+    );
+    let skip = opts.skip;
+    let architectures = isc_config.mirror.get_architectures();
+    // check for release image
+    if isc_config.mirror.release.is_some() && !skip.release() {
+        let res = release_mirror_to_disk(
             reg_con.clone(),
+            &scheduler,
             log,
-            String::from("./working-dir/"),
-            destination.clone(),
+            storage.as_ref(),
+            opts.skip_manifest_check,
+            state,
+            &architectures,
             isc_config.mirror.release.unwrap(),
         )
         .await;
-
-        operator_disk_to_mirror(
+        check_result(log, res);
+    }
+    // check for operators
+    if isc_config.mirror.operators.is_some() && !skip.operators() {
+        let res = operator_mirror_to_disk(
             reg_con.clone(),
+            &scheduler,
             log,
-            String::from("./working-dir/"),
-            destination.clone(),
+            storage.as_ref(),
+            opts.skip_gen,
+            state,
+            &architectures,
             isc_config.mirror.operators.unwrap(),
         )
         .await;
+        check_result(log, res);
+    }
+
+    // check for additional images
+    if isc_config.mirror.additional_images.is_some() && !skip.additional() {
+        let res = additional_mirror_to_disk(
+            reg_con.clone(),
+            &scheduler,
+            log,
+            storage.as_ref(),
+            state,
+            &architectures,
+            isc_config.mirror.additional_images.unwrap(),
+        )
+        .await;
+        check_result(log, res);
+    }
+}
+
+// run all collectors without downloading the image blobs (mirrorToMirror and dry-run)
+// only the metadata (manifests, release payloads and catalogs) is stored in the working-dir
+async fn mirror_metadata(
+    log: &Logging,
+    storage: &Arc<dyn Storage>,
+    isc_config: ImageSetConfig,
+    opts: &MirrorOptions,
+) {
+    check_sync(log, sync_from_storage(log, storage, false).await);
+    let mut state = RunState {
+        metadata_only: true,
+        ..RunState::default()
+    };
+    mirror_to_disk(log, storage, isc_config, opts, &mut state).await;
+    check_sync(log, sync_to_storage(log, storage).await);
+}
+
+// read and parse the imagesetconfig or exit with the exit code for the error
+fn load_isc(log: &Logging, cfg: String) -> (String, ImageSetConfig) {
+    let config = match load_config(cfg) {
+        Ok(val) => val,
+        Err(err) => {
+            log.error(&format!("{}", err));
+            std::process::exit(err.exit_code());
+        }
+    };
+    let isc_config = match parse_yaml_config(config.clone()) {
+        Ok(val) => val,
+        Err(err) => {
+            log.error(&format!("{}", err));
+            std::process::exit(err.exit_code());
+        }
+    };
+    (config, isc_config)
+}
+
+// remove the images of a DeleteImageSetConfiguration from working-dir and optionally
+// from the destination registry
+async fn delete(log: &Logging, cfg: String, storage: &dyn Storage, destination: Option<String>) {
+    // with blobs, so the keys removed locally can be removed from a remote workspace
+    check_sync(log, sync_from_storage(log, storage, true).await);
+    let (_, delete_config) = load_isc(log, cfg);
+    if let Err(err) =
+        delete_images(log, storage.local_dir(), &delete_config.mirror, destination).await
+    {
+        log.error(&format!("{}", err));
+        std::process::exit(err.exit_code());
+    }
+    check_sync(log, prune_storage(log, storage).await);
+}
+
+// sweep the blobs not referenced by any manifest from the blobs-store
+async fn gc(log: &Logging, storage: &dyn Storage, dry_run: bool) {
+    check_sync(log, sync_from_storage(log, storage, true).await);
+    if let Err(err) = collect_garbage(log, storage.local_dir(), dry_run) {
+        log.error(&format!("{}", err));
+        std::process::exit(err.exit_code());
+    }
+    if !dry_run {
+        check_sync(log, prune_storage(log, storage).await);
+    }
+}
+
+// verify all blobs in working-dir, with repair the invalid ones are fetched again
+async fn verify(
+    log: &Logging,
+    cfg: String,
+    storage: &Arc<dyn Storage>,
+    repair: bool,
+    opts: &MirrorOptions,
+) {
+    check_sync(log, sync_from_storage(log, storage, true).await);
+    let dir = storage.local_dir();
+    let blobs_dir = dir.clone() + "blobs-store/";
+    let report = match verify_working_dir(log, dir.clone(), blobs_dir.clone()) {
+        Ok(val) => val,
+        Err(err) => {
+            log.error(&format!("{}", err));
+            std::process::exit(err.exit_code());
+        }
+    };
+    log.info(&format!(
+        "verified {} manifests: {} valid, {} missing, {} truncated, {} corrupt blobs",
+        report.manifests,
+        report.valid,
+        report.missing.len(),
+        report.truncated.len(),
+        report.corrupt.len()
+    ));
+    if report.is_clean() {
+        return;
+    }
+    if !repair {
+        std::process::exit(exitcode::DATAERR);
+    }
+
+    if let Err(err) = remove_invalid_blobs(log, blobs_dir.clone(), &report) {
+        log.error(&format!("{}", err));
+        std::process::exit(err.exit_code());
+    }
+    // a remote workspace still has the invalid blobs, the collectors would skip them
+    if dir != storage.root() {
+        for digest in report.truncated.iter().chain(report.corrupt.iter()) {
+            if let Err(err) = storage.remove(&get_blob_key(digest)).await {
+                log.error(&format!("{}", err));
+                std::process::exit(err.exit_code());
+            }
+        }
+    }
+    // walk every image again (in-memory journal) so the collectors re-fetch the removed blobs
+    let (_, isc_config) = load_isc(log, cfg);
+    let mut state = RunState::default();
+    mirror_to_disk(log, storage, isc_config, opts, &mut state).await;
+
+    match verify_working_dir(log, dir.clone(), blobs_dir.clone()) {
+        Ok(report) if report.is_clean() => log.info("repair completed, all blobs are valid"),
+        Ok(_) => {
+            log.error("repair completed, but invalid blobs remain");
+            std::process::exit(exitcode::DATAERR);
+        }
+        Err(err) => {
+            log.error(&format!("{}", err));
+            std::process::exit(err.exit_code());
+        }
+    }
+
+    check_sync(log, sync_to_storage(log, storage).await);
+}
+
+// unpack a mirror-diff archive, check it against the config and merge it into working-dir
+// returns the imagesetconfig embedded in the archive
+async fn from_archive(
+    log: &Logging,
+    from: String,
+    cfg: String,
+    storage: &dyn Storage,
+) -> (String, ImageSetConfig) {
+    check_sync(log, sync_from_storage(log, storage, true).await);
+    let tar_file = from.trim_start_matches("archive://").to_string();
+    let res = rm_rf::ensure_removed(ARCHIVE_STAGING_DIR)
+        .map_err(|err| MirrorError::Io(format!("{:?}", err)))
+        .and_then(|_| unpack_diff_tar(log, tar_file, String::from(ARCHIVE_STAGING_DIR)))
+        .and_then(|archive_config| {
+            if !cfg.is_empty() {
+                check_archive_config(archive_config.clone(), load_config(cfg)?)?;
+            }
+            merge_working_dir(log, String::from(ARCHIVE_STAGING_DIR), storage.local_dir())?;
+            fill_staging_blobs(log, String::from(ARCHIVE_STAGING_DIR), storage.local_dir())?;
+            Ok(archive_config)
+        })
+        .and_then(|archive_config| {
+            let isc_config = parse_yaml_config(archive_config.clone())?;
+            Ok((archive_config, isc_config))
+        });
+    let res = match res {
+        Ok(val) => val,
+        Err(err) => {
+            log.error(&format!("{}", err));
+            std::process::exit(err.exit_code());
+        }
+    };
+    check_sync(log, sync_to_storage(log, storage).await);
+    res
+}
+
+// create a diff archive with every manifest and blob not shipped in a previous archive
+// with a date the archives created on or after that date are ignored
+async fn create_diff(
+    log: &Logging,
+    config: String,
+    storage: &dyn Storage,
+    date: String,
+    archive_size: u64,
+) {
+    let dir = storage.local_dir();
+    let res: Result<(), MirrorError> = async {
+        let mut history = History::load(dir.clone())?;
+        let before = match date.is_empty() {
+            true => None,
+            false => Some(parse_history_date(date)?),
+        };
+        let shipped = history.get_shipped(before);
+        let delta = get_diff_delta(log, dir.clone(), &shipped)?;
+        log.mid(&format!("difference {:#?}", delta.dirs));
+        if delta.dirs.is_empty() {
+            log.info("no difference found mirror-diff.tar.gz not created");
+            return Ok(());
+        }
+        match archive_size {
+            0 => log.info("creating mirror-diff.tar.gz"),
+            _ => log.info(&format!(
+                "creating mirror-diff volumes of at most {} bytes",
+                archive_size
+            )),
+        }
+        let (files, blobs) = create_diff_tar(
+            log,
+            String::from("mirror-diff.tar.gz"),
+            storage,
+            delta.dirs.iter().collect(),
+            config,
+            archive_size,
+            &shipped,
+        )
+        .await?;
+        for file in files.iter() {
+            log.info(&format!("{} successfully created", file));
+        }
+        history.add(files, delta.manifests, blobs)?;
+        Ok(())
+    }
+    .await;
+    if let Err(err) = res {
+        log.error(&format!("error creating diff tar {}", err));
+        std::process::exit(err.exit_code());
     }
 }
@@ -0,0 +1,341 @@
+use custom_logger::*;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::api::schema::Report;
+use crate::cluster::resources::{get_manifest_digests, get_mirrored_images};
+use crate::config::load::Mirror;
+use crate::error::handler::MirrorError;
+use crate::verify::blobs::get_blob_path;
+
+const LAYOUT_VERSION: &str = "1.0.0";
+const INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+// skopeo and oras resolve images by ref.name, podman and containerd by image.name
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+const IMAGE_NAME_ANNOTATION: &str = "io.containerd.image.name";
+
+// oci-layout file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageLayout {
+    #[serde(rename = "imageLayoutVersion")]
+    pub image_layout_version: String,
+}
+
+// index.json file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageIndex {
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: i64,
+
+    #[serde(rename = "mediaType")]
+    pub media_type: String,
+
+    #[serde(rename = "manifests")]
+    pub manifests: Vec<Descriptor>,
+}
+
+impl Default for ImageIndex {
+    fn default() -> ImageIndex {
+        ImageIndex {
+            schema_version: 2,
+            media_type: String::from(INDEX_MEDIA_TYPE),
+            manifests: vec![],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Descriptor {
+    #[serde(rename = "mediaType")]
+    pub media_type: String,
+
+    #[serde(rename = "digest")]
+    pub digest: String,
+
+    #[serde(rename = "size")]
+    pub size: i64,
+
+    #[serde(rename = "annotations", skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
+}
+
+// OCI image layout (oci-layout, index.json and blobs/sha256) i.e. oci:///data/mirror
+#[derive(Debug, Clone)]
+pub struct OciLayout {
+    pub dir: String,
+    pub index: ImageIndex,
+    pub report: Report,
+}
+
+impl OciLayout {
+    // create the layout or open an existing one, images already in index.json are kept
+    pub fn open(dir: &str) -> Result<OciLayout, MirrorError> {
+        let dir = dir.trim_end_matches("/").to_string() + "/";
+        fs::create_dir_all(dir.clone() + "blobs/sha256")?;
+        let layout = ImageLayout {
+            image_layout_version: String::from(LAYOUT_VERSION),
+        };
+        fs::write(dir.clone() + "oci-layout", serde_json::to_string(&layout)?)?;
+        let index_file = dir.clone() + "index.json";
+        let mut index = ImageIndex::default();
+        if Path::new(&index_file).exists() {
+            index = serde_json::from_str(&fs::read_to_string(&index_file)?)?;
+        }
+        Ok(OciLayout {
+            dir,
+            index,
+            report: Report::default(),
+        })
+    }
+
+    fn get_blob_file(&self, digest: &str) -> String {
+        self.dir.clone() + "blobs/sha256/" + digest.trim_start_matches("sha256:")
+    }
+
+    // write a manifest or index as blob, returns the digest and size
+    fn write_blob(&self, data: &[u8]) -> Result<(String, i64), MirrorError> {
+        let digest = String::from("sha256:") + &hex::encode(Sha256::digest(data));
+        fs::write(self.get_blob_file(&digest), data)?;
+        Ok((digest, data.len() as i64))
+    }
+
+    // copy a config or layer blob from the blobs-store
+    fn copy_blob(&mut self, blobs_dir: &str, digest: &str) -> Result<(), MirrorError> {
+        let to = self.get_blob_file(digest);
+        if Path::new(&to).exists() {
+            return Ok(());
+        }
+        let from = get_blob_path(blobs_dir, digest);
+        if !Path::new(&from).exists() {
+            return Err(MirrorError::Io(format!(
+                "blob {} not found in {}",
+                digest, blobs_dir
+            )));
+        }
+        self.report.bytes += fs::copy(&from, &to)? as i64;
+        self.report.blobs += 1;
+        Ok(())
+    }
+
+    // add a manifest with its blobs, for an index the child manifests found in manifests
+    // (digest -> file) are added and the children of architectures not mirrored are dropped
+    pub fn add_manifest(
+        &mut self,
+        blobs_dir: &str,
+        data: &str,
+        manifests: &HashMap<String, String>,
+    ) -> Result<Descriptor, MirrorError> {
+        let mut value: Value = serde_json::from_str(data)?;
+        let mut content = data.to_string();
+        let children = value["manifests"].as_array().cloned();
+        let media_type = match children {
+            Some(children) => {
+                let mut kept = vec![];
+                for child in children.iter() {
+                    let digest = child["digest"].as_str().unwrap_or_default();
+                    if let Some(file) = manifests.get(digest) {
+                        self.add_manifest(blobs_dir, &fs::read_to_string(file)?, manifests)?;
+                        kept.push(child.clone());
+                    }
+                }
+                if kept.is_empty() {
+                    return Err(MirrorError::ManifestParse(String::from(
+                        "manifest list without mirrored manifests",
+                    )));
+                }
+                // the original digest is kept when all architectures were mirrored
+                if kept.len() != children.len() {
+                    value["manifests"] = Value::Array(kept);
+                    content = serde_json::to_string(&value)?;
+                }
+                value["mediaType"].as_str().unwrap_or(INDEX_MEDIA_TYPE)
+            }
+            None => {
+                let mut digests = vec![];
+                if let Some(digest) = value["config"]["digest"].as_str() {
+                    digests.push(digest.to_string());
+                }
+                for layer in value["layers"].as_array().cloned().unwrap_or_default() {
+                    if let Some(digest) = layer["digest"].as_str() {
+                        digests.push(digest.to_string());
+                    }
+                }
+                for digest in digests.iter() {
+                    self.copy_blob(blobs_dir, digest)?;
+                }
+                value["mediaType"].as_str().unwrap_or(MANIFEST_MEDIA_TYPE)
+            }
+        }
+        .to_string();
+        let (digest, size) = self.write_blob(content.as_bytes())?;
+        Ok(Descriptor {
+            media_type,
+            digest,
+            size,
+            annotations: None,
+        })
+    }
+
+    // reference the image in index.json with its original reference
+    // an entry with the same reference from an earlier run is replaced
+    pub fn add_image(&mut self, reference: &str, desc: Descriptor) {
+        let mut desc = desc;
+        desc.annotations = Some(BTreeMap::from([
+            (String::from(REF_NAME_ANNOTATION), reference.to_string()),
+            (String::from(IMAGE_NAME_ANNOTATION), reference.to_string()),
+        ]));
+        self.index.manifests.retain(|m| {
+            m.annotations
+                .as_ref()
+                .and_then(|a| a.get(REF_NAME_ANNOTATION))
+                .map_or(true, |r| r != reference)
+        });
+        self.index.manifests.push(desc);
+        self.report.images += 1;
+    }
+
+    pub fn save(&self) -> Result<(), MirrorError> {
+        fs::write(
+            self.dir.clone() + "index.json",
+            serde_json::to_string_pretty(&self.index)?,
+        )?;
+        Ok(())
+    }
+}
+
+// write the mirrored images in the working-dir to the OCI image layout of the destination
+pub async fn write_oci_layout(
+    log: &Logging,
+    dir: String,
+    destination: String,
+    mirror: &Mirror,
+) -> Result<Report, MirrorError> {
+    log.hi("oci layout mode: diskToMirror");
+    let manifests = get_manifest_digests(&dir)?;
+    let images = get_mirrored_images(log, dir.clone(), mirror, &manifests).await?;
+    let destination = destination.trim_start_matches("oci://");
+    let mut layout = OciLayout::open(destination)?;
+    let blobs_dir = dir.clone() + "blobs-store/";
+    for (image, mi) in images.iter() {
+        log.debug(&format!("adding {} from {}", image, mi.file));
+        let desc = layout
+            .add_manifest(&blobs_dir, &fs::read_to_string(&mi.file)?, &manifests)
+            .map_err(|err| match err {
+                MirrorError::ManifestParse(msg) => {
+                    MirrorError::ManifestParse(format!("{} {}", image, msg))
+                }
+                err => err,
+            })?;
+        layout.add_image(image, desc);
+    }
+    layout.save()?;
+    log.info(&format!("oci image layout written to {}", layout.dir));
+    Ok(layout.report)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use crate::additional::collector::get_additional_manifest_json_dir;
+    use crate::config::load::Image;
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    // write content to the blobs-store, returns the digest
+    fn write_test_blob(blobs_dir: &str, data: &str) -> String {
+        let digest = String::from("sha256:") + &hex::encode(Sha256::digest(data.as_bytes()));
+        let path = get_blob_path(blobs_dir, &digest);
+        fs::create_dir_all(Path::new(&path).parent().unwrap()).expect("should create blob dir");
+        fs::write(&path, data).expect("should write blob");
+        digest
+    }
+
+    #[test]
+    fn write_oci_layout_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let dir = String::from("./test-artifacts/oci-test/working-dir/");
+        let destination = String::from("oci://./test-artifacts/oci-test/layout");
+        let config = write_test_blob(&(dir.clone() + "blobs-store/"), "config");
+        let layer = write_test_blob(&(dir.clone() + "blobs-store/"), "layer");
+        let manifest = format!(
+            r#"{{"schemaVersion": 2, "mediaType": "application/vnd.oci.image.manifest.v1+json", "config": {{"mediaType": "application/vnd.oci.image.config.v1+json", "digest": "{}", "size": 6}}, "layers": [{{"mediaType": "application/vnd.oci.image.layer.v1.tar+gzip", "digest": "{}", "size": 5}}]}}"#,
+            config, layer
+        );
+        let manifest_digest = String::from("sha256:") + &hex::encode(Sha256::digest(&manifest));
+        // the arm64 manifest was not mirrored
+        let list = format!(
+            r#"{{"schemaVersion": 2, "mediaType": "application/vnd.oci.image.index.v1+json", "manifests": [{{"mediaType": "application/vnd.oci.image.manifest.v1+json", "digest": "{}", "size": {}, "platform": {{"architecture": "amd64", "os": "linux"}}}}, {{"mediaType": "application/vnd.oci.image.manifest.v1+json", "digest": "sha256:1234", "size": 10, "platform": {{"architecture": "arm64", "os": "linux"}}}}]}}"#,
+            manifest_digest,
+            manifest.len()
+        );
+        let add_dir = get_additional_manifest_json_dir(dir.clone(), "ns", "name", "v1");
+        fs::create_dir_all(&add_dir).expect("should create test dir");
+        fs::write(add_dir.clone() + "/manifest-list.json", &list).expect("should write list");
+        fs::write(add_dir.clone() + "/manifest-amd64.json", &manifest)
+            .expect("should write manifest");
+
+        let mirror = Mirror {
+            release: None,
+            operators: None,
+            additional_images: Some(vec![
+                Image {
+                    name: String::from("quay.io/ns/name:v1"),
+                },
+                Image {
+                    name: String::from("quay.io/ns/other@") + &manifest_digest,
+                },
+            ]),
+            platform: None,
+            destination_template: None,
+        };
+        let res = aw!(write_oci_layout(
+            log,
+            dir.clone(),
+            destination.clone(),
+            &mirror
+        ));
+        assert_eq!(
+            res.unwrap(),
+            Report {
+                images: 2,
+                blobs: 2,
+                bytes: 11,
+            }
+        );
+
+        let layout = OciLayout::open("./test-artifacts/oci-test/layout").unwrap();
+        assert!(Path::new(&layout.get_blob_file(&layer)).exists());
+        assert!(Path::new(&layout.get_blob_file(&manifest_digest)).exists());
+        assert_eq!(layout.index.manifests.len(), 2);
+        let index = &layout.index.manifests[0];
+        assert_eq!(index.media_type, INDEX_MEDIA_TYPE);
+        assert_eq!(
+            index.annotations.as_ref().unwrap()[REF_NAME_ANNOTATION],
+            "quay.io/ns/name:v1"
+        );
+        let filtered: Value =
+            serde_json::from_str(&fs::read_to_string(layout.get_blob_file(&index.digest)).unwrap())
+                .unwrap();
+        assert_eq!(filtered["manifests"].as_array().unwrap().len(), 1);
+        assert_eq!(layout.index.manifests[1].digest, manifest_digest);
+
+        // a second run replaces the entries of the same images
+        let res = aw!(write_oci_layout(log, dir, destination, &mirror));
+        assert_eq!(res.unwrap().blobs, 0);
+        let layout = OciLayout::open("./test-artifacts/oci-test/layout").unwrap();
+        assert_eq!(layout.index.manifests.len(), 2);
+        rm_rf::remove("./test-artifacts/oci-test").expect("should delete test dir");
+    }
+}
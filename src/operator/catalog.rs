@@ -0,0 +1,471 @@
+use custom_logger::*;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use mirror_catalog_index::{get_blobs_url, get_manifest_json_file, ImageReference};
+use mirror_copy::{FsLayer, RegistryInterface};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::handler::{registry_error, MirrorError};
+use crate::scheduler::registry::RegistryScheduler;
+use crate::verify::blobs::get_blob_path;
+
+const OCI_LAYER: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+const DOCKER_LAYER: &str = "application/vnd.docker.image.rootfs.diff.tar.gzip";
+
+// get the directory of the filtered catalog i.e. working-dir/redhat-operator-index/v4.15/catalog
+pub fn get_catalog_dir(dir: String, name: &str, version: &str) -> String {
+    dir.trim_end_matches("/").to_string() + "/" + name + "/" + version + "/catalog"
+}
+
+// trim the declarative config of a package to the mirrored bundles
+// channels without mirrored bundles are dropped, the default channel is updated if needed
+pub fn filter_declarative_config(
+    log: &Logging,
+    config_dir: String,
+    bundles: &[String],
+) -> Result<Vec<Value>, MirrorError> {
+    let mut package = None;
+    let mut channels = vec![];
+    let mut others = vec![];
+    for entry in fs::read_dir(&config_dir)? {
+        let path = entry?.path();
+        if !path.is_file() || !path.display().to_string().ends_with(".json") {
+            continue;
+        }
+        let data = fs::read_to_string(&path)?;
+        for obj in serde_json::Deserializer::from_str(&data).into_iter::<Value>() {
+            let mut obj = obj?;
+            match obj["schema"].as_str().unwrap_or_default() {
+                "olm.package" => package = Some(obj),
+                "olm.channel" => {
+                    if let Some(entries) = obj["entries"].as_array_mut() {
+                        entries.retain(|e| {
+                            bundles.contains(&e["name"].as_str().unwrap_or_default().to_string())
+                        });
+                    }
+                    if obj["entries"].as_array().map_or(false, |e| !e.is_empty()) {
+                        channels.push(obj);
+                    }
+                }
+                "olm.bundle" => {
+                    if bundles.contains(&obj["name"].as_str().unwrap_or_default().to_string()) {
+                        others.push(obj);
+                    }
+                }
+                _ => others.push(obj),
+            }
+        }
+    }
+    let mut package = package.ok_or_else(|| {
+        MirrorError::ManifestParse(format!("no olm.package found in {}", config_dir))
+    })?;
+    let names: Vec<&str> = channels
+        .iter()
+        .map(|c| c["name"].as_str().unwrap_or_default())
+        .collect();
+    let default_channel = package["defaultChannel"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    if !names.contains(&default_channel.as_str()) {
+        if let Some(name) = names.first() {
+            log.debug(&format!(
+                "default channel {} not mirrored, using {}",
+                default_channel, name
+            ));
+            package["defaultChannel"] = json!(name);
+        }
+    }
+    let mut result = vec![package];
+    result.append(&mut channels);
+    result.append(&mut others);
+    Ok(result)
+}
+
+// the related images of each bundle in the declarative config of a package
+pub fn get_bundle_images(
+    config_dir: &str,
+) -> Result<BTreeMap<String, BTreeSet<String>>, MirrorError> {
+    if !Path::new(config_dir).is_dir() {
+        return Err(MirrorError::Config(format!(
+            "no declarative config found in {}",
+            config_dir
+        )));
+    }
+    let mut bundles = BTreeMap::new();
+    for entry in fs::read_dir(config_dir)? {
+        let path = entry?.path();
+        if !path.is_file() || !path.display().to_string().ends_with(".json") {
+            continue;
+        }
+        let data = fs::read_to_string(&path)?;
+        for obj in serde_json::Deserializer::from_str(&data).into_iter::<Value>() {
+            let obj = obj?;
+            if obj["schema"].as_str().unwrap_or_default() != "olm.bundle" {
+                continue;
+            }
+            let images: BTreeSet<String> = obj["relatedImages"]
+                .as_array()
+                .iter()
+                .copied()
+                .flatten()
+                .filter_map(|ri| ri["image"].as_str())
+                .filter(|image| !image.is_empty())
+                .map(|image| image.to_string())
+                .collect();
+            bundles.insert(obj["name"].as_str().unwrap_or_default().to_string(), images);
+        }
+    }
+    Ok(bundles)
+}
+
+// create a tar.gz layer that replaces configs/ (and the opm cache) with the filtered configs
+// returns the compressed layer and the digest of the uncompressed tar (diff_id)
+pub fn build_catalog_layer(
+    configs: &BTreeMap<String, Vec<Value>>,
+) -> Result<(Vec<u8>, String), MirrorError> {
+    let mut tar = tar::Builder::new(Vec::new());
+    // opaque whiteouts hide the upstream contents of these directories
+    for dir in ["configs", "tmp/cache"] {
+        append_entry(&mut tar, &(dir.to_string() + "/"), None)?;
+        append_entry(&mut tar, &(dir.to_string() + "/.wh..wh..opq"), Some(&[]))?;
+    }
+    for (pkg, objs) in configs.iter() {
+        let mut data = String::new();
+        for obj in objs.iter() {
+            data.push_str(&serde_json::to_string(obj)?);
+            data.push('\n');
+        }
+        append_entry(&mut tar, &format!("configs/{}/", pkg), None)?;
+        append_entry(
+            &mut tar,
+            &format!("configs/{}/catalog.json", pkg),
+            Some(data.as_bytes()),
+        )?;
+    }
+    let raw = tar.into_inner()?;
+    let diff_id = String::from("sha256:") + &hex::encode(Sha256::digest(&raw));
+    let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+    enc.write_all(&raw)?;
+    Ok((enc.finish()?, diff_id))
+}
+
+// entries get fixed metadata so the layer digest is reproducible
+fn append_entry(
+    tar: &mut tar::Builder<Vec<u8>>,
+    path: &str,
+    data: Option<&[u8]>,
+) -> Result<(), MirrorError> {
+    let mut header = tar::Header::new_gnu();
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    match data {
+        Some(data) => {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            tar.append_data(&mut header, path, data)?;
+        }
+        None => {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            header.set_cksum();
+            tar.append_data(&mut header, path, std::io::empty())?;
+        }
+    }
+    Ok(())
+}
+
+// write a blob into the blobs-store and return its digest
+fn write_blob(blobs_dir: &str, data: &[u8]) -> Result<String, MirrorError> {
+    let digest = String::from("sha256:") + &hex::encode(Sha256::digest(data));
+    let path = get_blob_path(blobs_dir, &digest);
+    if let Some(parent) = Path::new(&path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, data)?;
+    Ok(digest)
+}
+
+// build a schema 2 manifest and image config from a schema 1 manifest
+// the diff_ids are computed from the (already downloaded) layers in the blobs-store
+fn convert_schema1(blobs_dir: &str, manifest: &Value) -> Result<(Value, Value), MirrorError> {
+    let fs_layers = manifest["fsLayers"].as_array().cloned().unwrap_or_default();
+    let history = manifest["history"].as_array().cloned().unwrap_or_default();
+    if fs_layers.is_empty() || fs_layers.len() != history.len() {
+        return Err(MirrorError::ManifestParse(String::from(
+            "schema 1 manifest with unexpected fsLayers and history",
+        )));
+    }
+    let mut layers = vec![];
+    let mut diff_ids = vec![];
+    let mut entries = vec![];
+    // schema 1 lists the top layer first
+    for (fs_layer, hist) in fs_layers.iter().zip(history.iter()).rev() {
+        let v1: Value = serde_json::from_str(hist["v1Compatibility"].as_str().unwrap_or_default())?;
+        let throwaway = v1["throwaway"].as_bool().unwrap_or(false);
+        let created_by = v1["container_config"]["Cmd"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|c| c.as_str().map(|c| c.to_string()))
+            .collect::<Vec<String>>()
+            .join(" ");
+        entries.push(json!({
+            "created": v1["created"],
+            "created_by": created_by,
+            "empty_layer": throwaway,
+        }));
+        if throwaway {
+            continue;
+        }
+        let digest = fs_layer["blobSum"].as_str().unwrap_or_default().to_string();
+        let path = get_blob_path(blobs_dir, &digest);
+        let size = fs::metadata(&path)?.len();
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut GzDecoder::new(File::open(&path)?), &mut hasher)?;
+        diff_ids.push(json!(
+            String::from("sha256:") + &hex::encode(hasher.finalize())
+        ));
+        layers.push(json!({
+            "mediaType": DOCKER_LAYER,
+            "size": size,
+            "digest": digest,
+        }));
+    }
+    let top: Value =
+        serde_json::from_str(history[0]["v1Compatibility"].as_str().unwrap_or_default())?;
+    let config = json!({
+        "architecture": top["architecture"],
+        "os": top["os"],
+        "created": top["created"],
+        "config": top["config"],
+        "rootfs": {"type": "layers", "diff_ids": diff_ids},
+        "history": entries,
+    });
+    let manifest = json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+        "config": {
+            "mediaType": "application/vnd.docker.container.image.v1+json",
+            "size": 0,
+            "digest": "",
+        },
+        "layers": layers,
+    });
+    Ok((manifest, config))
+}
+
+// build the filtered catalog image from the upstream index image in the working-dir
+// the image is written to the catalog dir (manifest.json) with its blobs in the blobs-store
+pub async fn catalog_mirror_to_disk<T: RegistryInterface>(
+    reg_con: &T,
+    scheduler: &RegistryScheduler,
+    log: &Logging,
+    dir: String,
+    ir: &ImageReference,
+    configs: BTreeMap<String, Vec<Value>>,
+) -> Result<String, MirrorError> {
+    let index_manifest = get_manifest_json_file(dir.clone(), ir.name.clone(), ir.version.clone());
+    let catalog_dir = get_catalog_dir(dir.clone(), &ir.name, &ir.version);
+    let blobs_dir = dir.clone() + "/blobs-store/";
+    let blobs_url = get_blobs_url(ir.clone());
+    let data = fs::read_to_string(&index_manifest)?;
+    let manifest: Value = serde_json::from_str(&data)?;
+
+    // the index manifest can be schema 1 (no config), convert it to schema 2
+    let (mut manifest, mut config) = if manifest["schemaVersion"] == 1 {
+        convert_schema1(&blobs_dir, &manifest)?
+    } else {
+        let config_digest = manifest["config"]["digest"]
+            .as_str()
+            .ok_or_else(|| {
+                MirrorError::ManifestParse(format!("no config found in {}", index_manifest))
+            })?
+            .to_string();
+        // the index image config is needed to add the new layer
+        let config_path = get_blob_path(&blobs_dir, &config_digest);
+        if !Path::new(&config_path).exists() {
+            let layer = FsLayer {
+                blob_sum: config_digest.clone(),
+                original_ref: None,
+                size: manifest["config"]["size"].as_i64(),
+            };
+            scheduler
+                .run(log, &blobs_url, |url, token| {
+                    reg_con.get_blobs(log, blobs_dir.clone(), url, token, vec![layer.clone()])
+                })
+                .await
+                .map_err(|err| registry_error(&blobs_url, err))?;
+        }
+        let config: Value = serde_json::from_str(&fs::read_to_string(&config_path)?)?;
+        (manifest, config)
+    };
+
+    let (layer, diff_id) = build_catalog_layer(&configs)?;
+    let layer_digest = write_blob(&blobs_dir, &layer)?;
+    match config["rootfs"]["diff_ids"].as_array_mut() {
+        Some(diff_ids) => diff_ids.push(json!(diff_id)),
+        None => {
+            return Err(MirrorError::ManifestParse(format!(
+                "no rootfs found in config for {}",
+                index_manifest
+            )))
+        }
+    }
+    if let Some(history) = config["history"].as_array_mut() {
+        history.push(json!({"created_by": "rust-image-mirror filtered catalog"}));
+    }
+    let config_data = serde_json::to_vec(&config)?;
+    let new_config_digest = write_blob(&blobs_dir, &config_data)?;
+
+    // the new layer uses the media type of the upstream layers
+    let is_oci = manifest["layers"][0]["mediaType"]
+        .as_str()
+        .map_or(false, |m| m.contains("oci"));
+    manifest["config"]["digest"] = json!(new_config_digest);
+    manifest["config"]["size"] = json!(config_data.len());
+    match manifest["layers"].as_array_mut() {
+        Some(layers) => layers.push(json!({
+            "mediaType": if is_oci { OCI_LAYER } else { DOCKER_LAYER },
+            "size": layer.len(),
+            "digest": layer_digest,
+        })),
+        None => {
+            return Err(MirrorError::ManifestParse(format!(
+                "no layers found in {}",
+                index_manifest
+            )))
+        }
+    }
+
+    fs::create_dir_all(&catalog_dir)?;
+    for (pkg, objs) in configs.iter() {
+        let pkg_dir = catalog_dir.clone() + "/configs/" + pkg;
+        fs::create_dir_all(&pkg_dir)?;
+        let data: Vec<String> = objs
+            .iter()
+            .map(|o| serde_json::to_string(o).unwrap_or_default())
+            .collect();
+        fs::write(pkg_dir + "/catalog.json", data.join("\n") + "\n")?;
+    }
+    fs::write(
+        catalog_dir.clone() + "/manifest.json",
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    log.info(&format!(
+        "filtered catalog with {} packages written to {}",
+        configs.len(),
+        catalog_dir
+    ));
+    Ok(layer_digest)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+
+    #[test]
+    fn filter_declarative_config_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let res = filter_declarative_config(
+            log,
+            String::from(
+                "test-artifacts/test-index-operator/v1.0/cache/b4385e/configs/some-operator",
+            ),
+            &[String::from("aws-load-balancer-operator.v0.2.0")],
+        )
+        .unwrap();
+        let schemas: Vec<&str> = res.iter().map(|o| o["schema"].as_str().unwrap()).collect();
+        assert_eq!(
+            schemas,
+            vec!["olm.package", "olm.channel", "olm.channel", "olm.bundle"]
+        );
+        // stable-v1 is not mirrored so the default channel moves
+        assert_eq!(res[0]["defaultChannel"], "stable-v0");
+        assert_eq!(res[3]["name"], "aws-load-balancer-operator.v0.2.0");
+    }
+
+    #[test]
+    fn get_bundle_images_pass() {
+        let res = get_bundle_images(
+            "test-artifacts/test-index-operator/v1.0/cache/b4385e/configs/some-operator",
+        )
+        .unwrap();
+        assert_eq!(res.len(), 3);
+        // the operator is listed twice in the related images
+        let images = &res["aws-load-balancer-operator.v1.0.0"];
+        assert_eq!(images.len(), 4);
+        assert!(images.contains("registry.redhat.io/openshift4/ose-kube-rbac-proxy@sha256:422e4fbe1ed81c79084f43a826dc0674510a7ff578e62b4ddda119ed3266d0b6"));
+
+        let res = get_bundle_images("test-artifacts/no-such-catalog/configs/some-operator");
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+    }
+
+    #[test]
+    fn convert_schema1_pass() {
+        let blobs_dir = "test-artifacts/catalog-test/blobs-store";
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(b"test layer").unwrap();
+        let digest = write_blob(blobs_dir, &enc.finish().unwrap()).unwrap();
+        let manifest = json!({
+            "schemaVersion": 1,
+            "fsLayers": [
+                {"blobSum": "sha256:a3ed95caeb02ffe68cdd9fd84406680ae93d633cb16422d00e8a7c22955b46d4"},
+                {"blobSum": digest},
+            ],
+            "history": [
+                {"v1Compatibility": "{\"created\": \"2023-10-05T21:50:32Z\", \"throwaway\": true, \"architecture\": \"amd64\", \"os\": \"linux\", \"config\": {\"Cmd\": [\"serve\", \"/configs\"]}, \"container_config\": {\"Cmd\": [\"/bin/sh -c #(nop) CMD serve\"]}}"},
+                {"v1Compatibility": "{\"created\": \"2023-10-05T21:50:31Z\", \"container_config\": {\"Cmd\": [\"/bin/sh -c #(nop) ADD dir in /configs\"]}}"},
+            ],
+        });
+        let (res_manifest, config) = convert_schema1(blobs_dir, &manifest).unwrap();
+        rm_rf::remove("test-artifacts/catalog-test").expect("should delete test dir");
+        // the throwaway layer is dropped
+        assert_eq!(res_manifest["layers"].as_array().unwrap().len(), 1);
+        assert_eq!(res_manifest["layers"][0]["digest"], json!(digest));
+        assert_eq!(
+            config["rootfs"]["diff_ids"][0],
+            json!(String::from("sha256:") + &hex::encode(Sha256::digest(b"test layer")))
+        );
+        assert_eq!(config["architecture"], "amd64");
+        assert_eq!(config["history"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn build_catalog_layer_pass() {
+        let mut configs = BTreeMap::new();
+        configs.insert(
+            String::from("some-operator"),
+            vec![json!({"schema": "olm.package", "name": "some-operator"})],
+        );
+        let (layer, diff_id) = build_catalog_layer(&configs).unwrap();
+        // the layer is reproducible
+        let (again, _) = build_catalog_layer(&configs).unwrap();
+        assert_eq!(layer, again);
+        assert!(diff_id.starts_with("sha256:"));
+
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(&layer[..]));
+        let entries: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert!(entries.contains(&String::from("configs/.wh..wh..opq")));
+        assert!(entries.contains(&String::from("configs/some-operator/catalog.json")));
+    }
+}
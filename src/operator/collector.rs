@@ -1,18 +1,30 @@
 use custom_logger::*;
 use futures::stream::FuturesUnordered;
 use futures::stream::StreamExt;
-use mirror_auth::*;
 use mirror_catalog::*;
 use mirror_catalog_index::*;
 use mirror_copy::*;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 use std::fs::DirBuilder;
 use std::os::unix::fs::DirBuilderExt;
 use std::path::Path;
 use walkdir::WalkDir;
 
+use crate::api::schema::Report;
+use crate::cluster::resources::{get_manifest_digests, get_mirror_repo};
+use crate::config::destination::DestinationTemplate;
 use crate::config::load::*;
+use crate::error::handler::{registry_error, MirrorError};
+use crate::journal::run_state::*;
+use crate::operator::catalog::*;
+use crate::operator::upgrade_graph::get_package_bundles;
+use crate::reference::parser::Reference;
+use crate::release::collector::{get_image_manifest_url, get_manifest_lists};
+use crate::scheduler::registry::RegistryScheduler;
+use crate::storage::Storage;
+use crate::stream::registry::{Repository, StreamCopy};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ManifestList {
@@ -23,89 +35,98 @@ pub struct ManifestList {
     pub media_type: String,
 }
 
-// used to add path and arch (platform) info for mirroring
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct MirrorManifest {
-    pub registry: String,
-    pub namespace: String,
-    pub name: String,
-    pub version: String,
-    pub component: String,
-    pub channel: String,
-    pub sub_component: String,
-    pub manifest_file: String,
+// docker manifest lists and oci image indexes both reference a manifest per architecture
+const MANIFEST_LIST_MEDIA_TYPES: [&str; 2] = [
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+    "application/vnd.oci.image.index.v1+json",
+];
+
+impl ManifestList {
+    pub fn is_list(&self) -> bool {
+        MANIFEST_LIST_MEDIA_TYPES.contains(&self.media_type.as_str())
+    }
 }
 
 // collect all operator images
 pub async fn operator_mirror_to_disk<T: RegistryInterface>(
     reg_con: T,
+    scheduler: &RegistryScheduler,
     log: &Logging,
-    dir: String,
+    storage: &dyn Storage,
     skip_gen: bool,
+    state: &mut RunState,
+    architectures: &[String],
     operators: Vec<Operator>,
-) {
+) -> Result<Report, MirrorError> {
     log.hi("operator collector mode: mirrorToDisk");
+    let dir = storage.local_dir();
 
     // parse the config - iterate through each catalog
-    let img_ref = parse_index(log, operators.clone());
+    let img_ref = parse_index(log, operators.clone())?;
     log.info(&format!("image refs {:#?}", img_ref));
     let mut futs = FuturesUnordered::new();
-    let batch_size = 8;
+    let batch_size = scheduler.max_in_flight();
+    let mut report = Report::default();
+    // the journal as it was loaded, its blobs are not fetched again
+    let recorded = state.clone();
 
     for ir in img_ref.iter() {
         let manifest_json =
             get_manifest_json_file(dir.clone(), ir.name.clone(), ir.version.clone());
         log.trace(&format!("manifest json file {}", manifest_json));
-        let token = get_token(log, ir.registry.clone()).await;
-        // use token to get manifest
         let manifest_url = get_image_manifest_url(ir.clone());
-        let manifest = reg_con
-            .get_manifest(manifest_url.clone(), token.clone())
+        let manifest = scheduler
+            .run(log, &manifest_url, |url, token| {
+                reg_con.get_manifest(url, token)
+            })
             .await
-            .unwrap();
+            .map_err(|err| registry_error(&manifest_url, err))?;
 
         // create the full path
-        let manifest_dir = manifest_json.split("manifest.json").nth(0).unwrap();
+        let manifest_dir = manifest_json.trim_end_matches("manifest.json");
         log.info(&format!("manifest directory {}", manifest_dir));
-        fs::create_dir_all(manifest_dir).expect("unable to create directory manifest directory");
+        fs::create_dir_all(manifest_dir)?;
         let manifest_exists = Path::new(&manifest_json).exists();
-        let res_manifest_in_mem = parse_json_manifest(manifest.clone()).unwrap();
+        let res_manifest_in_mem = parse_json_manifest(manifest.clone())
+            .map_err(|err| MirrorError::ManifestParse(format!("{} {}", manifest_url, err)))?;
         let working_dir_cache = get_cache_dir(dir.clone(), ir.name.clone(), ir.version.clone());
         let cache_exists = Path::new(&working_dir_cache).exists();
         let sub_dir = dir.clone() + "/blobs-store/";
         let mut exists = true;
         if manifest_exists {
-            let manifest_on_disk = fs::read_to_string(&manifest_json).unwrap();
-            let res_manifest_on_disk = parse_json_manifest(manifest_on_disk).unwrap();
+            let manifest_on_disk = fs::read_to_string(&manifest_json)?;
+            let res_manifest_on_disk = parse_json_manifest(manifest_on_disk)
+                .map_err(|err| MirrorError::ManifestParse(format!("{} {}", manifest_json, err)))?;
             if res_manifest_on_disk != res_manifest_in_mem || !cache_exists {
                 exists = false;
             }
+            // when resuming the index only counts once its checkpoint was recorded
+            if state.resume && !state.is_image_done(&manifest_url) {
+                exists = false;
+            }
         }
         if !exists || !manifest_exists {
             log.info("detected change in index manifest");
-            fs::write(manifest_json.clone(), manifest.clone())
-                .expect("unable to write (index) manifest.json file");
             let blobs_url = get_blobs_url(ir.clone());
             // use a concurrent process to get related blobs
-            let response = reg_con
+            let response = scheduler
                 .get_blobs(
+                    &reg_con,
                     log,
-                    sub_dir.clone(),
-                    blobs_url,
-                    token.clone(),
-                    res_manifest_in_mem.fs_layers.clone(),
+                    &sub_dir,
+                    &blobs_url,
+                    &res_manifest_in_mem.fs_layers,
                 )
-                .await;
+                .await?;
             log.info(&format!("completed image index download {:#?}", response));
             // detected a change so clean the dir contents
             if cache_exists {
-                rm_rf::remove(&working_dir_cache).expect("should delete current untarred cache");
+                rm_rf::remove(&working_dir_cache)
+                    .map_err(|err| MirrorError::Io(format!("{} {:?}", working_dir_cache, err)))?;
                 // re-create the cache directory
                 let mut builder = DirBuilder::new();
                 builder.mode(0o777);
-                builder
-                    .create(&working_dir_cache)
-                    .expect("unable to create directory");
+                builder.create(&working_dir_cache)?;
             }
             untar_layers(
                 log,
@@ -115,6 +136,16 @@ pub async fn operator_mirror_to_disk<T: RegistryInterface>(
             )
             .await;
             log.hi("completed untar of layers");
+            // only write the index manifest once the cache is complete
+            fs::write(manifest_json.clone(), manifest.clone())?;
+            state.checkpoint(
+                manifest_url.clone(),
+                res_manifest_in_mem
+                    .fs_layers
+                    .iter()
+                    .map(|l| l.blob_sum.clone())
+                    .collect(),
+            )?;
         }
 
         // find the directory 'configs'
@@ -126,54 +157,84 @@ pub async fn operator_mirror_to_disk<T: RegistryInterface>(
 
         if !skip_gen {
             // build and streamline all declarative configs
-            DeclarativeConfig::build_updated_configs(log, config_dir.clone() + &"/")
-                .expect("should build updated configs");
+            DeclarativeConfig::build_updated_configs(log, config_dir.clone() + &"/").map_err(
+                |err| {
+                    MirrorError::ManifestParse(format!("unable to build updated configs {:?}", err))
+                },
+            )?;
         }
 
         let mut blob_tracker: Vec<String> = vec![];
+        // declarative config of the mirrored bundles for the filtered catalog
+        let mut configs = BTreeMap::new();
 
+        // only the operators of the catalog ir was parsed from
         for operator in operators.iter() {
+            if !is_catalog(operator, ir)? {
+                continue;
+            }
             // iterate through all packages in imagesetconfig
-            for pkg in operator.packages.clone().unwrap() {
+            let packages = operator.packages.clone().ok_or_else(|| {
+                MirrorError::Config(format!("catalog {} has no packages", operator.catalog))
+            })?;
+            for pkg in packages {
                 let dc_map = DeclarativeConfig::get_declarativeconfig_map(
                     config_dir.clone() + &"/" + &pkg.name.clone() + &"/updated-configs/",
                 );
 
                 log.ex(&format!("operator {:#?}", pkg.name));
+                // explicit bundles or the bundles resolved from the upgrade graph
+                let bundles =
+                    get_package_bundles(log, config_dir.clone() + &"/" + &pkg.name, &pkg)?;
+                configs.insert(
+                    pkg.name.clone(),
+                    filter_declarative_config(
+                        log,
+                        config_dir.clone() + &"/" + &pkg.name,
+                        &bundles,
+                    )?,
+                );
                 // iterate for each bundle
-                for bundle in pkg.bundles.clone() {
-                    let key = bundle.name.clone() + &"=olm.bundle".to_string();
-                    let bundle = dc_map.get(&key).unwrap();
+                for name in bundles {
+                    let key = name.clone() + &"=olm.bundle".to_string();
+                    let bundle = dc_map.get(&key).ok_or_else(|| {
+                        MirrorError::Config(format!(
+                            "bundle {} not found in catalog for package {}",
+                            name, pkg.name
+                        ))
+                    })?;
                     log.debug(&format!("bundle from dc_map {:#?}", bundle));
                     // we can  get all related images
-                    let related_images = bundle.related_images.clone().unwrap();
+                    let related_images = bundle.related_images.clone().unwrap_or_default();
                     for ri in related_images.iter() {
-                        let ir = parse_url(log, ri.image.clone());
+                        let ir = parse_url(log, ri.image.clone())?;
                         let url = get_image_manifest_url(ir.clone());
                         log.info(&format!(
                             "  checking manifest {:#?}",
                             ir.namespace.clone() + "/" + &ir.name
                         ));
-                        let manifest = reg_con
-                            .get_manifest(url.clone(), token.clone())
-                            .await
-                            .unwrap();
-                        log.trace(&format!("manifest {:#?}", manifest));
                         let op_dir = get_operator_manifest_json_dir(
                             manifest_dir.to_string(),
-                            &(ir.namespace + "/" + &ir.name),
+                            &(ir.namespace.clone() + "/" + &ir.name),
                             &ir.version,
                             &pkg.name,
                         );
-                        fs::create_dir_all(op_dir.clone())
-                            .expect("should create full operator path");
-                        log.debug(&format!("operator manifest path {:#?}", op_dir));
-                        fs::write(op_dir.clone() + "/manifest.json", manifest.clone())
-                            .expect("unable to write file");
-                        let opm = parse_json_manifest_operator(manifest.clone());
-                        if opm.is_err() {
-                            log.error(&format!("unable to parse manifest {:#?}", opm));
+                        // skip images completed in a previous (interrupted) run
+                        if state.is_image_done(&ri.image)
+                            && (Path::new(&(op_dir.clone() + "/manifest.json")).exists()
+                                || Path::new(&(op_dir.clone() + "/manifest-list.json")).exists())
+                        {
+                            log.debug(&format!("checkpoint found for {}", ri.image));
+                            continue;
                         }
+                        let manifest = scheduler
+                            .run(log, &url, |url, token| reg_con.get_manifest(url, token))
+                            .await
+                            .map_err(|err| registry_error(&url, err))?;
+                        log.trace(&format!("manifest {:#?}", manifest));
+                        fs::create_dir_all(op_dir.clone())?;
+                        log.debug(&format!("operator manifest path {:#?}", op_dir));
+                        report.images += 1;
 
                         let manifest_list = parse_json_manifestlist(manifest.clone());
                         log.trace(&format!("manifest list {:#?}", manifest_list));
@@ -181,36 +242,57 @@ pub async fn operator_mirror_to_disk<T: RegistryInterface>(
                         if manifest_list.is_ok() {
                             let ml = manifest_list.unwrap().clone();
                             log.trace(&format!("manifest list detected {:#?}", ml));
-                            if ml.media_type
-                                == "application/vnd.docker.distribution.manifest.list.v2+json"
-                            {
-                                fs::write(op_dir.clone() + "/manifest-list.json", manifest.clone())
-                                    .expect("unable to write file");
+                            if ml.is_list() {
+                                fs::write(
+                                    op_dir.clone() + "/manifest-list.json",
+                                    manifest.clone(),
+                                )?;
                                 // look for the digest
                                 // loop through each manifest
                                 for mf in ml.manifests.iter() {
-                                    let sub_manifest_url = get_manifest_url_by_digest(
-                                        ri.image.clone(),
-                                        mf.digest.clone().unwrap(),
-                                    );
+                                    let digest = mf.digest.clone().ok_or_else(|| {
+                                        MirrorError::ManifestParse(format!(
+                                            "manifest list entry without digest for {}",
+                                            ri.image
+                                        ))
+                                    })?;
+                                    let platform = mf.platform.clone().ok_or_else(|| {
+                                        MirrorError::ManifestParse(format!(
+                                            "manifest list entry without platform for {}",
+                                            ri.image
+                                        ))
+                                    })?;
+                                    if !is_architecture_mirrored(
+                                        architectures,
+                                        &platform.architecture,
+                                    ) {
+                                        log.debug(&format!(
+                                            "skipping architecture {} for {}",
+                                            platform.architecture, ri.image
+                                        ));
+                                        continue;
+                                    }
+                                    let sub_manifest_url =
+                                        get_manifest_url_by_digest(ri.image.clone(), digest)?;
                                     log.trace(&format!(
                                         "sub manifest url {:#?}",
                                         sub_manifest_url.clone()
                                     ));
                                     // use the RegistryInterface to make the api call
-                                    let local_manifest = reg_con
-                                        .get_manifest(sub_manifest_url.clone(), token.clone())
+                                    let local_manifest = scheduler
+                                        .run(log, &sub_manifest_url, |url, token| {
+                                            reg_con.get_manifest(url, token)
+                                        })
                                         .await
-                                        .unwrap();
+                                        .map_err(|err| registry_error(&sub_manifest_url, err))?;
 
                                     fs::write(
                                         op_dir.clone()
                                             + "/manifest-"
-                                            + &mf.platform.clone().unwrap().architecture
+                                            + &platform.architecture
                                             + ".json",
                                         local_manifest.clone(),
-                                    )
-                                    .expect("unable to write file");
+                                    )?;
                                     log.trace(&format!(
                                         "local manifest (from sub manifest url) {:#?}",
                                         local_manifest.clone()
@@ -218,128 +300,128 @@ pub async fn operator_mirror_to_disk<T: RegistryInterface>(
                                     // convert op_manifest.layer to FsLayer and add it to the collection
                                     let op_manifest =
                                         parse_json_manifest_operator(local_manifest.clone())
-                                            .unwrap();
-                                    // originally used map(|layer| FsLayer ...)
-                                    // changed to ensure no duplicates included using for..in
-                                    for layer in op_manifest.layers.unwrap().iter() {
-                                        if !blob_tracker.contains(&layer.digest.clone()) {
-                                            let fslayer = FsLayer {
-                                                blob_sum: layer.digest.clone(),
-                                                original_ref: Some(ri.image.clone()),
-                                                size: Some(layer.size),
-                                            };
-                                            fslayers.insert(0, fslayer);
-                                            blob_tracker.insert(0, layer.digest.clone());
-                                        }
-                                    }
-                                    let config = op_manifest.config.unwrap();
-                                    if !blob_tracker.contains(&config.digest) {
-                                        let cfg = FsLayer {
-                                            blob_sum: config.digest.clone(),
-                                            original_ref: Some(ri.image.clone()),
-                                            size: Some(config.size),
-                                        };
-                                        fslayers.insert(0, cfg);
-                                        blob_tracker.insert(0, config.digest);
-                                    }
+                                            .map_err(|err| {
+                                                MirrorError::ManifestParse(format!(
+                                                    "{} {}",
+                                                    sub_manifest_url, err
+                                                ))
+                                            })?;
+                                    fslayers.append(&mut get_operator_fslayers(
+                                        op_manifest,
+                                        ri.image.clone(),
+                                        &mut blob_tracker,
+                                    )?);
                                 }
                             }
                         } else {
-                            fs::write(op_dir.clone() + "/manifest.json", manifest.clone())
-                                .expect("unable to write file");
+                            fs::write(op_dir.clone() + "/manifest.json", manifest.clone())?;
                             // now download each related images blobs
                             log.debug(&format!("manifest dir {:#?}", op_dir));
-                            let op_manifest =
-                                parse_json_manifest_operator(manifest.clone()).unwrap();
+                            let op_manifest = parse_json_manifest_operator(manifest.clone())
+                                .map_err(|err| {
+                                    MirrorError::ManifestParse(format!("{} {}", url, err))
+                                })?;
                             log.trace(&format!("op_manifest {:#?}", op_manifest));
-                            // convert op_manifest.layer to FsLayer
-                            // originally used map(|layer| FsLayer ...)
-                            // changed to ensure no duplicates included using for..in
-                            for layer in op_manifest.layers.unwrap().iter() {
-                                if !blob_tracker.contains(&layer.digest.clone()) {
-                                    let fslayer = FsLayer {
-                                        blob_sum: layer.digest.clone(),
-                                        original_ref: Some(ri.image.clone()),
-                                        size: Some(layer.size),
-                                    };
-                                    fslayers.insert(0, fslayer);
-                                    blob_tracker.insert(0, layer.digest.clone());
-                                }
-                            }
-                            // add configs
-                            let config = op_manifest.config.unwrap();
-                            let cfg = FsLayer {
-                                blob_sum: config.digest.clone(),
-                                original_ref: Some(ri.image.clone()),
-                                size: Some(config.size),
-                            };
-                            if !blob_tracker.contains(&config.digest) {
-                                fslayers.insert(0, cfg);
-                                blob_tracker.insert(0, config.digest);
-                            }
+                            fslayers.append(&mut get_operator_fslayers(
+                                op_manifest,
+                                ri.image.clone(),
+                                &mut blob_tracker,
+                            )?);
                         }
 
+                        report.blobs += fslayers.len();
+                        report.bytes += fslayers.iter().map(|l| l.size.unwrap_or(0)).sum::<i64>();
+                        if state.metadata_only {
+                            continue;
+                        }
                         let op_url = get_blobs_url_by_string(ri.image.clone());
                         // batch the calls
-                        futs.push(reg_con.get_blobs(
+                        futs.push(get_blobs_tracked(
+                            &reg_con,
+                            scheduler,
                             log,
-                            sub_dir.clone(),
+                            storage,
                             op_url,
-                            token.clone(),
+                            ri.image.clone(),
                             fslayers,
+                            &recorded,
                         ));
                         if futs.len() >= batch_size {
-                            let response = futs.next().await.unwrap();
+                            let (image, blobs, response) = futs.next().await.unwrap();
+                            let response = response?;
+                            state.checkpoint(image, blobs)?;
                             log.debug(&format!(
                                 "completed batch of {} {:#?}",
-                                batch_size,
-                                response.unwrap()
+                                batch_size, response
                             ));
                         }
                     }
                     // wait for the remaining to finish.
-                    while let Some(response) = futs.next().await {
-                        log.debug(&format!("completed rest of batch {:#?}", response.unwrap()));
+                    while let Some((image, blobs, response)) = futs.next().await {
+                        let response = response?;
+                        state.checkpoint(image, blobs)?;
+                        log.debug(&format!("completed rest of batch {:#?}", response));
                     }
                 }
             }
         }
+
+        // build the filtered catalog image for this index
+        catalog_mirror_to_disk(&reg_con, scheduler, log, dir.clone(), ir, configs).await?;
+        report.images += 1;
     }
+    Ok(report)
 }
 
 pub async fn operator_disk_to_mirror<T: RegistryInterface>(
     reg_con: T,
+    scheduler: &RegistryScheduler,
     log: &Logging,
     dir: String,
     destination_url: String,
+    template: &DestinationTemplate,
     operators: Vec<Operator>,
-) -> String {
+) -> Result<Report, MirrorError> {
     // read isc catalogs, packages
     // read all manifests and blobs from disk
     // build the list
     // call push_blobs
     let mut mirror_manifests = vec![];
+    let mut report = Report::default();
     log.hi("operator collector mode: diskToMirror");
     for op in operators.iter() {
         log.info(&format!("catalog {:#?} ", &op.catalog));
-        for pkg in op.packages.clone().unwrap().iter() {
+        let packages = op.packages.clone().ok_or_else(|| {
+            MirrorError::Config(format!("catalog {} has no packages", op.catalog))
+        })?;
+        for pkg in packages.iter() {
             log.info(&format!("packages {:#?} ", pkg));
-            let ir = get_registry_details(&op.catalog);
-            // iterate through each directory in
-            // does it match with the pkg name
-            // if yes then lets see if channels are set
-            // with this info we can open the manifest to get all layers
-            let manifest_dir =
-                get_operator_manifest_json_dir(dir.clone(), &ir.name, &ir.version, &pkg.name);
-            for bundle in pkg.bundles.clone().iter() {
-                log.debug(&format!(
-                    "adding manifest {:#?}",
-                    manifest_dir.clone() + &"/" + &bundle.name
+            let ir = get_registry_details(&op.catalog)?;
+            // the filtered catalog holds the bundles mirrorToDisk resolved
+            let config_dir = get_package_config_dir(&dir, &ir, &pkg.name);
+            if !Path::new(&config_dir).exists() {
+                log.error(&format!(
+                    "no filtered catalog found for package {} of {}",
+                    pkg.name, op.catalog
                 ));
-                let check_dir = manifest_dir.clone() + &"/" + &bundle.name;
-                let am = get_all_assosciated_manifests(log, check_dir.clone());
-                mirror_manifests.insert(0, am.clone());
+                continue;
+            }
+            let mut manifests = vec![];
+            let mut lists = vec![];
+            for (image, image_dir) in get_package_images(log, &dir, &ir, pkg)? {
+                if !Path::new(&image_dir).exists() {
+                    log.error(&format!(
+                        "image {} of package {} not found in {}",
+                        image, pkg.name, image_dir
+                    ));
+                    continue;
+                }
+                log.debug(&format!("adding manifests {:#?}", image_dir));
+                manifests.append(&mut get_all_assosciated_manifests(log, image_dir.clone()));
+                lists.append(&mut get_manifest_lists(log, image_dir));
             }
+            let package_dir = get_package_dir(&dir, &ir, &pkg.name);
+            mirror_manifests.insert(0, (op, package_dir, manifests, lists));
         }
     }
     // we now have all the relevant manifests
@@ -348,75 +430,100 @@ pub async fn operator_disk_to_mirror<T: RegistryInterface>(
         mirror_manifests
     ));
 
+    // manifest lists and catalogs are pushed with the scheduler, their blobs are
+    // read from the blobs-store
+    let manifests = get_manifest_digests(&dir)?;
+    let mut copy = StreamCopy::new(&(dir.clone() + "blobs-store/"), scheduler);
     // using map and collect are not async
-    for mm in mirror_manifests.iter() {
+    for (op, check_dir, mm, lists) in mirror_manifests.iter() {
         for x in mm.iter() {
-            // we can infer some info from the manifest
+            // the source repository is inferred from the manifest path
             let binding = x.to_string();
-            let rd = get_registry_details_from_manifest(binding.clone());
-            log.trace(&format!("metadata for manifest {:#?}", rd));
-            let manifest = get_manifest(binding);
-            let _res = reg_con
+            let repository = get_operator_repository(check_dir, &binding)?;
+            let sub_component =
+                template.get_sub_component(op.destination_prefix.as_deref(), &repository);
+            log.trace(&format!("{} pushed to {}", binding, sub_component));
+            let manifest = get_manifest(binding)?;
+            reg_con
                 .push_image(
                     log,
-                    "./working-dir/".to_string(),
-                    rd.sub_component,
+                    dir.clone(),
+                    sub_component,
                     destination_url.clone(),
                     String::from(""),
                     manifest.clone(),
                 )
-                .await;
+                .await
+                .map_err(|err| {
+                    MirrorError::RegistryHttp(format!("{} {:?}", destination_url, err))
+                })?;
+            report.images += 1;
+        }
+        // the multi-arch images are referenced by the digest of their manifest list
+        for list in lists.iter() {
+            let repository = get_operator_repository(check_dir, list)?;
+            let sub_component =
+                template.get_sub_component(op.destination_prefix.as_deref(), &repository);
+            let dest = Repository::new(&get_mirror_repo(&destination_url, &sub_component))?;
+            copy.push_manifest_list(log, &dest, &fs::read_to_string(list)?, None, &manifests)
+                .await?;
+            report.images += 1;
         }
     }
-    String::from("done")
+
+    // push the filtered catalog next to the operator images, tagged with the catalog
+    // version the CatalogSource refers to
+    for op in operators.iter() {
+        let ir = get_registry_details(&op.catalog)?;
+        let catalog = get_catalog_dir(dir.clone(), &ir.name, &ir.version) + "/manifest.json";
+        if !Path::new(&catalog).exists() {
+            log.error(&format!("no filtered catalog found for {}", op.catalog));
+            continue;
+        }
+        let reference = Reference::parse(&op.catalog)?;
+        let source = Repository::new(&reference.repo())?;
+        let dest = Repository::new(&get_mirror_repo(
+            &destination_url,
+            &get_catalog_sub_component(template, op)?,
+        ))?;
+        copy.copy_image(
+            log,
+            &source,
+            &dest,
+            &fs::read_to_string(&catalog)?,
+            Some(&ir.version),
+            &HashMap::new(),
+        )
+        .await?;
+        report.images += 1;
+    }
+    Ok(report)
 }
 
-// parse_index - best attempt to parse image index and return ImageReference
-pub fn parse_index(log: &Logging, operators: Vec<Operator>) -> Vec<ImageReference> {
+// parse_index - parse the catalog references of the config
+pub fn parse_index(
+    log: &Logging,
+    operators: Vec<Operator>,
+) -> Result<Vec<ImageReference>, MirrorError> {
     let mut image_refs = vec![];
     for ops in operators.iter() {
-        let img = ops.catalog.clone();
-        log.trace(&format!("catalogs {:#?}", img));
-        let mut hld = img.split("/");
-        let reg = hld.nth(0).unwrap();
-        let ns = hld.nth(0).unwrap();
-        let index = hld.nth(0).unwrap();
-        let mut i = index.split(":");
-        let name = i.nth(0).unwrap();
-        let ver = i.nth(0).unwrap();
-        let ir = ImageReference {
-            registry: reg.to_string(),
-            namespace: ns.to_string(),
-            name: name.to_string(),
-            version: ver.to_string(),
-        };
-        log.debug(&format!("image reference {:#?}", img));
+        log.trace(&format!("catalogs {:#?}", ops.catalog));
+        let ir = get_registry_details(&ops.catalog)?;
+        log.debug(&format!("image reference {:#?}", ir));
         image_refs.insert(0, ir);
     }
-    image_refs
+    Ok(image_refs)
 }
 
-// parse_image - best attempt to parse image url and return ImageReference
-pub fn parse_url(log: &Logging, img: String) -> ImageReference {
-    let mut hld = img.split("/");
-    let reg = hld.nth(0).unwrap();
-    let ns = hld.nth(0).unwrap();
-    let index = hld.nth(0).unwrap();
-    let mut i = index.split(":");
-    let name = i.nth(0).unwrap();
-    let ver = i.nth(0).unwrap();
-    let ir = ImageReference {
-        registry: reg.to_string(),
-        namespace: ns.to_string(),
-        name: name.split("@").nth(0).unwrap().to_string(),
-        version: "sha256:".to_owned() + ver,
-    };
-    log.trace(&format!("image reference {:#?}", img));
-    ir
+// parse_url - parse a related image reference (usually by digest)
+pub fn parse_url(log: &Logging, img: String) -> Result<ImageReference, MirrorError> {
+    let ir = Reference::parse(&img)?.to_image_reference();
+    log.trace(&format!("image reference {:#?}", ir));
+    Ok(ir)
 }
 
 // utility functions - get_operator_manifest_json_dir
-fn get_operator_manifest_json_dir(
+pub fn get_operator_manifest_json_dir(
     dir: String,
     name: &str,
     version: &str,
@@ -432,6 +539,61 @@ fn get_operator_manifest_json_dir(
     file
 }
 
+// the index directory mirrorToDisk stores the package images below
+// i.e. working-dir/redhat-operator-index/v4.15/
+fn get_index_dir(dir: &str, ir: &ImageReference) -> String {
+    dir.trim_end_matches("/").to_string() + "/" + &ir.name + "/" + &ir.version + "/"
+}
+
+// the directory of the images of a package i.e. working-dir/redhat-operator-index/v4.15/operators/albo
+pub fn get_package_dir(dir: &str, ir: &ImageReference, package: &str) -> String {
+    get_index_dir(dir, ir) + "operators/" + package
+}
+
+// the declarative config of a package in the filtered catalog
+pub fn get_package_config_dir(dir: &str, ir: &ImageReference, package: &str) -> String {
+    get_catalog_dir(dir.to_string(), &ir.name, &ir.version) + "/configs/" + package
+}
+
+// the related images of the package bundles and the directory mirrorToDisk stored each in
+// the filtered catalog holds the bundles mirrorToDisk resolved (explicit or from the upgrade
+// graph) so all of them are used when the package has no explicit bundles
+pub fn get_package_images(
+    log: &Logging,
+    dir: &str,
+    ir: &ImageReference,
+    pkg: &Package,
+) -> Result<BTreeMap<String, String>, MirrorError> {
+    let config_dir = get_package_config_dir(dir, ir, &pkg.name);
+    let bundle_images = get_bundle_images(&config_dir)?;
+    let mut images = BTreeSet::new();
+    if pkg.bundles.is_empty() {
+        images.extend(bundle_images.into_values().flatten());
+    } else {
+        for bundle in pkg.bundles.iter() {
+            let related = bundle_images.get(&bundle.name).ok_or_else(|| {
+                MirrorError::Config(format!(
+                    "bundle {} of package {} not found in {}",
+                    bundle.name, pkg.name, config_dir
+                ))
+            })?;
+            images.extend(related.iter().cloned());
+        }
+    }
+    let mut image_dirs = BTreeMap::new();
+    for image in images.into_iter() {
+        let ri = parse_url(log, image.clone())?;
+        let image_dir = get_operator_manifest_json_dir(
+            get_index_dir(dir, ir),
+            &(ri.namespace.clone() + "/" + &ri.name),
+            &ri.version,
+            &pkg.name,
+        );
+        image_dirs.insert(image, image_dir);
+    }
+    Ok(image_dirs)
+}
+
 // parse the manifest json for operator indexes only
 pub fn parse_json_manifest_operator(data: String) -> Result<Manifest, Box<dyn std::error::Error>> {
     // Parse the string of data into serde_json::Manifest.
@@ -446,45 +608,32 @@ pub fn parse_json_manifestlist(data: String) -> Result<ManifestList, Box<dyn std
     Ok(root)
 }
 
-// contruct a manifest url from a string by digest
-pub fn get_manifest_url_by_digest(url: String, digest: String) -> String {
-    let mut parts = url.split("/");
-    let mut url = String::from("https://");
-    url.push_str(&parts.nth(0).unwrap());
-    url.push_str(&"/v2/");
-    url.push_str(&parts.nth(0).unwrap());
-    url.push_str(&"/");
-    let i = parts.nth(0).unwrap();
-    let mut sha = i.split("@");
-    url.push_str(&sha.nth(0).unwrap());
-    url.push_str(&"/");
-    url.push_str(&"manifests/");
-    url.push_str(&digest);
-    url
+// contruct a manifest url from an image reference and a digest
+pub fn get_manifest_url_by_digest(image: String, digest: String) -> Result<String, MirrorError> {
+    let mut ir = Reference::parse(&image)?.to_image_reference();
+    ir.version = digest;
+    Ok(get_image_manifest_url(ir))
 }
 
-fn get_registry_details(reg: &str) -> ImageReference {
-    let mut hld = reg.split("/");
-    let reg = hld.nth(0).unwrap();
-    let ns = hld.nth(0).unwrap();
-    let mut index = hld.nth(0).unwrap().split(":");
-    let name = index.nth(0).unwrap();
-    let ver = index.nth(0).unwrap();
-    let ir = ImageReference {
-        registry: reg.to_string(),
-        namespace: ns.to_string(),
-        name: name.to_string(),
-        version: ver.to_string(),
-    };
-    ir
+pub fn get_registry_details(reg: &str) -> Result<ImageReference, MirrorError> {
+    Ok(Reference::parse(reg)?.to_image_reference())
+}
+
+// true when the operator belongs to the catalog (index) ir was parsed from
+fn is_catalog(op: &Operator, ir: &ImageReference) -> Result<bool, MirrorError> {
+    let catalog = get_registry_details(&op.catalog)?;
+    Ok(catalog.registry == ir.registry
+        && catalog.namespace == ir.namespace
+        && catalog.name == ir.name
+        && catalog.version == ir.version)
 }
 
 fn get_all_assosciated_manifests(log: &Logging, dir: String) -> Vec<String> {
     let mut vec_manifests: Vec<String> = vec![];
     let result = WalkDir::new(&dir);
     for file in result.into_iter().filter_map(|file| file.ok()) {
-        if file.metadata().unwrap().is_file() & !file.path().display().to_string().contains("list")
-        {
+        let is_file = file.metadata().map(|m| m.is_file()).unwrap_or(false);
+        if is_file & !file.path().display().to_string().contains("list") {
             log.debug(&format!(
                 "assosciated manifest found {:#?}",
                 file.path().display().to_string()
@@ -495,33 +644,92 @@ fn get_all_assosciated_manifests(log: &Logging, dir: String) -> Vec<String> {
     vec_manifests
 }
 
-fn get_registry_details_from_manifest(name: String) -> MirrorManifest {
-    let res = name.split("/");
-    let collection = res.clone().collect::<Vec<&str>>();
-    let mm = MirrorManifest {
-        registry: collection[1].to_string(),
-        namespace: collection[2].to_string(),
-        name: String::from(""),
-        version: collection[3].to_string(),
-        component: collection[5].to_string(),
-        channel: collection[6].to_string(),
-        sub_component: collection[7].to_string() + &"/" + collection[8],
-        manifest_file: collection[9].to_string(),
-    };
-    mm
+// the repository (namespace and name) of an operator image from the path of its manifest
+// i.e. <package dir>/openshift4/ose-kube-rbac-proxy/<version>/manifest.json
+fn get_operator_repository(package_dir: &str, file: &str) -> Result<String, MirrorError> {
+    let components: Vec<&str> = file
+        .strip_prefix(package_dir)
+        .unwrap_or_default()
+        .split("/")
+        .filter(|component| !component.is_empty())
+        .collect();
+    if components.len() < 3 {
+        return Err(MirrorError::Config(format!(
+            "unexpected operator manifest path {}",
+            file
+        )));
+    }
+    Ok(components[..components.len() - 2].join("/"))
+}
+
+// the sub component of a filtered catalog, pushed next to its operator images
+pub fn get_catalog_sub_component(
+    template: &DestinationTemplate,
+    op: &Operator,
+) -> Result<String, MirrorError> {
+    let reference = Reference::parse(&op.catalog)?;
+    Ok(template.get_sub_component(op.destination_prefix.as_deref(), &reference.repository))
 }
 
-fn get_manifest(dir: String) -> Manifest {
-    let data = fs::read_to_string(&dir).expect("should read various arch manifest files");
-    let manifest = parse_json_manifest_operator(data).unwrap();
-    manifest
+fn get_manifest(dir: String) -> Result<Manifest, MirrorError> {
+    let data = fs::read_to_string(&dir)?;
+    let manifest = parse_json_manifest_operator(data)
+        .map_err(|err| MirrorError::ManifestParse(format!("{} {}", dir, err)))?;
+    Ok(manifest)
+}
+
+// convert op_manifest.layer to FsLayer
+// originally used map(|layer| FsLayer ...)
+// changed to ensure no duplicates included using for..in
+pub(crate) fn get_operator_fslayers(
+    op_manifest: Manifest,
+    original_ref: String,
+    blob_tracker: &mut Vec<String>,
+) -> Result<Vec<FsLayer>, MirrorError> {
+    let mut fslayers: Vec<FsLayer> = Vec::new();
+    for layer in op_manifest.layers.unwrap_or_default().iter() {
+        if !blob_tracker.contains(&layer.digest.clone()) {
+            let fslayer = FsLayer {
+                blob_sum: layer.digest.clone(),
+                original_ref: Some(original_ref.clone()),
+                size: Some(layer.size),
+            };
+            fslayers.insert(0, fslayer);
+            blob_tracker.insert(0, layer.digest.clone());
+        }
+    }
+    // add configs
+    let config = op_manifest.config.ok_or_else(|| {
+        MirrorError::ManifestParse(format!("manifest without config for {}", original_ref))
+    })?;
+    if !blob_tracker.contains(&config.digest) {
+        let cfg = FsLayer {
+            blob_sum: config.digest.clone(),
+            original_ref: Some(original_ref.clone()),
+            size: Some(config.size),
+        };
+        fslayers.insert(0, cfg);
+        blob_tracker.insert(0, config.digest);
+    }
+    Ok(fslayers)
 }
 
 #[cfg(test)]
 mod tests {
     // this brings everything from parent's scope into this scope
     use super::*;
+    use crate::auth::token_cache::TokenCache;
+    use crate::config::registries::SourceMirrors;
+    use crate::scheduler::registry::SchedulerConfig;
+    use crate::storage::local::LocalStorage;
+    use crate::verify::blobs::get_blob_path;
     use async_trait::async_trait;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use serde_json::{json, Value};
+    use sha2::{Digest, Sha256};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
 
     macro_rules! aw {
         ($e:expr) => {
@@ -543,6 +751,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_operator_repository_pass() {
+        let package_dir = "/tmp/working-dir/redhat-operator-index/v4.15/operators/albo";
+        let res = get_operator_repository(
+            package_dir,
+            &(package_dir.to_string()
+                + "/openshift4/sub/ose-kube-rbac-proxy/sha256:abc/manifest.json"),
+        );
+        assert_eq!(res.unwrap(), "openshift4/sub/ose-kube-rbac-proxy");
+        let res =
+            get_operator_repository(package_dir, &(package_dir.to_string() + "/manifest.json"));
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+
+        let op = Operator {
+            catalog: String::from("registry.redhat.io/redhat/redhat-operator-index:v4.15"),
+            packages: None,
+            destination_prefix: Some(String::from("catalogs")),
+        };
+        let template = DestinationTemplate::parse("flat").unwrap();
+        assert_eq!(
+            get_catalog_sub_component(&template, &op).unwrap(),
+            "catalogs/redhat-redhat-operator-index"
+        );
+    }
+
+    #[test]
+    fn is_catalog_pass() {
+        let op = Operator {
+            catalog: String::from("registry.redhat.io/redhat/redhat-operator-index:v4.15"),
+            packages: None,
+            destination_prefix: None,
+        };
+        let ir = get_registry_details("registry.redhat.io/redhat/redhat-operator-index:v4.15");
+        assert!(is_catalog(&op, &ir.unwrap()).unwrap());
+        // the same index in another version is a different catalog
+        let ir = get_registry_details("registry.redhat.io/redhat/redhat-operator-index:v4.16");
+        assert!(!is_catalog(&op, &ir.unwrap()).unwrap());
+        let ir = get_registry_details("registry.redhat.io/redhat/certified-operator-index:v4.15");
+        assert!(!is_catalog(&op, &ir.unwrap()).unwrap());
+    }
+
     #[test]
     fn get_related_images_from_catalog_with_channel_pass() {
         let log = &Logging {
@@ -555,6 +804,10 @@ mod tests {
         let _pkg = Package {
             name: String::from("some-operator"),
             bundles: vec_bundle,
+            channels: None,
+            min_version: None,
+            max_version: None,
+            shortest_path: false,
         };
 
         let ir1 = RelatedImage {
@@ -608,6 +861,10 @@ mod tests {
         let _pkg = Package {
             name: String::from("some-operator"),
             bundles: vec_bundle,
+            channels: None,
+            min_version: None,
+            max_version: None,
+            shortest_path: false,
         };
 
         let ir1 = RelatedImage {
@@ -658,23 +915,67 @@ mod tests {
             )
             .create();
         let bundle = Bundle {
-            name: String::from("aws-load-balancer-operator-bundle"),
+            name: String::from("aws-load-balancer-operator.v1.0.0"),
         };
         let vec_bundle = vec![bundle];
 
         let pkg = Package {
             name: String::from("some-operator"),
             bundles: vec_bundle,
+            channels: None,
+            min_version: None,
+            max_version: None,
+            shortest_path: false,
         };
 
         let pkgs = vec![pkg];
         let op = Operator {
             catalog: String::from(url.replace("http://", "") + "/test/test-index-operator:v1.0"),
             packages: Some(pkgs),
+            destination_prefix: None,
         };
 
+        // a working dir with the cached index, its manifest lists a single (gzipped) layer
+        // so the filtered catalog can be built on top of it
+        let dir = String::from("./test-artifacts/operator-mirror-test/");
+        let _ = fs::remove_dir_all(&dir);
+        let name = String::from("test-index-operator");
+        let version = String::from("v1.0");
+        let fixture = "test-artifacts/test-index-operator/v1.0/cache";
+        let cache_dir = get_cache_dir(dir.clone(), name.clone(), version.clone());
+        for entry in WalkDir::new(fixture) {
+            let entry = entry.unwrap();
+            let target = Path::new(&cache_dir).join(entry.path().strip_prefix(fixture).unwrap());
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(target).unwrap();
+            } else {
+                fs::copy(entry.path(), target).unwrap();
+            }
+        }
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(b"index layer").unwrap();
+        let layer = enc.finish().unwrap();
+        let digest = String::from("sha256:") + &hex::encode(Sha256::digest(&layer));
+        let blob = get_blob_path(&(dir.clone() + "blobs-store/"), &digest);
+        fs::create_dir_all(Path::new(&blob).parent().unwrap()).unwrap();
+        fs::write(&blob, &layer).unwrap();
+        let mut index: Value = serde_json::from_str(
+            &fs::read_to_string("test-artifacts/test-index-operator/v1.0/manifest.json").unwrap(),
+        )
+        .unwrap();
+        index["fsLayers"] = json!([{ "blobSum": digest }]);
+        index["history"] = json!([index["history"][0]]);
+        let index = serde_json::to_string_pretty(&index).unwrap();
+        fs::write(
+            get_manifest_json_file(dir.clone(), name.clone(), version.clone()),
+            &index,
+        )
+        .unwrap();
+
         #[derive(Clone)]
-        struct Fake {}
+        struct Fake {
+            index: String,
+        }
 
         #[async_trait]
         impl RegistryInterface for Fake {
@@ -686,9 +987,7 @@ mod tests {
                 let mut content = String::from("");
 
                 if url.contains("test-index-operator") {
-                    content =
-                        fs::read_to_string("test-artifacts/test-index-operator/v1.0/manifest.json")
-                            .expect("should read operator-index manifest file")
+                    content = self.index.clone();
                 }
                 if url.contains("cad8f6380b4dd4e1396dafcd7dfbf0f405aa10e4ae36214f849e6a77e6210d92")
                 {
@@ -748,20 +1047,219 @@ mod tests {
                 _url: String,
                 _token: String,
                 _manifest: Manifest,
-            ) -> Result<String, MirrorError> {
+            ) -> Result<String, mirror_copy::MirrorError> {
                 log.info("testing logging in fake test");
                 Ok(String::from("test"))
             }
         }
 
-        let fake = Fake {};
+        let fake = Fake { index };
 
         let ops = vec![op.clone()];
-        aw!(operator_mirror_to_disk(
+        // the fake registry serves no blobs, only the manifests and the catalog are written
+        let mut state = RunState {
+            metadata_only: true,
+            ..RunState::default()
+        };
+        let res = aw!(operator_mirror_to_disk(
             fake.clone(),
+            &RegistryScheduler::new(
+                SchedulerConfig::default(),
+                &[],
+                SourceMirrors::default(),
+                TokenCache::anonymous()
+            ),
             log,
-            String::from("./test-artifacts/"),
+            &LocalStorage::new(&dir),
+            false,
+            &mut state,
+            &[],
             ops.clone()
         ));
+        // five related images (the operator is listed twice) and the filtered catalog
+        assert_eq!(res.unwrap().images, 6);
+
+        let manifest_json = get_manifest_json_file(dir.clone(), name.clone(), version.clone());
+        let manifest_dir = manifest_json.trim_end_matches("manifest.json");
+        let op_dir = |image: &str| {
+            let ir = parse_url(log, image.to_string()).unwrap();
+            get_operator_manifest_json_dir(
+                manifest_dir.to_string(),
+                &(ir.namespace.clone() + "/" + &ir.name),
+                &ir.version,
+                "some-operator",
+            )
+        };
+        let controller = op_dir("registry.redhat.io/albo/aws-load-balancer-controller-rhel8@sha256:cad8f6380b4dd4e1396dafcd7dfbf0f405aa10e4ae36214f849e6a77e6210d92");
+        assert!(Path::new(&(controller.clone() + "/manifest-list.json")).exists());
+        assert!(Path::new(&(controller + "/manifest-amd64.json")).exists());
+        for image in [
+            "registry.redhat.io/albo/aws-load-balancer-operator-bundle@sha256:d4d65d0d7c249d076da74da22296280ddef534da2bf54efb9e46d2bd7b9a602d",
+            "registry.redhat.io/albo/aws-load-balancer-rhel8-operator@sha256:cbb31de2108b57172409cede667fa24d68d635ac3cc6db4af6e9b6f9dd1c5cd0",
+            "registry.redhat.io/openshift4/ose-kube-rbac-proxy@sha256:422e4fbe1ed81c79084f43a826dc0674510a7ff578e62b4ddda119ed3266d0b6",
+        ] {
+            assert!(Path::new(&(op_dir(image) + "/manifest.json")).exists());
+        }
+
+        // the filtered catalog only has the mirrored bundle
+        let catalog_dir = get_catalog_dir(dir.clone(), &name, &version);
+        assert!(Path::new(&(catalog_dir.clone() + "/manifest.json")).exists());
+        let catalog =
+            fs::read_to_string(catalog_dir + "/configs/some-operator/catalog.json").unwrap();
+        let bundles: Vec<Value> = catalog
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .filter(|o| o["schema"] == "olm.bundle")
+            .collect();
+        assert_eq!(bundles.len(), 1);
+        assert_eq!(bundles[0]["name"], "aws-load-balancer-operator.v1.0.0");
+        rm_rf::remove(&dir).expect("should delete test dir");
+    }
+
+    #[test]
+    fn disk_to_mirror_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+
+        // records the repositories images are pushed to
+        #[derive(Clone)]
+        struct Fake {
+            pushed: Arc<Mutex<Vec<String>>>,
+        }
+
+        #[async_trait]
+        impl RegistryInterface for Fake {
+            async fn get_manifest(
+                &self,
+                _url: String,
+                _token: String,
+            ) -> Result<String, Box<dyn std::error::Error>> {
+                Ok(String::new())
+            }
+
+            async fn get_blobs(
+                &self,
+                _log: &Logging,
+                _dir: String,
+                _url: String,
+                _token: String,
+                _layers: Vec<FsLayer>,
+            ) -> Result<String, Box<dyn std::error::Error>> {
+                Ok(String::new())
+            }
+
+            async fn push_image(
+                &self,
+                _log: &Logging,
+                _dir: String,
+                sub_component: String,
+                _url: String,
+                _token: String,
+                _manifest: Manifest,
+            ) -> Result<String, mirror_copy::MirrorError> {
+                self.pushed.lock().unwrap().push(sub_component);
+                Ok(String::new())
+            }
+        }
+
+        // the filtered catalog holds two bundles, the images of both are in the working-dir
+        let dir = String::from("./test-artifacts/operator-disk-test/");
+        let _ = fs::remove_dir_all(&dir);
+        let catalog = "registry.redhat.io/redhat/test-index-operator:v1.0";
+        let ir = get_registry_details(catalog).unwrap();
+        let configs = filter_declarative_config(
+            log,
+            String::from(
+                "test-artifacts/test-index-operator/v1.0/cache/b4385e/configs/some-operator",
+            ),
+            &[
+                String::from("aws-load-balancer-operator.v0.2.0"),
+                String::from("aws-load-balancer-operator.v1.0.0"),
+            ],
+        )
+        .unwrap();
+        let config_dir = get_package_config_dir(&dir, &ir, "some-operator");
+        fs::create_dir_all(&config_dir).unwrap();
+        let data: Vec<String> = configs.iter().map(|o| o.to_string()).collect();
+        fs::write(config_dir + "/catalog.json", data.join("\n")).unwrap();
+        let mut pkg = Package {
+            name: String::from("some-operator"),
+            bundles: vec![],
+            channels: None,
+            min_version: None,
+            max_version: None,
+            shortest_path: false,
+        };
+        let images = get_package_images(log, &dir, &ir, &pkg).unwrap();
+        assert_eq!(images.len(), 8);
+        for image_dir in images.values() {
+            fs::create_dir_all(image_dir).unwrap();
+            fs::copy(
+                "test-artifacts/simulate-api-call/manifest.json",
+                image_dir.clone() + "/manifest.json",
+            )
+            .unwrap();
+        }
+
+        // only the images of the explicit bundle are pushed
+        pkg.bundles = vec![Bundle {
+            name: String::from("aws-load-balancer-operator.v1.0.0"),
+        }];
+        let op = Operator {
+            catalog: String::from(catalog),
+            packages: Some(vec![pkg.clone()]),
+            destination_prefix: None,
+        };
+        let fake = Fake {
+            pushed: Arc::new(Mutex::new(vec![])),
+        };
+        let scheduler = RegistryScheduler::new(
+            SchedulerConfig::default(),
+            &[],
+            SourceMirrors::default(),
+            TokenCache::anonymous(),
+        );
+        let res = aw!(operator_disk_to_mirror(
+            fake.clone(),
+            &scheduler,
+            log,
+            dir.clone(),
+            String::from("localhost:5000/mirror"),
+            &DestinationTemplate::default(),
+            vec![op.clone()],
+        ));
+        assert_eq!(res.unwrap().images, 4);
+        let mut pushed = fake.pushed.lock().unwrap().clone();
+        pushed.sort();
+        assert_eq!(
+            pushed,
+            vec![
+                "albo/aws-load-balancer-controller-rhel8",
+                "albo/aws-load-balancer-operator-bundle",
+                "albo/aws-load-balancer-rhel8-operator",
+                "openshift4/ose-kube-rbac-proxy",
+            ]
+        );
+
+        // a bundle that was not mirrored
+        pkg.bundles = vec![Bundle {
+            name: String::from("aws-load-balancer-operator.v0.0.1"),
+        }];
+        let op = Operator {
+            packages: Some(vec![pkg]),
+            ..op
+        };
+        let res = aw!(operator_disk_to_mirror(
+            fake,
+            &scheduler,
+            log,
+            dir.clone(),
+            String::from("localhost:5000/mirror"),
+            &DestinationTemplate::default(),
+            vec![op],
+        ));
+        rm_rf::remove(&dir).expect("should delete test dir");
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
     }
 }
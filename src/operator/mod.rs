@@ -1 +1,3 @@
+pub mod catalog;
 pub mod collector;
+pub mod upgrade_graph;
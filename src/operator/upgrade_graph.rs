@@ -0,0 +1,416 @@
+use custom_logger::*;
+use semver::{Version, VersionReq};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+
+use crate::config::load::Package;
+use crate::error::handler::MirrorError;
+
+// channel entry from the declarative config (olm.channel)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelEntry {
+    pub name: String,
+    pub replaces: Option<String>,
+    pub skips: Vec<String>,
+    pub skip_range: Option<String>,
+}
+
+// upgrade graph of a single package
+#[derive(Debug, Clone, Default)]
+pub struct PackageGraph {
+    pub default_channel: String,
+    pub channels: HashMap<String, Vec<ChannelEntry>>,
+    pub versions: HashMap<String, Version>,
+}
+
+impl PackageGraph {
+    // read all json files of the package in the declarative config directory
+    pub fn load(dir: String) -> Result<PackageGraph, MirrorError> {
+        let mut graph = PackageGraph::default();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.display().to_string().ends_with(".json") {
+                graph.parse(&fs::read_to_string(&path)?)?;
+            }
+        }
+        if graph.channels.is_empty() {
+            return Err(MirrorError::ManifestParse(format!(
+                "no channels found in declarative config {}",
+                dir
+            )));
+        }
+        Ok(graph)
+    }
+
+    // the declarative config is a stream of json objects
+    pub fn parse(&mut self, data: &str) -> Result<(), MirrorError> {
+        for obj in serde_json::Deserializer::from_str(data).into_iter::<Value>() {
+            let obj = obj?;
+            let name = obj["name"].as_str().unwrap_or_default().to_string();
+            match obj["schema"].as_str().unwrap_or_default() {
+                "olm.package" => {
+                    self.default_channel = obj["defaultChannel"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string();
+                }
+                "olm.channel" => {
+                    let entries = obj["entries"]
+                        .as_array()
+                        .cloned()
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|e| ChannelEntry {
+                            name: e["name"].as_str().unwrap_or_default().to_string(),
+                            replaces: e["replaces"].as_str().map(|s| s.to_string()),
+                            skips: e["skips"]
+                                .as_array()
+                                .cloned()
+                                .unwrap_or_default()
+                                .iter()
+                                .filter_map(|s| s.as_str().map(|s| s.to_string()))
+                                .collect(),
+                            skip_range: e["skipRange"].as_str().map(|s| s.to_string()),
+                        })
+                        .collect();
+                    self.channels.insert(name, entries);
+                }
+                "olm.bundle" => {
+                    let properties = obj["properties"].as_array().cloned().unwrap_or_default();
+                    let version = properties
+                        .iter()
+                        .find(|p| p["type"] == "olm.package")
+                        .and_then(|p| p["value"]["version"].as_str())
+                        .and_then(|v| Version::parse(v).ok());
+                    if let Some(version) = version {
+                        self.versions.insert(name, version);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // version from the bundle properties, falls back to the bundle name (name.vX.Y.Z)
+    pub fn get_version(&self, bundle: &str) -> Option<Version> {
+        if let Some(version) = self.versions.get(bundle) {
+            return Some(version.clone());
+        }
+        let (_, version) = bundle.split_once(".v")?;
+        Version::parse(version).ok()
+    }
+
+    // the head is the entry that no other entry replaces or skips
+    pub fn get_head(&self, channel: &str) -> Result<ChannelEntry, MirrorError> {
+        let entries = self.get_entries(channel)?;
+        entries
+            .iter()
+            .filter(|e| {
+                !entries.iter().any(|o| {
+                    o.replaces.as_deref() == Some(e.name.as_str()) || o.skips.contains(&e.name)
+                })
+            })
+            .max_by_key(|e| self.get_version(&e.name))
+            .cloned()
+            .ok_or_else(|| {
+                MirrorError::ManifestParse(format!("no head found for channel {}", channel))
+            })
+    }
+
+    // resolve the bundles of the channel from min to max (or the head)
+    // without a minimum only the target bundle is mirrored, with shortest_path only the
+    // bundles a cluster needs to upgrade from min to the target
+    pub fn resolve(
+        &self,
+        log: &Logging,
+        channel: &str,
+        min: Option<Version>,
+        max: Option<Version>,
+        shortest_path: bool,
+    ) -> Result<Vec<String>, MirrorError> {
+        let entries = self.get_entries(channel)?;
+        let target = match &max {
+            Some(max) => entries
+                .iter()
+                .filter(|e| self.get_version(&e.name).map_or(false, |v| v <= *max))
+                .max_by_key(|e| self.get_version(&e.name))
+                .cloned()
+                .ok_or_else(|| {
+                    MirrorError::Config(format!(
+                        "no bundle with maxVersion {} in channel {}",
+                        max, channel
+                    ))
+                })?,
+            None => self.get_head(channel)?,
+        };
+        let min = match min {
+            Some(val) => val,
+            None => return Ok(vec![target.name]),
+        };
+
+        if !shortest_path {
+            // every bundle of the channel in the range, newest first
+            let target_version = self.get_version(&target.name);
+            let mut in_range: Vec<&ChannelEntry> = entries
+                .iter()
+                .filter(|e| {
+                    self.get_version(&e.name)
+                        .map_or(false, |v| v >= min && Some(&v) <= target_version.as_ref())
+                })
+                .collect();
+            in_range.sort_by_key(|e| std::cmp::Reverse(self.get_version(&e.name)));
+            return Ok(in_range.iter().map(|e| e.name.clone()).collect());
+        }
+
+        // walk back along the shortest upgrade path until the minimum version is reached
+        let mut bundles = vec![target.name.clone()];
+        let mut current = target;
+        while self.get_version(&current.name).map_or(false, |v| v > min) {
+            let next = entries
+                .iter()
+                .filter(|e| self.is_predecessor(&current, e))
+                .filter(|e| self.get_version(&e.name).map_or(false, |v| v >= min))
+                .min_by_key(|e| self.get_version(&e.name));
+            match next {
+                Some(entry) if !bundles.contains(&entry.name) => {
+                    log.trace(&format!("{} upgrades from {}", current.name, entry.name));
+                    bundles.push(entry.name.clone());
+                    current = entry.clone();
+                }
+                _ => break,
+            }
+        }
+        Ok(bundles)
+    }
+
+    fn get_entries(&self, channel: &str) -> Result<&Vec<ChannelEntry>, MirrorError> {
+        self.channels.get(channel).ok_or_else(|| {
+            MirrorError::Config(format!(
+                "channel {} not found in declarative config",
+                channel
+            ))
+        })
+    }
+
+    // true if entry can be upgraded to current (replaces, skips or skipRange)
+    fn is_predecessor(&self, current: &ChannelEntry, entry: &ChannelEntry) -> bool {
+        if current.name == entry.name {
+            return false;
+        }
+        if current.replaces.as_deref() == Some(entry.name.as_str())
+            || current.skips.contains(&entry.name)
+        {
+            return true;
+        }
+        match (&current.skip_range, self.get_version(&entry.name)) {
+            (Some(range), Some(version)) => parse_skip_range(range)
+                .map(|req| req.matches(&version))
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+}
+
+// skipRange uses spaces between comparators i.e. ">=4.1.0 <4.2.0"
+fn parse_skip_range(range: &str) -> Option<VersionReq> {
+    let req = range.split_whitespace().collect::<Vec<&str>>().join(", ");
+    VersionReq::parse(&req).ok()
+}
+
+fn parse_version(version: &Option<String>) -> Result<Option<Version>, MirrorError> {
+    match version {
+        Some(val) => Version::parse(val)
+            .map(Some)
+            .map_err(|err| MirrorError::Config(format!("invalid version {} {}", val, err))),
+        None => Ok(None),
+    }
+}
+
+// get the bundles to mirror for a package in the imagesetconfig
+// explicit bundles are used as is, otherwise the channels (or the default channel) are resolved
+pub fn get_package_bundles(
+    log: &Logging,
+    config_dir: String,
+    pkg: &Package,
+) -> Result<Vec<String>, MirrorError> {
+    if !pkg.bundles.is_empty() {
+        return Ok(pkg.bundles.iter().map(|b| b.name.clone()).collect());
+    }
+    let graph = PackageGraph::load(config_dir)?;
+    let channels = match &pkg.channels {
+        Some(channels) if !channels.is_empty() => channels
+            .iter()
+            .map(|c| {
+                (
+                    c.name.clone(),
+                    c.min_version.clone().or(pkg.min_version.clone()),
+                    c.max_version.clone().or(pkg.max_version.clone()),
+                )
+            })
+            .collect(),
+        _ => vec![(
+            graph.default_channel.clone(),
+            pkg.min_version.clone(),
+            pkg.max_version.clone(),
+        )],
+    };
+    let mut bundles = BTreeSet::new();
+    for (channel, min, max) in channels.iter() {
+        let resolved = graph.resolve(
+            log,
+            channel,
+            parse_version(min)?,
+            parse_version(max)?,
+            pkg.shortest_path,
+        )?;
+        log.debug(&format!(
+            "package {} channel {} bundles {:?}",
+            pkg.name, channel, resolved
+        ));
+        bundles.extend(resolved);
+    }
+    Ok(bundles.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use crate::config::load::Channel;
+
+    fn get_test_graph() -> PackageGraph {
+        let data = r#"
+            {"schema": "olm.package", "name": "test-operator", "defaultChannel": "stable"}
+            {"schema": "olm.channel", "name": "stable", "package": "test-operator", "entries": [
+                {"name": "test-operator.v1.0.0"},
+                {"name": "test-operator.v1.1.0", "replaces": "test-operator.v1.0.0"},
+                {"name": "test-operator.v1.2.0", "replaces": "test-operator.v1.1.0"},
+                {"name": "test-operator.v1.3.0", "replaces": "test-operator.v1.2.0", "skipRange": ">=1.1.0 <1.3.0"}
+            ]}
+            {"schema": "olm.channel", "name": "fast", "package": "test-operator", "entries": [
+                {"name": "test-operator.v1.3.0"},
+                {"name": "test-operator.v2.0.0", "replaces": "test-operator.v1.3.0"}
+            ]}
+            {"schema": "olm.bundle", "name": "test-operator.v1.3.0", "package": "test-operator",
+             "properties": [{"type": "olm.package", "value": {"packageName": "test-operator", "version": "1.3.0"}}]}
+        "#;
+        let mut graph = PackageGraph::default();
+        graph.parse(data).unwrap();
+        graph
+    }
+
+    #[test]
+    fn resolve_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let graph = get_test_graph();
+        assert_eq!(graph.default_channel, "stable");
+        assert_eq!(
+            graph.get_head("stable").unwrap().name,
+            "test-operator.v1.3.0"
+        );
+
+        // head of the channel only
+        let res = graph.resolve(log, "stable", None, None, false).unwrap();
+        assert_eq!(res, vec!["test-operator.v1.3.0"]);
+
+        // every bundle from the minimum to the head
+        let min = Version::parse("1.0.0").ok();
+        let res = graph.resolve(log, "stable", min, None, false).unwrap();
+        assert_eq!(
+            res,
+            vec![
+                "test-operator.v1.3.0",
+                "test-operator.v1.2.0",
+                "test-operator.v1.1.0",
+                "test-operator.v1.0.0"
+            ]
+        );
+        let min = Version::parse("1.1.0").ok();
+        let max = Version::parse("1.2.0").ok();
+        let res = graph.resolve(log, "stable", min, max, false).unwrap();
+        assert_eq!(res, vec!["test-operator.v1.2.0", "test-operator.v1.1.0"]);
+
+        // the shortest path uses the skipRange to jump over v1.2.0
+        let min = Version::parse("1.0.0").ok();
+        let res = graph.resolve(log, "stable", min, None, true).unwrap();
+        assert_eq!(
+            res,
+            vec![
+                "test-operator.v1.3.0",
+                "test-operator.v1.1.0",
+                "test-operator.v1.0.0"
+            ]
+        );
+
+        let min = Version::parse("1.0.0").ok();
+        let max = Version::parse("1.2.0").ok();
+        let res = graph.resolve(log, "stable", min, max, true).unwrap();
+        assert_eq!(
+            res,
+            vec![
+                "test-operator.v1.2.0",
+                "test-operator.v1.1.0",
+                "test-operator.v1.0.0"
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_fail() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let graph = get_test_graph();
+        let res = graph.resolve(log, "unknown", None, None, false);
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+        let max = Version::parse("0.1.0").ok();
+        let res = graph.resolve(log, "stable", None, max, false);
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+    }
+
+    #[test]
+    fn get_package_bundles_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let mut pkg = Package {
+            name: String::from("aws-load-balancer-operator"),
+            bundles: vec![],
+            channels: None,
+            min_version: None,
+            max_version: None,
+            shortest_path: false,
+        };
+        let dir = String::from(
+            "test-artifacts/test-index-operator/v1.0/cache/b4385e/configs/some-operator",
+        );
+        // head of the default channel
+        let res = get_package_bundles(log, dir.clone(), &pkg).unwrap();
+        assert_eq!(res, vec!["aws-load-balancer-operator.v1.0.0"]);
+
+        pkg.channels = Some(vec![
+            Channel {
+                name: String::from("stable-v0"),
+                min_version: None,
+                max_version: None,
+            },
+            Channel {
+                name: String::from("alpha"),
+                min_version: None,
+                max_version: None,
+            },
+        ]);
+        let res = get_package_bundles(log, dir, &pkg).unwrap();
+        assert_eq!(
+            res,
+            vec![
+                "aws-load-balancer-operator.v0.0.1",
+                "aws-load-balancer-operator.v0.2.0"
+            ]
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::error::handler::MirrorError;
+use crate::verify::blobs::get_blob_path;
+
+// summary of a delete or gc run
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PruneReport {
+    pub manifests: usize,
+    pub blobs: usize,
+    pub bytes: u64,
+    pub registry_manifests: usize,
+}
+
+// the manifest files below dir, the blobs-store, the untarred index caches and the
+// excluded directories are skipped
+pub fn get_manifest_files(dir: &str, excluded: &[String]) -> Vec<String> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|e| {
+            e.file_name() != "blobs-store"
+                && e.file_name() != "cache"
+                && !excluded.iter().any(|x| Path::new(x) == e.path())
+        })
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let name = e.file_name().to_string_lossy();
+            e.path().is_file() && name.starts_with("manifest") && name.ends_with(".json")
+        })
+        .map(|e| e.path().display().to_string())
+        .collect()
+}
+
+// the config and layer digests referenced by the manifest files, the schema 1 index
+// manifests (fsLayers) are included
+// a manifest that can not be parsed is an error, its blobs would be swept otherwise
+pub fn get_referenced_blobs(files: &[String]) -> Result<BTreeSet<String>, MirrorError> {
+    let mut blobs = BTreeSet::new();
+    for file in files.iter() {
+        let value: Value = serde_json::from_str(&fs::read_to_string(file)?)
+            .map_err(|err| MirrorError::ManifestParse(format!("{} {}", file, err)))?;
+        let mut layers = value["layers"].as_array().cloned().unwrap_or_default();
+        layers.push(value["config"].clone());
+        for layer in layers.iter() {
+            if let Some(digest) = layer["digest"].as_str() {
+                blobs.insert(digest.to_string());
+            }
+        }
+        for layer in value["fsLayers"].as_array().iter().copied().flatten() {
+            if let Some(digest) = layer["blobSum"].as_str() {
+                blobs.insert(digest.to_string());
+            }
+        }
+    }
+    Ok(blobs)
+}
+
+// remove a blob from the blobs-store, returns the bytes reclaimed (0 if it does not exist)
+pub fn remove_blob(blobs_dir: &str, digest: &str, dry_run: bool) -> Result<u64, MirrorError> {
+    let path = get_blob_path(blobs_dir, digest);
+    let size = match fs::metadata(&path) {
+        Ok(meta) => meta.len(),
+        Err(_) => return Ok(0),
+    };
+    if !dry_run {
+        fs::remove_file(&path)?;
+    }
+    Ok(size)
+}
@@ -0,0 +1,343 @@
+use custom_logger::*;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::additional::collector::{get_additional_manifest_json_dir, parse_additional_image};
+use crate::auth::token_cache::TokenCache;
+use crate::cluster::resources::{
+    get_manifest_digests, get_mirror_repo, get_mirrored_images, resolve_mirror,
+};
+use crate::config::load::Mirror;
+use crate::config::registries::SourceMirrors;
+use crate::error::handler::MirrorError;
+use crate::operator::catalog::get_bundle_images;
+use crate::operator::collector::{
+    get_package_config_dir, get_package_images, get_registry_details,
+};
+use crate::prune::blobs::*;
+use crate::release::collector::get_dir_from_isc;
+use crate::scheduler::registry::{RegistryScheduler, SchedulerConfig};
+use crate::stream::registry::*;
+use crate::verify::blobs::compute_sha256;
+
+// the working-dir directories holding the images of the delete config
+// the layout is the same as the one read by the diskToMirror collectors
+pub fn get_delete_dirs(
+    log: &Logging,
+    dir: &str,
+    delete: &Mirror,
+) -> Result<Vec<String>, MirrorError> {
+    let mut dirs = vec![];
+    for release in delete.release.iter().flatten() {
+        dirs.push(dir.to_string() + &get_dir_from_isc(release.image.clone())?);
+    }
+    for op in delete.operators.iter().flatten() {
+        let ir = get_registry_details(&op.catalog)?;
+        for pkg in op.packages.iter().flatten() {
+            // images shared with the bundles of the package that are kept stay in place
+            let kept: BTreeSet<String> = match pkg.bundles.is_empty() {
+                true => BTreeSet::new(),
+                false => get_bundle_images(&get_package_config_dir(dir, &ir, &pkg.name))?
+                    .into_iter()
+                    .filter(|(name, _)| !pkg.bundles.iter().any(|b| &b.name == name))
+                    .flat_map(|(_, images)| images)
+                    .collect(),
+            };
+            for (image, image_dir) in get_package_images(log, dir, &ir, pkg)? {
+                if kept.contains(&image) {
+                    log.debug(&format!("{} is used by a bundle that is kept", image));
+                    continue;
+                }
+                if !Path::new(&image_dir).exists() {
+                    log.error(&format!(
+                        "image {} of package {} not found in {}",
+                        image, pkg.name, image_dir
+                    ));
+                    continue;
+                }
+                dirs.push(image_dir);
+            }
+        }
+    }
+    for img in delete.additional_images.iter().flatten() {
+        let ir = parse_additional_image(log, img.name.clone())?;
+        dirs.push(get_additional_manifest_json_dir(
+            dir.to_string(),
+            &ir.namespace,
+            &ir.name,
+            &ir.version,
+        ));
+    }
+    Ok(dirs
+        .into_iter()
+        .filter(|d| {
+            let exists = Path::new(d).exists();
+            if !exists {
+                log.debug(&format!("nothing to delete in {}", d));
+            }
+            exists
+        })
+        .collect())
+}
+
+// remove the images of the delete config from the working-dir, the blobs only referenced
+// by the removed manifests are removed from the blobs-store
+// with a docker:// destination the manifests are deleted from the registry as well
+pub async fn delete_images(
+    log: &Logging,
+    dir: String,
+    delete: &Mirror,
+    destination: Option<String>,
+) -> Result<PruneReport, MirrorError> {
+    let mut report = PruneReport::default();
+    let delete = resolve_mirror(log, delete).await?;
+    let delete_dirs = get_delete_dirs(log, &dir, &delete)?;
+    let deleted_files: Vec<String> = delete_dirs
+        .iter()
+        .flat_map(|d| get_manifest_files(d, &[]))
+        .collect();
+    let kept_files = get_manifest_files(&dir, &delete_dirs);
+    let kept_blobs = get_referenced_blobs(&kept_files)?;
+    let deleted_blobs: BTreeSet<String> = get_referenced_blobs(&deleted_files)?
+        .into_iter()
+        .filter(|digest| !kept_blobs.contains(digest))
+        .collect();
+
+    // the registry is cleaned up first, the manifests are needed to find the digests
+    if let Some(destination) = destination {
+        let mut kept_digests = HashSet::new();
+        for file in kept_files.iter() {
+            let (hash, _) = compute_sha256(file)?;
+            kept_digests.insert(String::from("sha256:") + &hash);
+        }
+        report.registry_manifests = delete_from_registry(
+            log,
+            &dir,
+            &destination,
+            &delete,
+            &delete_dirs,
+            &kept_digests,
+        )
+        .await?;
+    }
+
+    for d in delete_dirs.iter() {
+        log.debug(&format!("removing {}", d));
+        report.manifests += get_manifest_files(d, &[]).len();
+        rm_rf::remove(d).map_err(|err| MirrorError::Io(format!("{} {}", d, err)))?;
+    }
+    let blobs_dir = dir.clone() + "blobs-store/";
+    for digest in deleted_blobs.iter() {
+        log.trace(&format!("removing blob {}", digest));
+        report.bytes += remove_blob(&blobs_dir, digest, false)?;
+        report.blobs += 1;
+    }
+    log.info(&format!(
+        "deleted {} manifests, {} blobs ({} bytes) and {} registry manifests",
+        report.manifests, report.blobs, report.bytes, report.registry_manifests
+    ));
+    Ok(report)
+}
+
+// delete the manifests (and the children of manifest lists) of the images stored in the
+// deleted directories, digests still referenced by the kept manifests are left in place
+async fn delete_from_registry(
+    log: &Logging,
+    dir: &str,
+    destination: &str,
+    delete: &Mirror,
+    delete_dirs: &[String],
+    kept_digests: &HashSet<String>,
+) -> Result<usize, MirrorError> {
+    let manifests = get_manifest_digests(dir)?;
+    let images = get_mirrored_images(log, dir.to_string(), delete, &manifests).await?;
+    let scheduler = RegistryScheduler::new(
+        SchedulerConfig::default(),
+        &[],
+        SourceMirrors::default(),
+        TokenCache::new(),
+    );
+    let client = StreamCopy::new(&(dir.to_string() + "blobs-store/"), &scheduler);
+    let mut deleted = 0;
+    for (image, mi) in images.iter() {
+        if !delete_dirs
+            .iter()
+            .any(|d| Path::new(&mi.file).starts_with(d))
+        {
+            continue;
+        }
+        let repo = get_mirror_repo(destination, &mi.sub_component);
+        let dest = Repository::new(&repo)?;
+        let content = get_pushed_content(&fs::read_to_string(&mi.file)?, &manifests)?;
+        let value: Value = serde_json::from_str(&content)?;
+        let mut digests = vec![get_content_digest(&content)];
+        for child in value["manifests"].as_array().iter().copied().flatten() {
+            if let Some(digest) = child["digest"].as_str() {
+                digests.push(digest.to_string());
+            }
+        }
+        for digest in digests.iter().filter(|d| !kept_digests.contains(*d)) {
+            match client.delete_manifest(log, &dest, digest).await? {
+                true => {
+                    log.debug(&format!("deleted {}@{} ({})", repo, digest, image));
+                    deleted += 1;
+                }
+                false => log.debug(&format!("{}@{} not found", repo, digest)),
+            }
+        }
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use crate::config::load::{Bundle, Image, Operator, Package};
+    use crate::operator::catalog::filter_declarative_config;
+    use crate::verify::blobs::get_blob_path;
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    #[test]
+    fn delete_images_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let dir = String::from("./test-artifacts/delete-test/");
+        let blobs_dir = dir.clone() + "blobs-store/";
+        let manifest = r#"{"schemaVersion": 2, "config": {"digest": "sha256:c0", "size": 10}, "layers": [{"digest": "sha256:a1", "size": 100}, {"digest": "sha256:a2", "size": 200}]}"#;
+        let other = r#"{"schemaVersion": 2, "config": {"digest": "sha256:c1", "size": 20}, "layers": [{"digest": "sha256:a1", "size": 100}]}"#;
+        for (name, data) in [("name", manifest), ("other", other)] {
+            let add_dir = get_additional_manifest_json_dir(dir.clone(), "ns", name, "v1");
+            fs::create_dir_all(&add_dir).expect("should create test dir");
+            fs::write(add_dir + "/manifest.json", data).expect("should write manifest");
+        }
+        for digest in ["sha256:c0", "sha256:c1", "sha256:a1", "sha256:a2"] {
+            let blob = get_blob_path(&blobs_dir, digest);
+            fs::create_dir_all(Path::new(&blob).parent().unwrap()).expect("should create dir");
+            fs::write(&blob, "blob").expect("should write blob");
+        }
+        let delete = Mirror {
+            release: None,
+            operators: None,
+            additional_images: Some(vec![Image {
+                name: String::from("quay.io/ns/name:v1"),
+            }]),
+            platform: None,
+            destination_template: None,
+        };
+        let report = aw!(delete_images(log, dir.clone(), &delete, None)).unwrap();
+        assert_eq!(report.manifests, 1);
+        // the shared layer is still referenced by ns/other
+        assert_eq!(report.blobs, 2);
+        assert_eq!(report.bytes, 8);
+        assert!(!Path::new(&get_additional_manifest_json_dir(
+            dir.clone(),
+            "ns",
+            "name",
+            "v1"
+        ))
+        .exists());
+        assert!(Path::new(&get_blob_path(&blobs_dir, "sha256:a1")).exists());
+        assert!(!Path::new(&get_blob_path(&blobs_dir, "sha256:a2")).exists());
+        rm_rf::remove(&dir).expect("should delete test dir");
+    }
+
+    #[test]
+    fn delete_bundle_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let dir = String::from("./test-artifacts/delete-bundle-test/");
+        let _ = fs::remove_dir_all(&dir);
+        let blobs_dir = dir.clone() + "blobs-store/";
+        let catalog = "registry.redhat.io/redhat/test-index-operator:v1.0";
+        let ir = get_registry_details(catalog).unwrap();
+        let bundles = [
+            String::from("aws-load-balancer-operator.v0.2.0"),
+            String::from("aws-load-balancer-operator.v1.0.0"),
+        ];
+        let configs = filter_declarative_config(
+            log,
+            String::from(
+                "test-artifacts/test-index-operator/v1.0/cache/b4385e/configs/some-operator",
+            ),
+            &bundles,
+        )
+        .unwrap();
+        let config_dir = get_package_config_dir(&dir, &ir, "some-operator");
+        fs::create_dir_all(&config_dir).unwrap();
+        let data: Vec<String> = configs.iter().map(|o| o.to_string()).collect();
+        fs::write(config_dir.clone() + "/catalog.json", data.join("\n")).unwrap();
+
+        // the images of each bundle have a layer of their own
+        let mut pkg = Package {
+            name: String::from("some-operator"),
+            bundles: vec![],
+            channels: None,
+            min_version: None,
+            max_version: None,
+            shortest_path: false,
+        };
+        let bundle_images = get_bundle_images(&config_dir).unwrap();
+        let image_dirs = get_package_images(log, &dir, &ir, &pkg).unwrap();
+        for (bundle, layer) in bundles.iter().zip(["sha256:a0", "sha256:a1"]) {
+            let manifest = format!(
+                r#"{{"schemaVersion": 2, "config": {{"digest": "sha256:c0", "size": 10}}, "layers": [{{"digest": "{}", "size": 100}}]}}"#,
+                layer
+            );
+            for image in bundle_images[bundle].iter() {
+                let image_dir = &image_dirs[image];
+                fs::create_dir_all(image_dir).expect("should create test dir");
+                fs::write(image_dir.clone() + "/manifest.json", &manifest)
+                    .expect("should write manifest");
+            }
+        }
+        for digest in ["sha256:c0", "sha256:a0", "sha256:a1"] {
+            let blob = get_blob_path(&blobs_dir, digest);
+            fs::create_dir_all(Path::new(&blob).parent().unwrap()).expect("should create dir");
+            fs::write(&blob, "blob").expect("should write blob");
+        }
+
+        pkg.bundles = vec![Bundle {
+            name: bundles[0].clone(),
+        }];
+        let mut delete = Mirror {
+            release: None,
+            operators: Some(vec![Operator {
+                catalog: String::from(catalog),
+                packages: Some(vec![pkg.clone()]),
+                destination_prefix: None,
+            }]),
+            additional_images: None,
+            platform: None,
+            destination_template: None,
+        };
+        let report = aw!(delete_images(log, dir.clone(), &delete, None)).unwrap();
+        assert_eq!(report.manifests, 4);
+        assert_eq!(report.blobs, 1);
+        for (bundle, exists) in bundles.iter().zip([false, true]) {
+            for image in bundle_images[bundle].iter() {
+                assert_eq!(Path::new(&image_dirs[image]).exists(), exists);
+            }
+        }
+        assert!(!Path::new(&get_blob_path(&blobs_dir, "sha256:a0")).exists());
+        assert!(Path::new(&get_blob_path(&blobs_dir, "sha256:c0")).exists());
+
+        // a bundle that is not in the filtered catalog
+        pkg.bundles = vec![Bundle {
+            name: String::from("aws-load-balancer-operator.v0.0.1"),
+        }];
+        delete.operators.as_mut().unwrap()[0].packages = Some(vec![pkg]);
+        let res = aw!(delete_images(log, dir.clone(), &delete, None));
+        rm_rf::remove(&dir).expect("should delete test dir");
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+    }
+}
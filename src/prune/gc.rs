@@ -0,0 +1,117 @@
+use custom_logger::*;
+use std::collections::HashSet;
+use walkdir::WalkDir;
+
+use crate::error::handler::MirrorError;
+use crate::prune::blobs::*;
+
+// mark every digest referenced by the manifests in working-dir (operators, release,
+// additional images, built catalogs and the cache index manifests) and sweep the
+// unreferenced blobs from the blobs-store, with dry_run nothing is removed
+pub fn collect_garbage(
+    log: &Logging,
+    dir: String,
+    dry_run: bool,
+) -> Result<PruneReport, MirrorError> {
+    let mut report = PruneReport::default();
+    let files = get_manifest_files(&dir, &[]);
+    // blobs are stored by hash without the algorithm prefix
+    let marked: HashSet<String> = get_referenced_blobs(&files)?
+        .iter()
+        .map(|d| d.split(":").last().unwrap_or_default().to_string())
+        .collect();
+    log.debug(&format!(
+        "marked {} blobs from {} manifests",
+        marked.len(),
+        files.len()
+    ));
+
+    let blobs_dir = dir.clone() + "blobs-store/";
+    for entry in WalkDir::new(&blobs_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+    {
+        let hash = entry.file_name().to_string_lossy().to_string();
+        if marked.contains(&hash) {
+            continue;
+        }
+        let digest = String::from("sha256:") + &hash;
+        log.trace(&format!("sweeping blob {}", digest));
+        report.bytes += remove_blob(&blobs_dir, &digest, dry_run)?;
+        report.blobs += 1;
+    }
+    let action = if dry_run {
+        "would reclaim"
+    } else {
+        "reclaimed"
+    };
+    log.info(&format!(
+        "gc {} {} bytes from {} unreferenced blobs",
+        action, report.bytes, report.blobs
+    ));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use crate::verify::blobs::get_blob_path;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn collect_garbage_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let dir = String::from("./test-artifacts/gc-test/");
+        let blobs_dir = dir.clone() + "blobs-store/";
+        let manifest = r#"{"schemaVersion": 2, "config": {"digest": "sha256:c0aa", "size": 10}, "layers": [{"digest": "sha256:a1aa", "size": 100}]}"#;
+        let index = r#"{"schemaVersion": 1, "fsLayers": [{"blobSum": "sha256:b1aa"}]}"#;
+        let add_dir = dir.clone() + "additional/ns/name/v1";
+        fs::create_dir_all(&add_dir).expect("should create test dir");
+        fs::write(add_dir + "/manifest.json", manifest).expect("should write manifest");
+        fs::create_dir_all(dir.clone() + "index/v1").expect("should create test dir");
+        fs::write(dir.clone() + "index/v1/manifest.json", index).expect("should write manifest");
+        for digest in ["sha256:c0aa", "sha256:a1aa", "sha256:b1aa", "sha256:d1aa"] {
+            let blob = get_blob_path(&blobs_dir, digest);
+            fs::create_dir_all(Path::new(&blob).parent().unwrap()).expect("should create dir");
+            fs::write(&blob, "orphan").expect("should write blob");
+        }
+        let orphan = get_blob_path(&blobs_dir, "sha256:d1aa");
+
+        let report = collect_garbage(log, dir.clone(), true).unwrap();
+        assert_eq!(report.blobs, 1);
+        assert_eq!(report.bytes, 6);
+        assert!(Path::new(&orphan).exists());
+
+        let report = collect_garbage(log, dir.clone(), false).unwrap();
+        assert_eq!(report.blobs, 1);
+        assert!(!Path::new(&orphan).exists());
+        assert!(Path::new(&get_blob_path(&blobs_dir, "sha256:b1aa")).exists());
+        assert!(Path::new(&get_blob_path(&blobs_dir, "sha256:c0aa")).exists());
+        rm_rf::remove(&dir).expect("should delete test dir");
+    }
+
+    #[test]
+    fn collect_garbage_fail() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let dir = String::from("./test-artifacts/gc-fail-test/");
+        let add_dir = dir.clone() + "additional/ns/name/v1";
+        fs::create_dir_all(&add_dir).expect("should create test dir");
+        fs::write(add_dir + "/manifest.json", "{\"schemaVersion\": 2,").expect("should write");
+        let blob = get_blob_path(&(dir.clone() + "blobs-store/"), "sha256:c0aa");
+        fs::create_dir_all(Path::new(&blob).parent().unwrap()).expect("should create dir");
+        fs::write(&blob, "config").expect("should write blob");
+
+        // nothing is swept when a manifest can not be parsed
+        let res = collect_garbage(log, dir.clone(), false);
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::DATAERR);
+        assert!(Path::new(&blob).exists());
+        rm_rf::remove(&dir).expect("should delete test dir");
+    }
+}
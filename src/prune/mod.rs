@@ -0,0 +1,3 @@
+pub mod blobs;
+pub mod delete;
+pub mod gc;
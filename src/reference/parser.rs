@@ -0,0 +1,362 @@
+use mirror_catalog_index::ImageReference;
+use std::fmt;
+
+use crate::error::handler::MirrorError;
+
+// references without a registry are pulled from docker hub
+pub const DEFAULT_REGISTRY: &str = "docker.io";
+pub const DEFAULT_TAG: &str = "latest";
+const LEGACY_DEFAULT_REGISTRY: &str = "index.docker.io";
+const OFFICIAL_REPO_PREFIX: &str = "library/";
+const NAME_TOTAL_LENGTH_MAX: usize = 255;
+const TAG_LENGTH_MAX: usize = 128;
+
+// a normalized image reference following the distribution reference grammar
+//   reference := name [ ":" tag ] [ "@" digest ]
+//   name      := [domain '/'] path-component ['/' path-component]*
+// i.e. localhost:5000/a/b/c:v1@sha256:<hex>, ubuntu -> docker.io/library/ubuntu
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub registry: String,
+    // every path component after the registry (namespace and name)
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl Reference {
+    pub fn parse(image: &str) -> Result<Reference, MirrorError> {
+        let invalid = |reason: &str| {
+            MirrorError::Config(format!("invalid image reference {} {}", image, reason))
+        };
+        let (name, digest) = match image.split_once("@") {
+            Some((name, digest)) => (name, Some(digest)),
+            None => (image, None),
+        };
+        let last = name.rfind("/").map(|idx| idx + 1).unwrap_or(0);
+        let (name, tag) = match name[last..].rfind(":") {
+            Some(idx) => (&name[..last + idx], Some(&name[last + idx + 1..])),
+            None => (name, None),
+        };
+        if name.is_empty() {
+            return Err(invalid("(empty name)"));
+        }
+        if name.len() > NAME_TOTAL_LENGTH_MAX {
+            return Err(invalid("(name too long)"));
+        }
+
+        // the first component is a registry when it looks like a host
+        let (registry, repository) = match name.split_once("/") {
+            Some((first, rest))
+                if first.contains(".")
+                    || first.contains(":")
+                    || first == "localhost"
+                    || first.chars().any(|c| c.is_ascii_uppercase()) =>
+            {
+                (first.to_string(), rest.to_string())
+            }
+            _ => (DEFAULT_REGISTRY.to_string(), name.to_string()),
+        };
+        if !is_domain(&registry) {
+            return Err(invalid("(invalid registry)"));
+        }
+        if !repository.split("/").all(is_path_component) {
+            return Err(invalid("(invalid repository)"));
+        }
+        if tag.is_some_and(|t| !is_tag(t)) {
+            return Err(invalid("(invalid tag)"));
+        }
+        if digest.is_some_and(|d| !is_digest(d)) {
+            return Err(invalid("(invalid digest)"));
+        }
+
+        let registry = match registry.as_str() {
+            LEGACY_DEFAULT_REGISTRY => DEFAULT_REGISTRY.to_string(),
+            _ => registry,
+        };
+        // official images are in the library namespace of docker hub
+        let repository = if registry == DEFAULT_REGISTRY && !repository.contains("/") {
+            OFFICIAL_REPO_PREFIX.to_string() + &repository
+        } else {
+            repository
+        };
+        Ok(Reference {
+            registry,
+            repository,
+            tag: tag.map(|t| t.to_string()),
+            digest: digest.map(|d| d.to_string()),
+        })
+    }
+
+    // the path components before the name, empty for registry/name
+    pub fn namespace(&self) -> &str {
+        self.repository
+            .rsplit_once("/")
+            .map(|(ns, _)| ns)
+            .unwrap_or_default()
+    }
+
+    pub fn name(&self) -> &str {
+        self.repository
+            .rsplit_once("/")
+            .map(|(_, name)| name)
+            .unwrap_or(&self.repository)
+    }
+
+    // the manifest to pull, the digest wins over the tag
+    pub fn version(&self) -> String {
+        self.digest
+            .clone()
+            .or_else(|| self.tag.clone())
+            .unwrap_or_else(|| DEFAULT_TAG.to_string())
+    }
+
+    // registry/repository without tag or digest
+    pub fn repo(&self) -> String {
+        self.registry.clone() + "/" + &self.repository
+    }
+
+    pub fn to_image_reference(&self) -> ImageReference {
+        ImageReference {
+            registry: self.registry.clone(),
+            namespace: self.namespace().to_string(),
+            name: self.name().to_string(),
+            version: self.version(),
+        }
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.repo())?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
+    }
+}
+
+// domain := domain-component ['.' domain-component]* [':' port-number]
+// an IPv6 address is enclosed in brackets i.e. [::1]:5000
+fn is_domain(domain: &str) -> bool {
+    let (host, port) = match domain.rsplit_once(":") {
+        Some((host, port)) if !port.contains("]") => (host, Some(port)),
+        _ => (domain, None),
+    };
+    if port.is_some_and(|p| p.is_empty() || !p.chars().all(|c| c.is_ascii_digit())) {
+        return false;
+    }
+    if let Some(addr) = host.strip_prefix("[") {
+        return addr.strip_suffix("]").is_some_and(|a| {
+            !a.is_empty() && a.chars().all(|c| c.is_ascii_hexdigit() || c == ':')
+        });
+    }
+    !host.is_empty()
+        && host.split(".").all(|c| {
+            !c.is_empty()
+                && !c.starts_with("-")
+                && !c.ends_with("-")
+                && c.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+// path-component := [a-z0-9]+ (separator [a-z0-9]+)*
+// separator      := [_.] | __ | [-]*
+fn is_path_component(component: &str) -> bool {
+    let alnum = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    if !component.starts_with(alnum) || !component.ends_with(alnum) {
+        return false;
+    }
+    component
+        .split(alnum)
+        .filter(|sep| !sep.is_empty())
+        .all(|sep| ["_", ".", "__"].contains(&sep) || sep.chars().all(|c| c == '-'))
+}
+
+// tag := [\w][\w.-]{0,127}
+fn is_tag(tag: &str) -> bool {
+    let word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    tag.len() <= TAG_LENGTH_MAX
+        && tag.starts_with(word)
+        && tag.chars().all(|c| word(c) || c == '.' || c == '-')
+}
+
+// digest    := algorithm ":" encoded
+// algorithm := [A-Za-z][A-Za-z0-9]* ([+._-] [A-Za-z][A-Za-z0-9]*)*
+// encoded   := [a-fA-F0-9]{32,} (64 for sha256)
+fn is_digest(digest: &str) -> bool {
+    let (algorithm, encoded) = match digest.split_once(":") {
+        Some(val) => val,
+        None => return false,
+    };
+    let valid_algorithm = algorithm.split(|c| "+._-".contains(c)).all(|c| {
+        c.starts_with(|c: char| c.is_ascii_alphabetic())
+            && c.chars().all(|c| c.is_ascii_alphanumeric())
+    });
+    let valid_length = match algorithm {
+        "sha256" => encoded.len() == 64,
+        _ => encoded.len() >= 32,
+    };
+    valid_algorithm && valid_length && encoded.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use proptest::prelude::*;
+
+    const SHA256: &str = "sha256:5e03f571c5993f0853a910b7c0cab44ec0e451b94a9677ed82e921b54a4b735a";
+
+    #[test]
+    fn parse_reference_pass() {
+        let res = Reference::parse(&format!("localhost:5000/a/b/c:v1@{}", SHA256)).unwrap();
+        assert_eq!(res.registry, "localhost:5000");
+        assert_eq!(res.repository, "a/b/c");
+        assert_eq!(res.namespace(), "a/b");
+        assert_eq!(res.name(), "c");
+        assert_eq!(res.tag.as_deref(), Some("v1"));
+        // the digest wins over the tag
+        assert_eq!(res.version(), SHA256);
+
+        let res = Reference::parse("ubuntu").unwrap();
+        assert_eq!(res.to_string(), "docker.io/library/ubuntu");
+        assert_eq!(res.version(), DEFAULT_TAG);
+        let res = Reference::parse("index.docker.io/user/repo:tag").unwrap();
+        assert_eq!(res.to_string(), "docker.io/user/repo:tag");
+        let res = Reference::parse("localhost/name").unwrap();
+        assert_eq!(res.repo(), "localhost/name");
+        assert_eq!(res.namespace(), "");
+
+        let ir = Reference::parse("registry.redhat.io/redhat/redhat-operator-index:v4.15")
+            .unwrap()
+            .to_image_reference();
+        assert_eq!(ir.registry, "registry.redhat.io");
+        assert_eq!(ir.namespace, "redhat");
+        assert_eq!(ir.name, "redhat-operator-index");
+        assert_eq!(ir.version, "v4.15");
+    }
+
+    // path-component := [a-z0-9]+ (separator [a-z0-9]+)*
+    fn path_component() -> impl Strategy<Value = String> {
+        "[a-z0-9]{1,4}(([._]|__|-{1,3})[a-z0-9]{1,4}){0,2}"
+    }
+
+    fn repository() -> impl Strategy<Value = String> {
+        proptest::collection::vec(path_component(), 1..5).prop_map(|c| c.join("/"))
+    }
+
+    // hosts with a '.' or a port, localhost or an IPv6 address are always a registry
+    fn registry() -> impl Strategy<Value = String> {
+        let host = prop_oneof![
+            "[a-zA-Z0-9]([a-zA-Z0-9-]{0,6}[a-zA-Z0-9])?(\\.[a-zA-Z0-9]([a-zA-Z0-9-]{0,6}[a-zA-Z0-9])?){1,3}",
+            Just(String::from("localhost")),
+            Just(String::from("[::1]")),
+        ];
+        (host, proptest::option::of(any::<u16>()))
+            .prop_map(|(host, port)| match port {
+                Some(port) => format!("{}:{}", host, port),
+                None => host,
+            })
+            .prop_filter("docker hub is normalized", |registry| {
+                registry != DEFAULT_REGISTRY && registry != LEGACY_DEFAULT_REGISTRY
+            })
+    }
+
+    fn tag() -> impl Strategy<Value = String> {
+        "[A-Za-z0-9_][A-Za-z0-9_.-]{0,40}"
+    }
+
+    fn digest() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "sha256:[a-f0-9]{64}",
+            "sha512:[a-f0-9]{128}",
+            "[a-z][a-z0-9]{0,5}([+._-][a-z][a-z0-9]{0,5})?:[a-fA-F0-9]{32,48}"
+                .prop_filter("sha256 has 64 hex digits", |d| !d.starts_with("sha256:")),
+        ]
+    }
+
+    proptest! {
+        // a reference with an explicit registry keeps its parts and is printed back unchanged
+        #[test]
+        fn parse_reference_property_pass(
+            registry in registry(),
+            repository in repository(),
+            tag in proptest::option::of(tag()),
+            digest in proptest::option::of(digest()),
+        ) {
+            let mut image = registry.clone() + "/" + &repository;
+            if let Some(tag) = &tag {
+                image = image + ":" + tag;
+            }
+            if let Some(digest) = &digest {
+                image = image + "@" + digest;
+            }
+            let res = Reference::parse(&image).unwrap();
+            prop_assert_eq!(&res.registry, &registry);
+            prop_assert_eq!(&res.repository, &repository);
+            prop_assert_eq!(&res.tag, &tag);
+            prop_assert_eq!(&res.digest, &digest);
+            prop_assert_eq!(res.repo(), registry + "/" + &repository);
+            prop_assert_eq!(res.to_string(), image);
+            prop_assert_eq!(Reference::parse(&res.to_string()).unwrap(), res);
+        }
+
+        // a reference without a registry is on docker hub, single names in library/
+        #[test]
+        fn parse_reference_docker_hub_pass(
+            components in proptest::collection::vec("[a-z0-9]{1,4}((_|__|-{1,3})[a-z0-9]{1,4}){0,2}", 1..4),
+            tag in proptest::option::of(tag()),
+        ) {
+            let repository = components.join("/");
+            let mut image = repository.clone();
+            if let Some(tag) = &tag {
+                image = image + ":" + tag;
+            }
+            let res = Reference::parse(&image).unwrap();
+            prop_assert_eq!(res.registry.as_str(), DEFAULT_REGISTRY);
+            if components.len() == 1 {
+                prop_assert_eq!(res.repository, String::from(OFFICIAL_REPO_PREFIX) + &repository);
+            } else {
+                prop_assert_eq!(res.repository, repository);
+            }
+            prop_assert_eq!(res.tag, tag);
+            // the legacy docker hub registry is the same registry
+            let legacy = Reference::parse(&(String::from(LEGACY_DEFAULT_REGISTRY) + "/" + &image)).unwrap();
+            prop_assert_eq!(legacy.registry.as_str(), DEFAULT_REGISTRY);
+        }
+    }
+
+    #[test]
+    fn parse_reference_fail() {
+        let long_tag = String::from("quay.io/ns/name:") + &"a".repeat(TAG_LENGTH_MAX + 1);
+        let long_name = String::from("quay.io/") + &"a".repeat(NAME_TOTAL_LENGTH_MAX);
+        for image in [
+            "",
+            ":v1",
+            "quay.io/NS/name",
+            "quay.io/ns/name:",
+            "quay.io/ns/name:-v1",
+            "quay.io/ns/name@sha256:1234",
+            "quay.io/ns/name@sha256",
+            "quay.io/ns/name@1sha:0123456789abcdef0123456789abcdef",
+            "quay.io//name",
+            "quay.io/ns/na..me",
+            "quay.io/ns/name_",
+            "quay.io/ns/na___me",
+            "-quay.io/ns/name",
+            "quay.io:port/ns/name",
+            "quay.io:/ns/name",
+            "[::1/ns/name",
+            long_tag.as_str(),
+            long_name.as_str(),
+        ] {
+            let res = Reference::parse(image);
+            assert!(res.is_err(), "{} should not parse", image);
+            assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+        }
+    }
+}
@@ -0,0 +1,459 @@
+use custom_logger::*;
+use semver::Version;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+
+use crate::config::load::Release;
+use crate::error::handler::MirrorError;
+use crate::reference::parser::Reference;
+
+const CHANNELS_KEY: &str = "io.openshift.upgrades.graph.release.channels";
+// the releases resolved by mirrorToDisk, diskToMirror reads them back from the working-dir
+pub const RESOLVED_RELEASES_FILE: &str = "resolved-releases.json";
+
+// cincinnati graph i.e. https://api.openshift.com/api/upgrades_info/v1/graph
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Graph {
+    #[serde(rename = "nodes")]
+    pub nodes: Vec<Node>,
+
+    // index pairs into nodes (from, to)
+    #[serde(rename = "edges")]
+    pub edges: Vec<(usize, usize)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Node {
+    #[serde(rename = "version")]
+    pub version: String,
+
+    #[serde(rename = "payload")]
+    pub payload: String,
+
+    #[serde(rename = "metadata", default)]
+    pub metadata: HashMap<String, String>,
+}
+
+impl Graph {
+    // read the graph from a local json file or a (local stand-in) cincinnati endpoint
+    pub async fn load(
+        log: &Logging,
+        source: &str,
+        channel: &str,
+        arch: &str,
+    ) -> Result<Graph, MirrorError> {
+        let data = if source.starts_with("http://") || source.starts_with("https://") {
+            log.info(&format!(
+                "fetching upgrade graph {} channel {}",
+                source, channel
+            ));
+            let client = reqwest::Client::new();
+            let res = client
+                .get(source)
+                .query(&[("channel", channel), ("arch", arch)])
+                .header("Accept", "application/json")
+                .send()
+                .await
+                .map_err(|err| MirrorError::RegistryHttp(format!("{} {}", source, err)))?;
+            if !res.status().is_success() {
+                return Err(MirrorError::RegistryHttp(format!(
+                    "{} returned {}",
+                    source,
+                    res.status()
+                )));
+            }
+            res.text()
+                .await
+                .map_err(|err| MirrorError::RegistryHttp(format!("{} {}", source, err)))?
+        } else {
+            fs::read_to_string(source)
+                .map_err(|err| MirrorError::Config(format!("graph {} {}", source, err)))?
+        };
+        let graph: Graph = serde_json::from_str(&data)?;
+        Ok(graph.filter_channel(channel))
+    }
+
+    // a graph file can hold several channels, nodes without channel metadata are kept
+    pub fn filter_channel(&self, channel: &str) -> Graph {
+        let keep: Vec<bool> = self
+            .nodes
+            .iter()
+            .map(|n| match n.metadata.get(CHANNELS_KEY) {
+                Some(channels) => channels.split(",").any(|c| c.trim() == channel),
+                None => true,
+            })
+            .collect();
+        let mut index = HashMap::new();
+        let mut nodes = vec![];
+        for (i, node) in self.nodes.iter().enumerate() {
+            if keep[i] {
+                index.insert(i, nodes.len());
+                nodes.push(node.clone());
+            }
+        }
+        let edges = self
+            .edges
+            .iter()
+            .filter_map(|(from, to)| Some((*index.get(from)?, *index.get(to)?)))
+            .collect();
+        Graph { nodes, edges }
+    }
+
+    // all versions in the graph sorted by semver
+    fn get_versions(&self) -> Result<BTreeMap<Version, usize>, MirrorError> {
+        let mut versions = BTreeMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let version = Version::parse(&node.version).map_err(|err| {
+                MirrorError::ManifestParse(format!("graph version {} {}", node.version, err))
+            })?;
+            versions.insert(version, i);
+        }
+        Ok(versions)
+    }
+
+    // the releases to mirror between min and max (both inclusive)
+    // max defaults to the latest release in the channel, min defaults to max
+    // with shortest_path only the releases on the fewest upgrade hops are returned
+    pub fn resolve(
+        &self,
+        log: &Logging,
+        min: Option<String>,
+        max: Option<String>,
+        shortest_path: bool,
+    ) -> Result<Vec<Node>, MirrorError> {
+        let versions = self.get_versions()?;
+        let parse = |v: &str| {
+            Version::parse(v)
+                .map_err(|err| MirrorError::Config(format!("release version {} {}", v, err)))
+        };
+        let max = match max {
+            Some(v) => parse(&v)?,
+            None => match versions.keys().last() {
+                Some(v) => v.clone(),
+                None => return Err(MirrorError::Config(String::from("upgrade graph is empty"))),
+            },
+        };
+        let min = match min {
+            Some(v) => parse(&v)?,
+            None => max.clone(),
+        };
+        for v in [&min, &max] {
+            if !versions.contains_key(v) {
+                return Err(MirrorError::Config(format!(
+                    "release {} not found in upgrade graph",
+                    v
+                )));
+            }
+        }
+        if min > max {
+            return Err(MirrorError::Config(format!(
+                "release minVersion {} is greater than maxVersion {}",
+                min, max
+            )));
+        }
+        if !shortest_path {
+            return Ok(versions
+                .iter()
+                .filter(|(v, _)| **v >= min && **v <= max)
+                .map(|(_, i)| self.nodes[*i].clone())
+                .collect());
+        }
+
+        // breadth first search from min to max
+        let start = versions[&min];
+        let end = versions[&max];
+        let mut previous: HashMap<usize, usize> = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+        while let Some(current) = queue.pop_front() {
+            if current == end {
+                break;
+            }
+            for (_, to) in self.edges.iter().filter(|(from, _)| *from == current) {
+                if *to != start && !previous.contains_key(to) {
+                    previous.insert(*to, current);
+                    queue.push_back(*to);
+                }
+            }
+        }
+        let mut path = vec![self.nodes[end].clone()];
+        let mut current = end;
+        while current != start {
+            current = *previous.get(&current).ok_or_else(|| {
+                MirrorError::Config(format!("no upgrade path from {} to {}", min, max))
+            })?;
+            path.insert(0, self.nodes[current].clone());
+        }
+        log.debug(&format!(
+            "shortest upgrade path {:?}",
+            path.iter()
+                .map(|n| n.version.as_str())
+                .collect::<Vec<&str>>()
+        ));
+        Ok(path)
+    }
+}
+
+// the release image of a graph node, the configured repository (i.e. a mirror) with the
+// tag suffix of the configured image kept and pinned to the digest of the node payload
+// i.e. quay.io/openshift-release-dev/ocp-release:4.14.6-x86_64 -> ...:4.14.8-x86_64@sha256:...
+pub fn get_release_image(release: &Release, node: &Node) -> Result<String, MirrorError> {
+    let version = &node.version;
+    let reference = Reference::parse(&release.image)?;
+    let tag = reference.tag.as_deref().unwrap_or_default();
+    let suffix = if !release.version.is_empty() && tag.starts_with(&release.version) {
+        &tag[release.version.len()..]
+    } else {
+        tag.rfind("-").map(|idx| &tag[idx..]).unwrap_or_default()
+    };
+    let image = reference.repo() + ":" + version + suffix;
+    let payload = Reference::parse(&node.payload).map_err(|err| {
+        MirrorError::ManifestParse(format!("graph release {} payload {}", version, err))
+    })?;
+    match payload.digest {
+        Some(digest) => Ok(image + "@" + &digest),
+        None => Ok(image),
+    }
+}
+
+// expand releases with a channel to every release on the upgrade path
+pub async fn resolve_releases(
+    log: &Logging,
+    releases: Vec<Release>,
+    architectures: &[String],
+) -> Result<Vec<Release>, MirrorError> {
+    let arch = architectures
+        .first()
+        .cloned()
+        .unwrap_or_else(|| String::from("amd64"));
+    let mut resolved: Vec<Release> = vec![];
+    for release in releases {
+        let channel = match &release.channel {
+            Some(channel) => channel.clone(),
+            None => {
+                resolved.push(release);
+                continue;
+            }
+        };
+        let source = release.graph.clone().ok_or_else(|| {
+            MirrorError::Config(format!(
+                "release channel {} requires a graph file or endpoint",
+                channel
+            ))
+        })?;
+        let graph = Graph::load(log, &source, &channel, &arch).await?;
+        let nodes = graph.resolve(
+            log,
+            release.min_version.clone(),
+            release.max_version.clone(),
+            release.shortest_path,
+        )?;
+        log.info(&format!(
+            "channel {} releases {:?}",
+            channel,
+            nodes
+                .iter()
+                .map(|n| n.version.as_str())
+                .collect::<Vec<&str>>()
+        ));
+        for node in nodes {
+            let image = get_release_image(&release, &node)?;
+            if resolved.iter().any(|r| r.image == image) {
+                continue;
+            }
+            resolved.push(Release {
+                version: node.version,
+                image,
+                channel: None,
+                min_version: None,
+                max_version: None,
+                shortest_path: false,
+                graph: None,
+            });
+        }
+    }
+    Ok(resolved)
+}
+
+// write the resolved releases to the working-dir
+pub fn save_resolved_releases(dir: &str, releases: &[Release]) -> Result<(), MirrorError> {
+    fs::create_dir_all(dir)?;
+    fs::write(
+        dir.to_string() + RESOLVED_RELEASES_FILE,
+        serde_json::to_string_pretty(releases)?,
+    )?;
+    Ok(())
+}
+
+// the releases resolved by mirrorToDisk, releases without a channel are used as they are
+pub fn load_resolved_releases(
+    dir: &str,
+    releases: Vec<Release>,
+) -> Result<Vec<Release>, MirrorError> {
+    if releases.iter().all(|r| r.channel.is_none()) {
+        return Ok(releases);
+    }
+    let file = dir.to_string() + RESOLVED_RELEASES_FILE;
+    let data = fs::read_to_string(&file).map_err(|err| {
+        MirrorError::Config(format!(
+            "release channels are resolved by mirrorToDisk, {} {}",
+            file, err
+        ))
+    })?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    fn get_release(graph: String, shortest_path: bool) -> Release {
+        Release {
+            version: String::from("4.14.6"),
+            image: String::from("quay.io/openshift-release-dev/ocp-release:4.14.6-x86_64"),
+            channel: Some(String::from("stable-4.14")),
+            min_version: Some(String::from("4.14.6")),
+            max_version: Some(String::from("4.14.10")),
+            shortest_path,
+            graph: Some(graph),
+        }
+    }
+
+    #[test]
+    fn resolve_releases_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let graph = String::from("test-artifacts/cincinnati-graph.json");
+
+        // 4.14.9 is only in the fast channel
+        let res = aw!(resolve_releases(
+            log,
+            vec![get_release(graph.clone(), false)],
+            &[]
+        ));
+        let versions: Vec<String> = res.unwrap().into_iter().map(|r| r.version).collect();
+        assert_eq!(versions, vec!["4.14.6", "4.14.7", "4.14.8", "4.14.10"]);
+
+        // the images are pinned to the payload digests of the graph
+        let res = aw!(resolve_releases(log, vec![get_release(graph, true)], &[])).unwrap();
+        let images: Vec<String> = res.iter().map(|r| r.image.clone()).collect();
+        let image = |version: &str, digest: &str| {
+            format!(
+                "quay.io/openshift-release-dev/ocp-release:{}-x86_64@sha256:{:0>64}",
+                version, digest
+            )
+        };
+        assert_eq!(
+            images,
+            vec![
+                image("4.14.6", "1406"),
+                image("4.14.8", "1408"),
+                image("4.14.10", "1410"),
+            ]
+        );
+
+        // the resolved releases are read back without the graph
+        let dir = String::from("./test-artifacts/resolved-releases-test/");
+        let mut release = get_release(String::from("missing-graph.json"), true);
+        assert!(load_resolved_releases(&dir, vec![release.clone()]).is_err());
+        save_resolved_releases(&dir, &res).unwrap();
+        let loaded = load_resolved_releases(&dir, vec![release.clone()]).unwrap();
+        assert_eq!(
+            loaded
+                .iter()
+                .map(|r| r.image.clone())
+                .collect::<Vec<String>>(),
+            images
+        );
+        rm_rf::remove(&dir).expect("should delete test dir");
+        // releases without a channel don't need the resolved releases
+        release.channel = None;
+        let loaded = load_resolved_releases(&dir, vec![release]).unwrap();
+        assert_eq!(loaded.len(), 1);
+    }
+
+    #[test]
+    fn get_release_image_pass() {
+        let sha = "sha256:5e03f571c5993f0853a910b7c0cab44ec0e451b94a9677ed82e921b54a4b735a";
+        let mut node = Node {
+            version: String::from("4.14.8"),
+            payload: String::from("quay.io/openshift-release-dev/ocp-release@") + sha,
+            metadata: HashMap::new(),
+        };
+        // the configured repository is kept, the digest is the one of the payload
+        let mut release = get_release(String::new(), false);
+        release.image =
+            String::from("mirror.example.com/ocp/release:4.14.6-x86_64@sha256:") + &"0".repeat(64);
+        assert_eq!(
+            get_release_image(&release, &node).unwrap(),
+            String::from("mirror.example.com/ocp/release:4.14.8-x86_64@") + sha
+        );
+        release.image = String::from("ocp-release");
+        node.payload = String::from("quay.io/openshift-release-dev/ocp-release:4.14.8");
+        assert_eq!(
+            get_release_image(&release, &node).unwrap(),
+            "docker.io/library/ocp-release:4.14.8"
+        );
+        release.image = String::from("quay.io/openshift-release-dev/ocp-release:-4.14.6");
+        assert!(get_release_image(&release, &node).is_err());
+        release.image = String::from("ocp-release");
+        node.payload = String::new();
+        assert!(get_release_image(&release, &node).is_err());
+    }
+
+    #[test]
+    fn resolve_releases_endpoint_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let mut server = mockito::Server::new();
+        server
+            .mock("GET", "/graph")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("channel".into(), "stable-4.14".into()),
+                mockito::Matcher::UrlEncoded("arch".into(), "arm64".into()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(fs::read_to_string("test-artifacts/cincinnati-graph.json").unwrap())
+            .create();
+        let res = aw!(resolve_releases(
+            log,
+            vec![get_release(server.url() + "/graph", true)],
+            &[String::from("arm64")],
+        ));
+        assert_eq!(res.unwrap().len(), 3);
+    }
+
+    #[test]
+    fn resolve_releases_fail() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let mut release = get_release(String::from("test-artifacts/cincinnati-graph.json"), true);
+        release.max_version = Some(String::from("4.15.0"));
+        let res = aw!(resolve_releases(log, vec![release.clone()], &[]));
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+
+        // min is greater than max with and without shortestPath
+        release.min_version = Some(String::from("4.14.10"));
+        release.max_version = Some(String::from("4.14.6"));
+        let res = aw!(resolve_releases(log, vec![release.clone()], &[]));
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+        release.shortest_path = false;
+        let res = aw!(resolve_releases(log, vec![release.clone()], &[]));
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+
+        release.graph = None;
+        let res = aw!(resolve_releases(log, vec![release], &[]));
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+    }
+}
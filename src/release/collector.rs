@@ -1,18 +1,32 @@
 use custom_logger::*;
 use futures::stream::FuturesUnordered;
 use futures::stream::StreamExt;
-use mirror_auth::*;
 use mirror_catalog_index::*;
 use mirror_copy::*;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::Value;
 use std::fs;
 use std::fs::DirBuilder;
 use std::os::unix::fs::DirBuilderExt;
 use std::path::Path;
 use walkdir::WalkDir;
 
+use crate::api::schema::Report;
+use crate::cluster::resources::{get_manifest_digests, get_mirror_repo};
+use crate::config::destination::{DestinationTemplate, RELEASE_REPOSITORY};
 use crate::config::load::*;
+use crate::error::handler::{registry_error, MirrorError};
+use crate::journal::run_state::*;
+use crate::operator::collector::{
+    get_manifest_url_by_digest, get_operator_fslayers, parse_json_manifestlist,
+};
+use crate::reference::parser::Reference;
+use crate::release::cincinnati::{
+    load_resolved_releases, resolve_releases, save_resolved_releases,
+};
+use crate::scheduler::registry::RegistryScheduler;
+use crate::storage::Storage;
+use crate::stream::registry::{Repository, StreamCopy};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ReleaseSchema {
@@ -67,34 +81,54 @@ pub struct MetaData {
 // collect all operator images
 pub async fn release_mirror_to_disk<T: RegistryInterface>(
     reg_con: T,
+    scheduler: &RegistryScheduler,
     log: &Logging,
-    dir: String,
+    storage: &dyn Storage,
     skip_manifests: bool,
+    state: &mut RunState,
+    architectures: &[String],
     releases: Vec<Release>,
-) {
+) -> Result<Report, MirrorError> {
     log.hi("release collector mode: mirrorToDisk");
+    let dir = storage.local_dir();
+    let mut report = Report::default();
+    let releases = resolve_releases(log, releases, architectures).await?;
+    save_resolved_releases(&dir, &releases)?;
 
     // parse the config
     for release in releases.iter() {
-        let img_ref = convert_release_image_index(log, release.image.clone());
+        let img_ref = convert_release_image_index(log, release.image.clone())?;
         log.debug(&format!("image refs {:#?}", img_ref));
 
         let manifest_json =
             get_manifest_json_file(dir.clone(), img_ref.name.clone(), img_ref.version.clone());
         log.trace(&format!("manifest json file {}", manifest_json));
-        let token = get_token(log, img_ref.clone().registry).await;
         let manifest_url = get_image_manifest_url(img_ref.clone());
         log.trace(&format!("manifest url {}", manifest_url));
-        let manifest = reg_con
-            .get_manifest(manifest_url.clone(), token.clone())
+        let manifest = scheduler
+            .run(log, &manifest_url, |url, token| {
+                reg_con.get_manifest(url, token)
+            })
             .await
-            .unwrap();
-
-        let manifest_dir = manifest_json.split("manifest.json").nth(0).unwrap();
+            .map_err(|err| registry_error(&manifest_url, err))?;
+        let manifest_dir = manifest_json.trim_end_matches("manifest.json");
         log.info(&format!("manifest directory {}", manifest_dir));
-        fs::create_dir_all(manifest_dir).expect("unable to create directory manifest directory");
+        fs::create_dir_all(manifest_dir)?;
+        let mut payloads = get_release_payload_manifests(
+            &reg_con,
+            scheduler,
+            log,
+            &img_ref,
+            manifest,
+            manifest_dir,
+            architectures,
+        )
+        .await?;
+        let manifest = payloads.remove(0);
         let manifest_exists = Path::new(&manifest_json).exists();
-        let res_manifest_in_mem = parse_json_manifest(manifest.clone()).unwrap();
+        let original_ref =
+            img_ref.registry.clone() + "/" + &img_ref.namespace + "/" + &img_ref.name;
+        let (layers, blobs) = get_payload_layers(&manifest, &original_ref)?;
         let working_dir_cache =
             get_cache_dir(dir.clone(), img_ref.name.clone(), img_ref.version.clone());
         let cache_exists = Path::new(&working_dir_cache).exists();
@@ -103,51 +137,66 @@ pub async fn release_mirror_to_disk<T: RegistryInterface>(
         log.info(&format!("blobs-store {} ", sub_dir.clone()));
         let mut exists = true;
         if manifest_exists {
-            let manifest_on_disk = fs::read_to_string(&manifest_json).unwrap();
-            let res_manifest_on_disk = parse_json_manifest(manifest_on_disk).unwrap();
-            if res_manifest_on_disk != res_manifest_in_mem || !cache_exists {
+            let manifest_on_disk = fs::read_to_string(&manifest_json)?;
+            if manifest_on_disk != manifest || !cache_exists {
                 exists = false;
             }
         } else {
             exists = false;
         }
+        // when resuming the index only counts once its checkpoint was recorded
+        if state.resume && !state.is_image_done(&manifest_url) {
+            exists = false;
+        }
         if !exists {
             log.info("detected change in index manifest");
-            fs::write(manifest_json, manifest.clone())
-                .expect("unable to write (index) manifest.json file");
             let blobs_url = get_blobs_url(img_ref.clone());
             // use a concurrent process to get related blobs
-            let response = reg_con
-                .get_blobs(
-                    log,
-                    sub_dir.clone(),
-                    blobs_url,
-                    token.clone(),
-                    res_manifest_in_mem.fs_layers.clone(),
-                )
-                .await;
+            let response = scheduler
+                .get_blobs(&reg_con, log, &sub_dir, &blobs_url, &blobs)
+                .await?;
             log.info(&format!(
                 "completed release image index download {:#?}",
                 response
             ));
             if cache_exists {
-                rm_rf::remove(&working_dir_cache).expect("should delete current untarred cache");
+                rm_rf::remove(&working_dir_cache)
+                    .map_err(|err| MirrorError::Io(format!("{} {:?}", working_dir_cache, err)))?;
             }
 
             let mut builder = DirBuilder::new();
             builder.mode(0o777);
-            builder
-                .create(&working_dir_cache)
-                .expect("unable to create directory");
+            builder.create(&working_dir_cache)?;
 
-            untar_layers(
-                log,
-                sub_dir.clone(),
-                working_dir_cache.clone(),
-                res_manifest_in_mem.fs_layers,
-            )
-            .await;
+            untar_layers(log, sub_dir.clone(), working_dir_cache.clone(), layers).await;
             log.hi("completed untar of layers");
+            // only write the index manifest once the cache is complete
+            fs::write(manifest_json.clone(), manifest.clone())?;
+            state.checkpoint(
+                manifest_url.clone(),
+                blobs.iter().map(|l| l.blob_sum.clone()).collect(),
+            )?;
+        }
+        // the payloads of the other mirrored architectures only need their blobs
+        if !state.metadata_only {
+            for payload in payloads {
+                let (_, blobs) = get_payload_layers(&payload, &original_ref)?;
+                let response = scheduler
+                    .get_blobs(
+                        &reg_con,
+                        log,
+                        &sub_dir,
+                        &get_blobs_url(img_ref.clone()),
+                        &blobs,
+                    )
+                    .await?;
+                log.debug(&format!(
+                    "completed release payload download {:#?}",
+                    response
+                ));
+                report.blobs += blobs.len();
+                report.bytes += blobs.iter().map(|l| l.size.unwrap_or(0)).sum::<i64>();
+            }
         }
 
         // find the directory 'release-manifests'
@@ -163,7 +212,7 @@ pub async fn release_mirror_to_disk<T: RegistryInterface>(
         ));
 
         // parse the image-references json from release-manfests directory
-        let imgs = parse_json_release_imagereference(config_dir + "/image-references");
+        let imgs = parse_json_release_imagereference(config_dir + "/image-references")?;
         log.trace(&format!(
             "images from release-manifests/image-reference {:#?}",
             imgs
@@ -172,181 +221,368 @@ pub async fn release_mirror_to_disk<T: RegistryInterface>(
         // iterate through all the release image-references
         let release_dir =
             dir.clone() + "/" + &img_ref.clone().name + "/" + &img_ref.clone().version + "/";
-        let mut vec_flayer: Vec<FsLayer> = Vec::new();
         let mut vec_common_blobs: Vec<String> = Vec::new();
-        let mut fslayers: HashMap<String, Vec<FsLayer>> = HashMap::new();
-        let blobs_dir = dir.clone() + &"/blobs-store/".to_string();
+        // (component image, blobs url, layers not shared with a previous component)
+        let mut components: Vec<(String, String, Vec<FsLayer>)> = Vec::new();
         let mut manifest: String;
 
-        for img in imgs.unwrap().spec.tags.iter() {
+        for img in imgs.spec.tags.iter() {
             // first check if the release operators exist on disk
             let release_op_dir = release_dir.clone() + "/release/" + &img.name;
             let release_op = release_op_dir.clone() + "/manifest.json";
-            fs::create_dir_all(release_op_dir.clone()).expect("should create release operator dir");
+            let release_list = release_op_dir.clone() + "/manifest-list.json";
+            fs::create_dir_all(release_op_dir.clone())?;
+            // skip images completed in a previous (interrupted) run
+            if state.is_image_done(&img.from.name)
+                && (Path::new(&release_op).exists() || Path::new(&release_list).exists())
+            {
+                log.debug(&format!("checkpoint found for {}", img.name));
+                continue;
+            }
             if !skip_manifests {
-                let manifest_url = get_manifest_url(img.from.name.clone());
+                let manifest_url = get_manifest_url(img.from.name.clone())?;
                 log.trace(&format!("manifest url {:#?}", manifest_url.clone()));
                 // use the RegistryInterface to make the call
-                manifest = reg_con
-                    .get_manifest(manifest_url.clone(), token.clone())
+                manifest = scheduler
+                    .run(log, &manifest_url, |url, token| {
+                        reg_con.get_manifest(url, token)
+                    })
                     .await
-                    .unwrap();
+                    .map_err(|err| registry_error(&manifest_url, err))?;
 
                 log.info(&format!("checking manifest {:#?}", img.name.clone()));
                 log.trace(&format!("manifest contents {:#?}", manifest));
-                let metadata = fs::metadata(release_op);
-
-                if metadata.is_ok() {
-                    let meta = metadata.as_ref().unwrap();
-                    if meta.len() != manifest.len() as u64 {
-                        log.info(&format!("writing manifest for {:#?}", img.name.clone()));
-                        fs::write(release_op_dir + "/manifest.json", manifest.clone())
-                            .expect("unable to write manifest.json file");
-                    }
-                } else if metadata.is_err() {
-                    log.info(&format!("writing  manifest {:#?}", img.name.clone()));
-                    fs::write(release_op_dir + "/manifest.json", manifest.clone())
-                        .expect("unable to write manifest.json file");
-                }
+            } else if Path::new(&release_list).exists() {
+                manifest = fs::read_to_string(release_list.clone())?;
             } else {
-                manifest = fs::read_to_string(release_op.clone()).expect("should read manifest");
+                manifest = fs::read_to_string(release_op.clone())?;
             }
+            report.images += 1;
 
-            let op_manifest = parse_json_manifest_operator(manifest.clone()).unwrap();
-            let origin_tmp = img.from.name.split("@");
-            let origin = origin_tmp.clone().nth(0).unwrap();
+            let op_manifests = get_release_component_manifests(
+                &reg_con,
+                scheduler,
+                log,
+                &img.from.name,
+                manifest,
+                release_op_dir,
+                skip_manifests,
+                architectures,
+            )
+            .await?;
+            let origin = img.from.name.split("@").nth(0).unwrap_or_default();
             let op_url = get_blobs_url_by_string(img.from.name.clone());
+            let mut vec_flayer: Vec<FsLayer> = Vec::new();
 
-            for layer in op_manifest.layers.unwrap().iter() {
-                // check for duplicates
-                if vec_common_blobs.contains(&layer.digest) {
-                    continue;
+            for op_manifest in op_manifests {
+                for layer in op_manifest.layers.unwrap_or_default().iter() {
+                    // check for duplicates
+                    if vec_common_blobs.contains(&layer.digest) {
+                        continue;
+                    }
+                    vec_common_blobs.push(layer.digest.clone());
+                    // convert op_manifest.layer to FsLayer
+                    let fslayer = FsLayer {
+                        blob_sum: layer.digest.clone(),
+                        original_ref: Some(origin.to_string()),
+                        size: Some(layer.size),
+                    };
+                    vec_flayer.insert(0, fslayer);
                 }
-                vec_common_blobs.push(layer.digest.clone());
-                // convert op_manifest.layer to FsLayer
-                let fslayer = FsLayer {
-                    blob_sum: layer.digest.clone(),
+                // add configs
+                let config = op_manifest.config.ok_or_else(|| {
+                    MirrorError::ManifestParse(format!("manifest without config for {}", img.name))
+                })?;
+                let cfg = FsLayer {
+                    blob_sum: config.digest,
                     original_ref: Some(origin.to_string()),
-                    size: Some(layer.size),
+                    size: Some(config.size),
                 };
-                vec_flayer.insert(0, fslayer);
+                vec_flayer.insert(0, cfg);
             }
-            // add configs
-            let config = op_manifest.config.unwrap();
-            let cfg = FsLayer {
-                blob_sum: config.digest,
-                original_ref: Some(origin.to_string()),
-                size: Some(config.size),
-            };
-            vec_flayer.insert(0, cfg);
-            // finally add the fslayers to the hashmap
-            fslayers.insert(op_url.clone(), vec_flayer.clone());
+            // one checkpoint per component, the components share the blobs url
             log.trace(&format!("blobs_url {}", op_url));
-            log.trace(&format!("fslayer for {} {:#?}", img.name, fslayers));
+            log.trace(&format!("fslayer for {} {:#?}", img.name, vec_flayer));
+            report.blobs += vec_flayer.len();
+            report.bytes += vec_flayer.iter().map(|l| l.size.unwrap_or(0)).sum::<i64>();
+            components.push((img.from.name.clone(), op_url, vec_flayer));
+        }
+
+        if state.metadata_only {
+            components.clear();
         }
+        // the journal as it was loaded, its blobs are not fetched again
+        let recorded = state.clone();
 
         // get blobs in batch of 8
         // each future handles get_blobs api call
         // with 8 threads (one per digest)
         let mut futs = FuturesUnordered::new();
-        let batch_size = 8;
-        for (k, v) in fslayers.iter() {
+        let batch_size = scheduler.max_in_flight();
+        for (image, url, layers) in components {
             // batch the calls
-            futs.push(reg_con.get_blobs(
-                log,
-                blobs_dir.clone(),
-                k.to_string(),
-                token.clone(),
-                v.clone(),
+            futs.push(get_blobs_tracked(
+                &reg_con, scheduler, log, storage, url, image, layers, &recorded,
             ));
             if futs.len() >= batch_size {
-                let response = futs.next().await.unwrap();
+                let (image, blobs, response) = futs.next().await.unwrap();
+                let response = response?;
+                state.checkpoint(image, blobs)?;
                 log.debug(&format!(
                     "completed batch of {} {:#?}",
-                    batch_size,
-                    response.unwrap()
+                    batch_size, response
                 ));
             }
         }
         // Wait for the remaining to finish.
-        while let Some(response) = futs.next().await {
-            log.debug(&format!("completed rest of batch {:#?}", response.unwrap()));
+        while let Some((image, blobs, response)) = futs.next().await {
+            let response = response?;
+            state.checkpoint(image, blobs)?;
+            log.debug(&format!("completed rest of batch {:#?}", response));
         }
     }
+    Ok(report)
 }
 
 pub async fn release_disk_to_mirror<T: RegistryInterface>(
     reg_con: T,
+    scheduler: &RegistryScheduler,
     log: &Logging,
     dir: String,
     destination_url: String,
+    template: &DestinationTemplate,
     releases: Vec<Release>,
-) -> String {
+) -> Result<Report, MirrorError> {
+    let mut report = Report::default();
+    let sub_component = template.get_sub_component(None, RELEASE_REPOSITORY);
+    // the releases are not resolved again, the graph is not available disconnected
+    let releases = load_resolved_releases(&dir, releases)?;
+    // manifest lists are pushed with the scheduler once the manifests they list were pushed
+    let manifests = get_manifest_digests(&dir)?;
+    let dest = Repository::new(&get_mirror_repo(&destination_url, &sub_component))?;
+    let mut copy = StreamCopy::new(&(dir.clone() + "blobs-store/"), scheduler);
     for release in releases {
-        let release_dir = dir.clone() + &get_dir_from_isc(release.image.clone());
+        let release_dir = dir.clone() + &get_dir_from_isc(release.image.clone())?;
         log.debug(&format!("release directory {}", release_dir.clone()));
-        let manifests = get_all_assosciated_manifests(log, release_dir);
+        let mm_list = get_all_assosciated_manifests(log, release_dir.clone());
         // using map and collect are not async
-        for mm in manifests.iter() {
+        for mm in mm_list.iter() {
             // we can infer some info from the manifest
             let binding = mm.to_string();
-            let manifest = get_release_manifest(binding.clone());
+            let manifest = get_release_manifest(binding.clone())?;
             log.trace(&format!("manifest struct {:#?}", manifest));
             log.trace(&format!("directory {}", binding));
-            let _res = reg_con
+            reg_con
                 .push_image(
                     log,
                     dir.clone(),
-                    String::from("ocp-release"),
+                    sub_component.clone(),
                     destination_url.clone(),
                     String::from(""),
                     manifest.clone(),
                 )
-                .await;
+                .await
+                .map_err(|err| {
+                    MirrorError::RegistryHttp(format!("{} {:?}", destination_url, err))
+                })?;
+            report.images += 1;
+        }
+        // the multi-arch components are referenced by the digest of their manifest list
+        for list in get_manifest_lists(log, release_dir) {
+            copy.push_manifest_list(log, &dest, &fs::read_to_string(&list)?, None, &manifests)
+                .await?;
+            report.images += 1;
         }
     }
-    String::from("ok")
+    Ok(report)
 }
 
 // utility functions
 
-pub fn parse_json_release_imagereference(
-    file: String,
-) -> Result<ReleaseSchema, Box<dyn std::error::Error>> {
-    let data = fs::read_to_string(&file)
-        .expect("should read release-manifests/image-references json file");
+// multi-arch release payloads are manifest lists, the payload of every mirrored architecture
+// is written to dir (manifest-<arch>.json) with the list filtered to those architectures
+// the release-manifests are read from the first payload returned
+async fn get_release_payload_manifests<T: RegistryInterface>(
+    reg_con: &T,
+    scheduler: &RegistryScheduler,
+    log: &Logging,
+    img_ref: &ImageReference,
+    manifest: String,
+    dir: &str,
+    architectures: &[String],
+) -> Result<Vec<String>, MirrorError> {
+    let ml = match parse_json_manifestlist(manifest.clone()) {
+        Ok(ml) if ml.is_list() => ml,
+        _ => return Ok(vec![manifest]),
+    };
+    let mut payloads = vec![];
+    let mut mirrored = vec![];
+    for mf in ml.manifests.iter() {
+        let arch = match mf.platform.as_ref() {
+            Some(platform) if is_architecture_mirrored(architectures, &platform.architecture) => {
+                platform.architecture.clone()
+            }
+            _ => continue,
+        };
+        let digest = mf.digest.clone().ok_or_else(|| {
+            MirrorError::ManifestParse(format!(
+                "manifest list entry without digest for {}",
+                img_ref.name
+            ))
+        })?;
+        let mut sub_ir = img_ref.clone();
+        sub_ir.version = digest.clone();
+        let url = get_image_manifest_url(sub_ir);
+        log.info(&format!("multi-arch release payload {} {}", arch, url));
+        let payload = scheduler
+            .run(log, &url, |url, token| reg_con.get_manifest(url, token))
+            .await
+            .map_err(|err| registry_error(&url, err))?;
+        fs::write(dir.to_string() + "manifest-" + &arch + ".json", &payload)?;
+        payloads.push(payload);
+        mirrored.push(digest);
+    }
+    if payloads.is_empty() {
+        return Err(MirrorError::Config(format!(
+            "release {} has no payload for architectures {:?}",
+            img_ref.name, architectures
+        )));
+    }
+    // the list only references the payloads that are mirrored
+    let mut list: Value = serde_json::from_str(&manifest)?;
+    let entries: Vec<Value> = list["manifests"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|mf| mirrored.iter().any(|d| mf["digest"].as_str() == Some(d)))
+        .collect();
+    list["manifests"] = Value::Array(entries);
+    fs::write(
+        dir.to_string() + "manifest-list.json",
+        serde_json::to_string(&list)?,
+    )?;
+    Ok(payloads)
+}
+
+// the layers of a release payload in the order they are untarred and all of its blobs
+// payloads resolved from a manifest list (by digest) are schema 2, a single
+// architecture release can still be served as schema 1
+fn get_payload_layers(
+    payload: &str,
+    original_ref: &str,
+) -> Result<(Vec<FsLayer>, Vec<FsLayer>), MirrorError> {
+    let value: Value = serde_json::from_str(payload)?;
+    if value["schemaVersion"].as_i64() == Some(1) {
+        let layers = parse_json_manifest(payload.to_string())
+            .map_err(|err| MirrorError::ManifestParse(format!("{} {}", original_ref, err)))?
+            .fs_layers;
+        return Ok((layers.clone(), layers));
+    }
+    let manifest = parse_json_manifest_operator(payload.to_string())
+        .map_err(|err| MirrorError::ManifestParse(format!("{} {}", original_ref, err)))?;
+    let layers = manifest
+        .layers
+        .clone()
+        .unwrap_or_default()
+        .iter()
+        .map(|layer| FsLayer {
+            blob_sum: layer.digest.clone(),
+            original_ref: Some(original_ref.to_string()),
+            size: Some(layer.size),
+        })
+        .collect();
+    let blobs = get_operator_fslayers(manifest, original_ref.to_string(), &mut vec![])?;
+    Ok((layers, blobs))
+}
+
+// write the manifest of a release component, manifest lists are resolved per mirrored architecture
+async fn get_release_component_manifests<T: RegistryInterface>(
+    reg_con: &T,
+    scheduler: &RegistryScheduler,
+    log: &Logging,
+    image: &str,
+    manifest: String,
+    dir: String,
+    skip_manifests: bool,
+    architectures: &[String],
+) -> Result<Vec<Manifest>, MirrorError> {
+    let ml = match parse_json_manifestlist(manifest.clone()) {
+        Ok(ml) if ml.is_list() => ml,
+        _ => {
+            let release_op = dir + "/manifest.json";
+            // only write the manifest if it changed
+            let changed = fs::metadata(&release_op)
+                .map(|meta| meta.len() != manifest.len() as u64)
+                .unwrap_or(true);
+            if !skip_manifests && changed {
+                fs::write(&release_op, manifest.clone())?;
+            }
+            let op_manifest = parse_json_manifest_operator(manifest)
+                .map_err(|err| MirrorError::ManifestParse(format!("{} {}", image, err)))?;
+            return Ok(vec![op_manifest]);
+        }
+    };
+    if !skip_manifests {
+        fs::write(dir.clone() + "/manifest-list.json", manifest)?;
+    }
+    let mut manifests = vec![];
+    for mf in ml.manifests.iter() {
+        let platform = mf.platform.clone().ok_or_else(|| {
+            MirrorError::ManifestParse(format!(
+                "manifest list entry without platform for {}",
+                image
+            ))
+        })?;
+        if !is_architecture_mirrored(architectures, &platform.architecture) {
+            continue;
+        }
+        let file = dir.clone() + "/manifest-" + &platform.architecture + ".json";
+        let local_manifest = if skip_manifests {
+            fs::read_to_string(&file)?
+        } else {
+            let digest = mf.digest.clone().ok_or_else(|| {
+                MirrorError::ManifestParse(format!(
+                    "manifest list entry without digest for {}",
+                    image
+                ))
+            })?;
+            let url = get_manifest_url_by_digest(image.to_string(), digest)?;
+            let local_manifest = scheduler
+                .run(log, &url, |url, token| reg_con.get_manifest(url, token))
+                .await
+                .map_err(|err| registry_error(&url, err))?;
+            fs::write(&file, local_manifest.clone())?;
+            local_manifest
+        };
+        let op_manifest = parse_json_manifest_operator(local_manifest)
+            .map_err(|err| MirrorError::ManifestParse(format!("{} {}", file, err)))?;
+        manifests.push(op_manifest);
+    }
+    Ok(manifests)
+}
+
+pub fn parse_json_release_imagereference(file: String) -> Result<ReleaseSchema, MirrorError> {
+    let data = fs::read_to_string(&file)?;
     // Parse the string of data into ReleaseSchema
     let root: ReleaseSchema = serde_json::from_str(&data)?;
     Ok(root)
 }
 
-fn get_dir_from_isc(release: String) -> String {
-    let res = release.split("/");
-    let collection = res.clone().collect::<Vec<&str>>();
-    let name = collection[2].split(":");
-    let result = name.clone().nth(0).unwrap().to_string()
-        + "/"
-        + name.clone().nth(1).unwrap()
-        + &"/release/";
-    result
+// the release dir in the working-dir i.e. ocp-release/4.14.6-x86_64/release/
+pub fn get_dir_from_isc(release: String) -> Result<String, MirrorError> {
+    let reference = Reference::parse(&release)?;
+    Ok(reference.name().to_string() + "/" + &reference.version() + "/release/")
 }
 
-// parse_release_image_index - best attempt to parse image index and return catalog reference
-pub fn convert_release_image_index(log: &Logging, release: String) -> ImageReference {
-    let hld = &mut release.split("/");
-    let reg = hld.nth(0).unwrap();
-    let ns = hld.nth(0).unwrap();
-    let mut index = hld.nth(0).unwrap().split(":");
-    let name = index.nth(0).unwrap();
-    let ver = index.nth(0).unwrap();
-    let ir = ImageReference {
-        registry: reg.to_string(),
-        namespace: ns.to_string(),
-        name: name.to_string(),
-        version: ver.to_string(),
-    };
+// parse_release_image_index - parse the release image of the config
+pub fn convert_release_image_index(
+    log: &Logging,
+    release: String,
+) -> Result<ImageReference, MirrorError> {
+    let ir = Reference::parse(&release)?.to_image_reference();
     log.trace(&format!("image reference {:#?}", ir));
-    ir
+    Ok(ir)
 }
 
 // contruct the manifest url
@@ -356,8 +592,11 @@ pub fn get_image_manifest_url(image_ref: ImageReference) -> String {
     let mut url = String::from("https://");
     url.push_str(&image_ref.registry);
     url.push_str(&"/v2/");
-    url.push_str(&image_ref.namespace);
-    url.push_str(&"/");
+    // images directly below the registry have no namespace
+    if !image_ref.namespace.is_empty() {
+        url.push_str(&image_ref.namespace);
+        url.push_str(&"/");
+    }
     url.push_str(&image_ref.name);
     url.push_str(&"/");
     url.push_str(&"manifests/");
@@ -366,20 +605,10 @@ pub fn get_image_manifest_url(image_ref: ImageReference) -> String {
 }
 
 // contruct a manifest url from a string
-pub fn get_manifest_url(url: String) -> String {
-    let mut parts = url.split("/");
-    let mut url = String::from("https://");
-    url.push_str(&parts.nth(0).unwrap());
-    url.push_str(&"/v2/");
-    url.push_str(&parts.nth(0).unwrap());
-    url.push_str(&"/");
-    let i = parts.nth(0).unwrap();
-    let mut sha = i.split("@");
-    url.push_str(&sha.nth(0).unwrap());
-    url.push_str(&"/");
-    url.push_str(&"manifests/");
-    url.push_str(&sha.nth(0).unwrap());
-    url
+pub fn get_manifest_url(url: String) -> Result<String, MirrorError> {
+    Ok(get_image_manifest_url(
+        Reference::parse(&url)?.to_image_reference(),
+    ))
 }
 
 pub fn parse_json_manifest_operator(data: String) -> Result<Manifest, Box<dyn std::error::Error>> {
@@ -392,8 +621,8 @@ pub fn get_all_assosciated_manifests(log: &Logging, dir: String) -> Vec<String>
     let mut vec_manifests: Vec<String> = vec![];
     let result = WalkDir::new(&dir);
     for file in result.into_iter().filter_map(|file| file.ok()) {
-        if file.metadata().unwrap().is_file() & !file.path().display().to_string().contains("list")
-        {
+        let is_file = file.metadata().map(|m| m.is_file()).unwrap_or(false);
+        if is_file & !file.path().display().to_string().contains("list") {
             log.debug(&format!(
                 "assosciated manifest found {:#?}",
                 file.path().display().to_string()
@@ -404,43 +633,466 @@ pub fn get_all_assosciated_manifests(log: &Logging, dir: String) -> Vec<String>
     vec_manifests
 }
 
-fn get_release_manifest(dir: String) -> Manifest {
-    let data = fs::read_to_string(&dir).expect("should read release-operator-manifest json file");
-    let release_manifest = parse_json_manifest_operator(data).unwrap();
-    release_manifest
+// the manifest lists below dir, they are pushed after the manifests they list
+pub fn get_manifest_lists(log: &Logging, dir: String) -> Vec<String> {
+    let mut lists: Vec<String> = vec![];
+    for file in WalkDir::new(&dir).into_iter().filter_map(|file| file.ok()) {
+        if file.file_name() == "manifest-list.json" && file.path().is_file() {
+            log.debug(&format!("manifest list found {}", file.path().display()));
+            lists.push(file.path().display().to_string());
+        }
+    }
+    lists
+}
+
+fn get_release_manifest(dir: String) -> Result<Manifest, MirrorError> {
+    let data = fs::read_to_string(&dir)?;
+    let release_manifest = parse_json_manifest_operator(data)
+        .map_err(|err| MirrorError::ManifestParse(format!("{} {}", dir, err)))?;
+    Ok(release_manifest)
 }
 
 #[cfg(test)]
 mod tests {
     // this brings everything from parent's scope into this scope
     use super::*;
+    use crate::auth::token_cache::TokenCache;
+    use crate::config::registries::SourceMirrors;
+    use crate::scheduler::registry::SchedulerConfig;
+    use crate::storage::local::LocalStorage;
+    use crate::verify::blobs::get_blob_path;
+    use async_trait::async_trait;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
 
     #[test]
     fn mirror_to_disk_pass() {
-        let _log = &Logging {
-            log_level: Level::TRACE,
+        // serves the multi-arch release and writes the blobs it is asked for
+        #[derive(Clone)]
+        struct Fake {
+            list: String,
+            payloads: Vec<(String, String)>,
+            blobs: Vec<(String, Vec<u8>)>,
+            requested: Arc<Mutex<Vec<String>>>,
+        }
+
+        #[async_trait]
+        impl RegistryInterface for Fake {
+            async fn get_manifest(
+                &self,
+                url: String,
+                _token: String,
+            ) -> Result<String, Box<dyn std::error::Error>> {
+                let payload = self
+                    .payloads
+                    .iter()
+                    .find(|(digest, _)| url.ends_with(digest.as_str()));
+                match payload {
+                    Some((_, payload)) => Ok(payload.clone()),
+                    None => Ok(self.list.clone()),
+                }
+            }
+
+            async fn get_blobs(
+                &self,
+                _log: &Logging,
+                dir: String,
+                _url: String,
+                _token: String,
+                layers: Vec<FsLayer>,
+            ) -> Result<String, Box<dyn std::error::Error>> {
+                for layer in layers {
+                    self.requested.lock().unwrap().push(layer.blob_sum.clone());
+                    if let Some((_, data)) = self.blobs.iter().find(|(d, _)| *d == layer.blob_sum) {
+                        let path = get_blob_path(&dir, &layer.blob_sum);
+                        fs::create_dir_all(Path::new(&path).parent().unwrap())?;
+                        fs::write(&path, data)?;
+                    }
+                }
+                Ok(String::from("test"))
+            }
+
+            async fn push_image(
+                &self,
+                _log: &Logging,
+                _dir: String,
+                _subdir: String,
+                _url: String,
+                _token: String,
+                _manifest: Manifest,
+            ) -> Result<String, mirror_copy::MirrorError> {
+                Ok(String::from("test"))
+            }
+        }
+
+        let log = &Logging {
+            log_level: Level::INFO,
         };
+        let dir = String::from("./test-artifacts/release-mirror-test/");
+        let _ = fs::remove_dir_all(&dir);
+        let digest = |data: &[u8]| String::from("sha256:") + &hex::encode(Sha256::digest(data));
 
-        // we set up a mock server for the auth-credentials
-        let mut server = mockito::Server::new();
-        let _url = server.url();
-
-        // Create a mock
-        server
-            .mock("GET", "/auth")
-            .with_status(200)
-            .with_header("content-type", "application/json")
-            .with_body(
-                "{
-                    \"token\": \"test\",
-                    \"access_token\": \"aebcdef1234567890\",
-                    \"expires_in\":300,
-                    \"issued_at\":\"2023-10-20T13:23:31Z\"
-                }",
+        // the amd64 payload holds the release-manifests, the s390x payload only a layer
+        let refs = json!({
+            "kind": "ImageStream",
+            "apiVersion": "image.openshift.io/v1",
+            "metadata": { "name": "4.15.0", "creationTimestamp": "2024-02-27T00:00:00Z" },
+            "spec": { "lookupPolicy": { "local": false }, "tags": [] }
+        })
+        .to_string();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(refs.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        builder
+            .append_data(
+                &mut header,
+                "release-manifests/image-references",
+                refs.as_bytes(),
             )
-            .create();
+            .unwrap();
+        let amd64_layer = builder.into_inner().unwrap().finish().unwrap();
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(b"s390x layer").unwrap();
+        let s390x_layer = enc.finish().unwrap();
+        let amd64_config = b"{\"architecture\":\"amd64\"}".to_vec();
+        let s390x_config = b"{\"architecture\":\"s390x\"}".to_vec();
+        let payload = |config: &[u8], layer: &[u8]| {
+            json!({
+                "schemaVersion": 2,
+                "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                "config": {
+                    "mediaType": "application/vnd.docker.container.image.v1+json",
+                    "size": config.len(),
+                    "digest": digest(config)
+                },
+                "layers": [{
+                    "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                    "size": layer.len(),
+                    "digest": digest(layer)
+                }]
+            })
+            .to_string()
+        };
+
+        let fake = Fake {
+            list: fs::read_to_string("test-artifacts/simulate-api-call/manifest-list-kube.json")
+                .expect("should read test manifest-list file"),
+            payloads: vec![
+                (
+                    String::from(
+                        "sha256:20bfb196dcbb88f23fb90905eba48d6116371cf5c25791b7462caf093a5e34d7",
+                    ),
+                    payload(&amd64_config, &amd64_layer),
+                ),
+                (
+                    String::from(
+                        "sha256:c95725d9530fca76627c3e9aff95b09db700cb9397dd643c8426cfd2c272ae75",
+                    ),
+                    payload(&s390x_config, &s390x_layer),
+                ),
+            ],
+            blobs: vec![
+                (digest(&amd64_config), amd64_config.clone()),
+                (digest(&amd64_layer), amd64_layer.clone()),
+                (digest(&s390x_config), s390x_config.clone()),
+                (digest(&s390x_layer), s390x_layer.clone()),
+            ],
+            requested: Arc::new(Mutex::new(vec![])),
+        };
+        let releases = vec![Release {
+            version: String::new(),
+            image: String::from("quay.io/openshift-release-dev/ocp-release:4.15.0-multi"),
+            channel: None,
+            min_version: None,
+            max_version: None,
+            shortest_path: false,
+            graph: None,
+        }];
+        let mut state = RunState::default();
+        let report = aw!(release_mirror_to_disk(
+            fake.clone(),
+            &RegistryScheduler::new(
+                SchedulerConfig::default(),
+                &[],
+                SourceMirrors::default(),
+                TokenCache::anonymous()
+            ),
+            log,
+            &LocalStorage::new(&dir),
+            false,
+            &mut state,
+            &[String::from("amd64"), String::from("s390x")],
+            releases,
+        ))
+        .unwrap();
+
+        // the amd64 layer is untarred into the cache
+        let cache = get_cache_dir(
+            dir.clone(),
+            String::from("ocp-release"),
+            String::from("4.15.0-multi"),
+        );
+        let untarred = WalkDir::new(&cache)
+            .into_iter()
+            .filter_map(|file| file.ok())
+            .find(|file| file.file_name() == "image-references")
+            .expect("should untar the release-manifests");
+        assert_eq!(fs::read_to_string(untarred.path()).unwrap(), refs);
+
+        // the layers and config of every mirrored architecture are fetched
+        let requested = fake.requested.lock().unwrap().clone();
+        for blob in [&amd64_config, &amd64_layer, &s390x_config, &s390x_layer] {
+            assert!(requested.contains(&digest(blob)));
+            assert!(Path::new(&get_blob_path(
+                &(dir.clone() + "blobs-store/"),
+                &digest(blob)
+            ))
+            .exists());
+        }
+        assert_eq!(requested.len(), 4);
+        assert_eq!(report.blobs, 2);
+        assert!(state.is_blob_done(&digest(&amd64_layer)));
+        rm_rf::remove(&dir).expect("should delete test dir");
+    }
+
+    #[test]
+    fn get_release_component_manifests_pass() {
+        #[derive(Clone)]
+        struct Fake {}
+
+        #[async_trait]
+        impl RegistryInterface for Fake {
+            async fn get_manifest(
+                &self,
+                _url: String,
+                _token: String,
+            ) -> Result<String, Box<dyn std::error::Error>> {
+                let content =
+                    fs::read_to_string("test-artifacts/simulate-api-call/manifest-amd64.json")
+                        .expect("should read test manifest-amd64 file");
+                Ok(content)
+            }
+
+            async fn get_blobs(
+                &self,
+                _log: &Logging,
+                _dir: String,
+                _url: String,
+                _token: String,
+                _layers: Vec<FsLayer>,
+            ) -> Result<String, Box<dyn std::error::Error>> {
+                Ok(String::from("test"))
+            }
 
-        //#[derive(Clone)]
-        //struct Fake {}
+            async fn push_image(
+                &self,
+                _log: &Logging,
+                _dir: String,
+                _subdir: String,
+                _url: String,
+                _token: String,
+                _manifest: Manifest,
+            ) -> Result<String, mirror_copy::MirrorError> {
+                Ok(String::from("test"))
+            }
+        }
+
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let scheduler = RegistryScheduler::new(
+            SchedulerConfig::default(),
+            &[],
+            SourceMirrors::default(),
+            TokenCache::anonymous(),
+        );
+        let dir = String::from("./test-artifacts/release-arch-test");
+        fs::create_dir_all(&dir).expect("should create test dir");
+        let list = fs::read_to_string("test-artifacts/simulate-api-call/manifest-list.json")
+            .expect("should read test manifest-list file");
+        let image = "quay.io/openshift-release-dev/ocp-v4.0-art-dev@sha256:5e03f571c5993f0853a910b7c0cab44ec0e451b94a9677ed82e921b54a4b735a";
+
+        // the list only has an amd64 manifest
+        let res = aw!(get_release_component_manifests(
+            &Fake {},
+            &scheduler,
+            log,
+            image,
+            list.clone(),
+            dir.clone(),
+            false,
+            &[String::from("arm64")],
+        ))
+        .unwrap();
+        assert_eq!(res.len(), 0);
+        assert!(!Path::new(&(dir.clone() + "/manifest-amd64.json")).exists());
+
+        let res = aw!(get_release_component_manifests(
+            &Fake {},
+            &scheduler,
+            log,
+            image,
+            list.clone(),
+            dir.clone(),
+            false,
+            &[String::from("amd64")],
+        ))
+        .unwrap();
+        assert_eq!(res.len(), 1);
+        assert!(Path::new(&(dir.clone() + "/manifest-list.json")).exists());
+        assert!(Path::new(&(dir.clone() + "/manifest-amd64.json")).exists());
+        rm_rf::remove(&dir).expect("should delete test dir");
+
+        // an oci image index is resolved like a manifest list
+        fs::create_dir_all(&dir).expect("should create test dir");
+        let index = list.replace(
+            "application/vnd.docker.distribution.manifest.list.v2+json",
+            "application/vnd.oci.image.index.v1+json",
+        );
+        assert_ne!(index, list);
+        let res = aw!(get_release_component_manifests(
+            &Fake {},
+            &scheduler,
+            log,
+            image,
+            index,
+            dir.clone(),
+            false,
+            &[String::from("amd64")],
+        ))
+        .unwrap();
+        assert_eq!(res.len(), 1);
+        assert!(Path::new(&(dir.clone() + "/manifest-amd64.json")).exists());
+        assert!(!Path::new(&(dir.clone() + "/manifest.json")).exists());
+        rm_rf::remove(&dir).expect("should delete test dir");
+    }
+
+    #[test]
+    fn get_release_payload_manifests_pass() {
+        #[derive(Clone)]
+        struct Fake {}
+
+        #[async_trait]
+        impl RegistryInterface for Fake {
+            async fn get_manifest(
+                &self,
+                url: String,
+                _token: String,
+            ) -> Result<String, Box<dyn std::error::Error>> {
+                let arch = if url
+                    .contains("20bfb196dcbb88f23fb90905eba48d6116371cf5c25791b7462caf093a5e34d7")
+                {
+                    "amd64"
+                } else {
+                    "s390x"
+                };
+                let content = fs::read_to_string(format!(
+                    "test-artifacts/simulate-api-call/manifest-{}-kube.json",
+                    arch
+                ))
+                .expect("should read test manifest file");
+                Ok(content)
+            }
+
+            async fn get_blobs(
+                &self,
+                _log: &Logging,
+                _dir: String,
+                _url: String,
+                _token: String,
+                _layers: Vec<FsLayer>,
+            ) -> Result<String, Box<dyn std::error::Error>> {
+                Ok(String::from("test"))
+            }
+
+            async fn push_image(
+                &self,
+                _log: &Logging,
+                _dir: String,
+                _subdir: String,
+                _url: String,
+                _token: String,
+                _manifest: Manifest,
+            ) -> Result<String, mirror_copy::MirrorError> {
+                Ok(String::from("test"))
+            }
+        }
+
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let scheduler = RegistryScheduler::new(
+            SchedulerConfig::default(),
+            &[],
+            SourceMirrors::default(),
+            TokenCache::anonymous(),
+        );
+        let dir = String::from("./test-artifacts/release-payload-test/");
+        fs::create_dir_all(&dir).expect("should create test dir");
+        let list = fs::read_to_string("test-artifacts/simulate-api-call/manifest-list-kube.json")
+            .expect("should read test manifest-list file");
+        let ir = convert_release_image_index(
+            log,
+            String::from("quay.io/openshift-release-dev/ocp-release:4.15.0-multi"),
+        )
+        .unwrap();
+
+        // every mirrored architecture is returned, the first one holds the release-manifests
+        let res = aw!(get_release_payload_manifests(
+            &Fake {},
+            &scheduler,
+            log,
+            &ir,
+            list.clone(),
+            &dir,
+            &[String::from("amd64"), String::from("s390x")],
+        ))
+        .unwrap();
+        assert_eq!(res.len(), 2);
+        for arch in ["amd64", "s390x"] {
+            let expected = fs::read_to_string(format!(
+                "test-artifacts/simulate-api-call/manifest-{}-kube.json",
+                arch
+            ))
+            .unwrap();
+            let written = fs::read_to_string(dir.clone() + "manifest-" + arch + ".json").unwrap();
+            assert_eq!(written, expected);
+        }
+        assert!(!Path::new(&(dir.clone() + "manifest-arm64.json")).exists());
+        let written: Value =
+            serde_json::from_str(&fs::read_to_string(dir.clone() + "manifest-list.json").unwrap())
+                .unwrap();
+        let archs: Vec<&str> = written["manifests"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|mf| mf["platform"]["architecture"].as_str().unwrap())
+            .collect();
+        assert_eq!(archs, vec!["amd64", "s390x"]);
+
+        // a list without a payload for the architectures is an error
+        let res = aw!(get_release_payload_manifests(
+            &Fake {},
+            &scheduler,
+            log,
+            &ir,
+            list,
+            &dir,
+            &[String::from("riscv64")],
+        ));
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::CONFIG);
+        rm_rf::remove(&dir).expect("should delete test dir");
     }
 }
@@ -1 +1,2 @@
+pub mod cincinnati;
 pub mod collector;
@@ -0,0 +1,269 @@
+use async_trait::async_trait;
+use custom_logger::*;
+use futures::StreamExt;
+use mirror_copy::{FsLayer, ImplRegistryInterface, Manifest, RegistryInterface};
+use reqwest::{Client, RequestBuilder, Response};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::error::handler::{MirrorError, RequestError};
+use crate::storage::{get_blob_key, Storage};
+use crate::verify::blobs::get_blob_path;
+
+const MANIFEST_ACCEPT: &str = "application/vnd.oci.image.index.v1+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+
+// blobs shared by images are downloaded to different temporary files
+static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+
+// the RegistryInterface used with the scheduler, manifests and blobs are requested
+// directly so a failed request returns a RequestError with its status and Retry-After
+// blobs are stored through the workspace storage, images are pushed with mirror_copy
+#[derive(Clone)]
+pub struct RegistryClient {
+    client: Client,
+    storage: Arc<dyn Storage>,
+}
+
+impl RegistryClient {
+    pub fn new(storage: Arc<dyn Storage>) -> RegistryClient {
+        RegistryClient {
+            client: Client::new(),
+            storage,
+        }
+    }
+
+    async fn send(
+        &self,
+        req: RequestBuilder,
+        url: &str,
+        token: &str,
+    ) -> Result<Response, RequestError> {
+        let req = if token.is_empty() {
+            req
+        } else {
+            req.bearer_auth(token)
+        };
+        let res = req
+            .send()
+            .await
+            .map_err(|err| RequestError::from_reqwest(url, err))?;
+        if !res.status().is_success() {
+            return Err(RequestError::from_response(url, &res));
+        }
+        Ok(res)
+    }
+
+    // stream a blob to a temporary file, it is stored once it is complete and matches
+    // its digest so an interrupted or corrupt download never ends up in the blobs-store
+    async fn get_blob(
+        &self,
+        blobs_dir: &str,
+        url: &str,
+        token: &str,
+        digest: &str,
+    ) -> Result<u64, Box<dyn Error>> {
+        let path = get_blob_path(blobs_dir, digest);
+        let res = self.send(self.client.get(url), url, token).await?;
+        if let Some(parent) = Path::new(&path).parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let tmp = format!("{}.{}.tmp", path, DOWNLOADS.fetch_add(1, Ordering::SeqCst));
+        let mut file = fs::File::create(&tmp).await?;
+        let mut stream = res.bytes_stream();
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    let _ = fs::remove_file(&tmp).await;
+                    return Err(RequestError::from_reqwest(url, err).into());
+                }
+            };
+            file.write_all(&chunk).await?;
+            hasher.update(&chunk);
+            size += chunk.len() as u64;
+        }
+        file.flush().await?;
+        let hash = hex::encode(hasher.finalize());
+        // only sha256 digests can be verified
+        if digest.starts_with("sha256:") && hash != digest.trim_start_matches("sha256:") {
+            let _ = fs::remove_file(&tmp).await;
+            return Err(Box::new(MirrorError::BlobDigestMismatch {
+                expected: digest.to_string(),
+                actual: String::from("sha256:") + &hash,
+            }));
+        }
+        self.storage.write_file(&get_blob_key(digest), &tmp).await?;
+        Ok(size)
+    }
+}
+
+#[async_trait]
+impl RegistryInterface for RegistryClient {
+    async fn get_manifest(&self, url: String, token: String) -> Result<String, Box<dyn Error>> {
+        let req = self.client.get(&url).header("Accept", MANIFEST_ACCEPT);
+        let res = self.send(req, &url, &token).await?;
+        let body = res
+            .text()
+            .await
+            .map_err(|err| RequestError::from_reqwest(&url, err))?;
+        Ok(body)
+    }
+
+    // url is the blobs url of the repository i.e. https://quay.io/v2/ns/name/blobs/
+    // dir is the blobs-store of the workspace, blobs already in it are not downloaded
+    // again, the blobs a remote workspace has are fetched from it
+    async fn get_blobs(
+        &self,
+        log: &Logging,
+        dir: String,
+        url: String,
+        token: String,
+        layers: Vec<FsLayer>,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut downloaded = 0;
+        for layer in layers.iter() {
+            if Path::new(&get_blob_path(&dir, &layer.blob_sum)).exists() {
+                log.trace(&format!("blob {} exists", layer.blob_sum));
+                continue;
+            }
+            let key = get_blob_key(&layer.blob_sum);
+            if self.storage.exists(&key).await? {
+                self.storage.fetch(&key).await?;
+                log.debug(&format!(
+                    "fetched blob {} from {}",
+                    layer.blob_sum,
+                    self.storage.root()
+                ));
+                continue;
+            }
+            let blob_url = url.trim_end_matches("/").to_string() + "/" + &layer.blob_sum;
+            let size = self
+                .get_blob(&dir, &blob_url, &token, &layer.blob_sum)
+                .await?;
+            log.debug(&format!(
+                "downloaded blob {} ({} bytes)",
+                layer.blob_sum, size
+            ));
+            downloaded += 1;
+        }
+        Ok(format!(
+            "downloaded {} of {} blobs",
+            downloaded,
+            layers.len()
+        ))
+    }
+
+    async fn push_image(
+        &self,
+        log: &Logging,
+        dir: String,
+        sub_component: String,
+        url: String,
+        token: String,
+        manifest: Manifest,
+    ) -> Result<String, mirror_copy::MirrorError> {
+        ImplRegistryInterface {}
+            .push_image(log, dir, sub_component, url, token, manifest)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use crate::storage::local::LocalStorage;
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    #[test]
+    fn get_blobs_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let mut server = mockito::Server::new();
+        let digest = String::from("sha256:") + &hex::encode(Sha256::digest("layer"));
+        let blob = server
+            .mock("GET", format!("/v2/ns/name/blobs/{}", digest).as_str())
+            .match_header("Authorization", "Bearer abcd")
+            .with_status(200)
+            .with_body("layer")
+            .expect(1)
+            .create();
+        server
+            .mock("GET", "/v2/ns/name/manifests/v1")
+            .with_status(429)
+            .with_header("Retry-After", "5")
+            .create();
+
+        let corrupt = String::from("sha256:") + &hex::encode(Sha256::digest("other"));
+        server
+            .mock("GET", format!("/v2/ns/name/blobs/{}", corrupt).as_str())
+            .with_status(200)
+            .with_body("layer")
+            .create();
+
+        let root = String::from("./test-artifacts/client-test/");
+        let dir = root.clone() + "blobs-store/";
+        let _ = std::fs::remove_dir_all(&root);
+        let client = RegistryClient::new(Arc::new(LocalStorage::new(&root)));
+        let layers = vec![FsLayer {
+            blob_sum: digest.clone(),
+            original_ref: None,
+            size: Some(5),
+        }];
+        let url = server.url() + "/v2/ns/name/blobs/";
+        let res = aw!(client.get_blobs(
+            log,
+            dir.clone(),
+            url.clone(),
+            String::from("abcd"),
+            layers.clone()
+        ));
+        assert_eq!(res.unwrap(), "downloaded 1 of 1 blobs");
+        assert_eq!(
+            std::fs::read_to_string(get_blob_path(&dir, &digest)).unwrap(),
+            "layer"
+        );
+        // the blob is in the blobs-store, it is not requested again
+        let res =
+            aw!(client.get_blobs(log, dir.clone(), url.clone(), String::from("abcd"), layers));
+        assert_eq!(res.unwrap(), "downloaded 0 of 1 blobs");
+        blob.assert();
+
+        // a blob that does not match its digest is not stored
+        let layers = vec![FsLayer {
+            blob_sum: corrupt.clone(),
+            original_ref: None,
+            size: None,
+        }];
+        let err = aw!(client.get_blobs(log, dir.clone(), url, String::new(), layers)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MirrorError>(),
+            Some(MirrorError::BlobDigestMismatch { .. })
+        ));
+        assert!(!Path::new(&get_blob_path(&dir, &corrupt)).exists());
+        rm_rf::remove(&root).expect("should delete test dir");
+
+        let url = server.url() + "/v2/ns/name/manifests/v1";
+        let err = aw!(client.get_manifest(url, String::new())).unwrap_err();
+        let err = err.downcast_ref::<RequestError>().unwrap();
+        assert_eq!(err.status, Some(429));
+        assert_eq!(err.retry_after, Some(std::time::Duration::from_secs(5)));
+        assert!(err.is_transient());
+    }
+}
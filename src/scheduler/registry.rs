@@ -0,0 +1,540 @@
+use custom_logger::*;
+use futures::future::join_all;
+use mirror_copy::{FsLayer, RegistryInterface};
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+use crate::auth::token_cache::{TokenCache, PULL};
+use crate::config::load::RegistrySettings;
+use crate::config::registries::SourceMirrors;
+use crate::error::handler::{registry_error, MirrorError, RequestError};
+
+pub const DEFAULT_MAX_CONCURRENCY: usize = 8;
+pub const DEFAULT_MAX_RETRIES: u32 = 5;
+
+// delay before the first retry, doubled for every further attempt
+const BACKOFF_BASE_MS: u64 = 500;
+// upper bound for the backoff and for Retry-After
+const BACKOFF_MAX_MS: u64 = 60_000;
+
+// request limits for a registry
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchedulerConfig {
+    pub max_concurrency: usize,
+    // requests per second, 0 disables rate limiting
+    pub rate_limit: f64,
+    pub max_retries: u32,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> SchedulerConfig {
+        SchedulerConfig {
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+            rate_limit: 0.0,
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+}
+
+impl SchedulerConfig {
+    // the values set for a registry override the defaults
+    pub fn merge(&self, settings: &RegistrySettings) -> SchedulerConfig {
+        SchedulerConfig {
+            max_concurrency: settings
+                .max_concurrency
+                .unwrap_or(self.max_concurrency)
+                .max(1),
+            rate_limit: settings.rate_limit.unwrap_or(self.rate_limit),
+            max_retries: settings.max_retries.unwrap_or(self.max_retries),
+        }
+    }
+}
+
+// token bucket holding up to one second of requests, a token taken from an empty
+// bucket is a reservation so waiting requests are released in order
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> TokenBucket {
+        let capacity = rate.max(1.0);
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last: Instant::now(),
+        }
+    }
+
+    // take a token, returns how long to wait before the request can be sent
+    fn take(&mut self, now: Instant) -> Duration {
+        if self.rate <= 0.0 {
+            return Duration::ZERO;
+        }
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RegistryLimits {
+    config: SchedulerConfig,
+    permits: Semaphore,
+    bucket: Mutex<TokenBucket>,
+}
+
+// per registry concurrency, rate limiting and retries shared by the mirrorToDisk collectors
+// and mirrorToMirror, every manifest and blob request takes its own slot
+#[derive(Debug)]
+pub struct RegistryScheduler {
+    defaults: SchedulerConfig,
+    settings: Vec<RegistrySettings>,
+    limits: Mutex<HashMap<String, Arc<RegistryLimits>>>,
+    sources: SourceMirrors,
+    tokens: TokenCache,
+}
+
+impl RegistryScheduler {
+    pub fn new(
+        defaults: SchedulerConfig,
+        settings: &[RegistrySettings],
+        sources: SourceMirrors,
+        tokens: TokenCache,
+    ) -> RegistryScheduler {
+        RegistryScheduler {
+            defaults,
+            settings: settings.to_vec(),
+            limits: Mutex::new(HashMap::new()),
+            sources,
+            tokens,
+        }
+    }
+
+    pub fn get_config(&self, registry: &str) -> SchedulerConfig {
+        match self.settings.iter().find(|s| s.name == registry) {
+            Some(settings) => self.defaults.merge(settings),
+            None => self.defaults.clone(),
+        }
+    }
+
+    // the number of blob downloads the collectors keep in flight, the scheduler
+    // decides how many of them run against each registry
+    pub fn max_in_flight(&self) -> usize {
+        self.settings
+            .iter()
+            .map(|s| self.defaults.merge(s).max_concurrency)
+            .fold(self.defaults.max_concurrency, usize::max)
+            .max(1)
+    }
+
+    fn get_limits(&self, registry: &str) -> Arc<RegistryLimits> {
+        let mut limits = self.limits.lock().unwrap();
+        limits
+            .entry(registry.to_string())
+            .or_insert_with(|| {
+                let config = self.get_config(registry);
+                Arc::new(RegistryLimits {
+                    permits: Semaphore::new(config.max_concurrency.max(1)),
+                    bucket: Mutex::new(TokenBucket::new(config.rate_limit)),
+                    config,
+                })
+            })
+            .clone()
+    }
+
+    // send a request for url (built from the original reference) to its source mirrors
+    // in order, the request gets the url of the source and the pull token of its repository
+    pub async fn run<T, F, Fut>(
+        &self,
+        log: &Logging,
+        url: &str,
+        request: F,
+    ) -> Result<T, Box<dyn Error>>
+    where
+        F: Fn(String, String) -> Fut,
+        Fut: Future<Output = Result<T, Box<dyn Error>>>,
+    {
+        let urls = self.sources.get_source_urls(url);
+        for (i, source) in urls.iter().enumerate() {
+            match self.run_source(log, source, PULL, true, &request).await {
+                Err(err) if i + 1 < urls.len() => {
+                    log.info(&format!("{} failed ({}) trying next source", source, err));
+                }
+                res => return res,
+            }
+        }
+        Err(format!("no source for {}", url).into())
+    }
+
+    // send a request to a destination (source mirrors do not apply) with the token for
+    // actions (PUSH or DELETE)
+    pub async fn run_destination<T, F, Fut>(
+        &self,
+        log: &Logging,
+        url: &str,
+        actions: &str,
+        request: F,
+    ) -> Result<T, Box<dyn Error>>
+    where
+        F: Fn(String, String) -> Fut,
+        Fut: Future<Output = Result<T, Box<dyn Error>>>,
+    {
+        self.run_source(log, url, actions, true, &request).await
+    }
+
+    // send a request that can not be sent again (streamed uploads) without refresh or
+    // retries, the caller starts over on a transient error
+    pub async fn run_once<T, F, Fut>(
+        &self,
+        log: &Logging,
+        url: &str,
+        actions: &str,
+        request: F,
+    ) -> Result<T, Box<dyn Error>>
+    where
+        F: Fn(String, String) -> Fut,
+        Fut: Future<Output = Result<T, Box<dyn Error>>>,
+    {
+        self.run_source(log, url, actions, false, &request).await
+    }
+
+    // download the layers of an image, each blob is a request of its own so the layers
+    // of one image do not hold a single slot for the whole download
+    pub async fn get_blobs<T: RegistryInterface>(
+        &self,
+        reg_con: &T,
+        log: &Logging,
+        dir: &str,
+        url: &str,
+        layers: &[FsLayer],
+    ) -> Result<String, MirrorError> {
+        let futs = layers.iter().map(|layer| async move {
+            self.run(log, url, |url, token| {
+                reg_con.get_blobs(log, dir.to_string(), url, token, vec![layer.clone()])
+            })
+            .await
+            .map_err(|err| registry_error(url, err))
+        });
+        let responses = join_all(futs)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<String>, MirrorError>>()?;
+        Ok(responses.join("\n"))
+    }
+
+    // send a request once a slot and a rate limit token are free, a 401 refreshes the
+    // token once
+    // failed requests (RequestError) that were throttled (429), unavailable (502, 503, 504)
+    // or got no response are retried with exponential backoff or after Retry-After
+    async fn run_source<T, F, Fut>(
+        &self,
+        log: &Logging,
+        url: &str,
+        actions: &str,
+        retry: bool,
+        request: &F,
+    ) -> Result<T, Box<dyn Error>>
+    where
+        F: Fn(String, String) -> Fut,
+        Fut: Future<Output = Result<T, Box<dyn Error>>>,
+    {
+        let (registry, repository) = get_repository_scope(url);
+        let limits = self.get_limits(&registry);
+        let mut attempt = 0;
+        let mut refreshed = false;
+        loop {
+            let token = self.tokens.get(log, &registry, &repository, actions).await;
+            let delay = {
+                let _permit = limits
+                    .permits
+                    .acquire()
+                    .await
+                    .map_err(|err| -> Box<dyn Error> { err.to_string().into() })?;
+                let wait = limits.bucket.lock().unwrap().take(Instant::now());
+                if !wait.is_zero() {
+                    log.trace(&format!("rate limit {} waiting {:?}", registry, wait));
+                    tokio::time::sleep(wait).await;
+                }
+                let err = match request(url.to_string(), token).await {
+                    Ok(val) => return Ok(val),
+                    Err(err) => err,
+                };
+                // errors without a status (other RegistryInterface implementations) fail
+                let failed = match err.downcast_ref::<RequestError>() {
+                    Some(failed) if retry => failed.clone(),
+                    _ => return Err(err),
+                };
+                if !refreshed && failed.is_unauthorized() {
+                    log.debug(&format!("refreshing token for {}", url));
+                    self.tokens.invalidate(&registry, &repository);
+                    refreshed = true;
+                    continue;
+                }
+                match get_retry_delay(&failed, attempt) {
+                    Some(delay) if attempt < limits.config.max_retries => {
+                        log.debug(&format!(
+                            "retry {}/{} for {} in {:?} ({})",
+                            attempt + 1,
+                            limits.config.max_retries,
+                            url,
+                            delay,
+                            failed
+                        ));
+                        delay
+                    }
+                    _ => return Err(err),
+                }
+            };
+            attempt += 1;
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+// the registry host of a url i.e. registry.redhat.io
+pub fn get_registry(url: &str) -> String {
+    url.trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .split("/")
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+// the registry and repository of a manifest or blobs url
+// i.e. https://quay.io/v2/ns/name/manifests/v1 -> (quay.io, ns/name)
+pub fn get_repository_scope(url: &str) -> (String, String) {
+    let path = url.split_once("/v2/").map(|(_, p)| p).unwrap_or_default();
+    let repository = ["/manifests/", "/blobs/"]
+        .iter()
+        .find_map(|sep| path.split_once(sep).map(|(repo, _)| repo))
+        .unwrap_or_else(|| path.trim_end_matches("/"));
+    (get_registry(url), repository.to_string())
+}
+
+// the delay before the next attempt, None if the request failed for good
+// Retry-After takes precedence over the backoff
+pub fn get_retry_delay(err: &RequestError, attempt: u32) -> Option<Duration> {
+    if !err.is_transient() {
+        return None;
+    }
+    let backoff = Duration::from_millis(BACKOFF_BASE_MS.saturating_mul(1 << attempt.min(16)));
+    let delay = err.retry_after.unwrap_or(backoff);
+    Some(delay.min(Duration::from_millis(BACKOFF_MAX_MS)))
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use crate::config::load::SourceRegistry;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    fn request_error(status: Option<u16>, retry_after: Option<u64>) -> RequestError {
+        RequestError {
+            url: String::from("https://quay.io/v2/ns/name/manifests/v1"),
+            status,
+            retry_after: retry_after.map(Duration::from_secs),
+            msg: String::from("failed"),
+        }
+    }
+
+    #[test]
+    fn get_retry_delay_pass() {
+        assert_eq!(
+            get_retry_delay(&request_error(Some(503), None), 0),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            get_retry_delay(&request_error(Some(429), None), 2),
+            Some(Duration::from_millis(2000))
+        );
+        assert_eq!(
+            get_retry_delay(&request_error(Some(429), Some(7)), 0),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            get_retry_delay(&request_error(Some(503), Some(3600)), 0),
+            Some(Duration::from_millis(BACKOFF_MAX_MS))
+        );
+        // no response (connection refused, timeout)
+        assert_eq!(
+            get_retry_delay(&request_error(None, None), 1),
+            Some(Duration::from_millis(1000))
+        );
+        assert_eq!(get_retry_delay(&request_error(Some(404), None), 0), None);
+        assert_eq!(get_retry_delay(&request_error(Some(401), None), 0), None);
+    }
+
+    #[test]
+    fn token_bucket_pass() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(2.0);
+        assert_eq!(bucket.take(now), Duration::ZERO);
+        assert_eq!(bucket.take(now), Duration::ZERO);
+        // the bucket is empty, the next requests wait for the refill
+        assert_eq!(bucket.take(now), Duration::from_millis(500));
+        assert_eq!(bucket.take(now), Duration::from_millis(1000));
+        let mut unlimited = TokenBucket::new(0.0);
+        assert_eq!(unlimited.take(now), Duration::ZERO);
+    }
+
+    #[test]
+    fn get_config_pass() {
+        let settings = vec![RegistrySettings {
+            name: String::from("registry.redhat.io"),
+            max_concurrency: Some(16),
+            rate_limit: Some(2.0),
+            max_retries: None,
+        }];
+        let scheduler = RegistryScheduler::new(
+            SchedulerConfig::default(),
+            &settings,
+            SourceMirrors::default(),
+            TokenCache::anonymous(),
+        );
+        let config = scheduler.get_config("registry.redhat.io");
+        assert_eq!(config.max_concurrency, 16);
+        assert_eq!(config.rate_limit, 2.0);
+        assert_eq!(config.max_retries, DEFAULT_MAX_RETRIES);
+        assert_eq!(scheduler.get_config("quay.io"), SchedulerConfig::default());
+        assert_eq!(scheduler.max_in_flight(), 16);
+        assert_eq!(
+            get_registry("https://registry.redhat.io/v2/ns/name/manifests/v1"),
+            "registry.redhat.io"
+        );
+    }
+
+    #[test]
+    fn run_retry_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let scheduler = RegistryScheduler::new(
+            SchedulerConfig {
+                max_retries: 2,
+                ..SchedulerConfig::default()
+            },
+            &[],
+            SourceMirrors::default(),
+            TokenCache::anonymous(),
+        );
+        let calls = AtomicUsize::new(0);
+        let res: Result<String, Box<dyn Error>> =
+            aw!(
+                scheduler.run(log, "https://quay.io/v2/ns/name", |_, _| async {
+                    match calls.fetch_add(1, Ordering::SeqCst) {
+                        0 => Err(request_error(Some(429), Some(0)).into()),
+                        _ => Ok(String::from("ok")),
+                    }
+                })
+            );
+        assert_eq!(res.unwrap(), "ok");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // not transient, no retry
+        let calls = AtomicUsize::new(0);
+        let res: Result<String, Box<dyn Error>> =
+            aw!(
+                scheduler.run(log, "https://quay.io/v2/ns/name", |_, _| async {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Err(request_error(Some(404), None).into())
+                })
+            );
+        assert!(res.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // errors without a status are not retried
+        let calls = AtomicUsize::new(0);
+        let res: Result<String, Box<dyn Error>> =
+            aw!(
+                scheduler.run(log, "https://quay.io/v2/ns/name", |_, _| async {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Err("503 Service Unavailable".into())
+                })
+            );
+        assert!(res.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // a 401 refreshes the token once without counting as a retry
+        let calls = AtomicUsize::new(0);
+        let res: Result<String, Box<dyn Error>> =
+            aw!(
+                scheduler.run(log, "https://quay.io/v2/ns/name", |_, _| async {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Err(request_error(Some(401), None).into())
+                })
+            );
+        assert!(res.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn run_source_mirrors_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let scheduler = RegistryScheduler::new(
+            SchedulerConfig::default(),
+            &[],
+            SourceMirrors::new(vec![SourceRegistry {
+                prefix: String::from("quay.io"),
+                location: Some(String::from("proxy.internal/quay")),
+                mirrors: vec![String::from("cache.internal/quay")],
+            }]),
+            TokenCache::anonymous(),
+        );
+        let calls = &Mutex::new(vec![]);
+        let res: Result<String, Box<dyn Error>> = aw!(scheduler.run(
+            log,
+            "https://quay.io/v2/ns/name/manifests/v1",
+            |url, _| async move {
+                calls.lock().unwrap().push(url.clone());
+                match url.starts_with("https://cache.internal") {
+                    true => Err("404 Not Found".into()),
+                    false => Ok(url),
+                }
+            }
+        ));
+        // the mirror failed, the location is used
+        assert_eq!(
+            res.unwrap(),
+            "https://proxy.internal/v2/quay/ns/name/manifests/v1"
+        );
+        assert_eq!(calls.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn get_repository_scope_pass() {
+        assert_eq!(
+            get_repository_scope("https://quay.io/v2/ns/name/manifests/v1"),
+            (String::from("quay.io"), String::from("ns/name"))
+        );
+        assert_eq!(
+            get_repository_scope("https://registry.redhat.io/v2/a/b/c/blobs/sha256:1234"),
+            (String::from("registry.redhat.io"), String::from("a/b/c"))
+        );
+    }
+}
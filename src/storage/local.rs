@@ -0,0 +1,136 @@
+use async_trait::async_trait;
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::error::handler::MirrorError;
+use crate::storage::{move_file, Storage, StorageObject};
+
+// workspace on the local filesystem, the collectors work in it directly
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    pub root: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str) -> LocalStorage {
+        LocalStorage {
+            root: root.trim_end_matches("/").to_string() + "/",
+        }
+    }
+
+    fn get_path(&self, key: &str) -> String {
+        self.root.clone() + key.trim_start_matches("/")
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    fn root(&self) -> String {
+        self.root.clone()
+    }
+
+    fn local_dir(&self) -> String {
+        self.root.clone()
+    }
+
+    // the file is moved into the workspace
+    async fn write_file(&self, key: &str, path: &str) -> Result<(), MirrorError> {
+        let to = self.get_path(key);
+        if to == path {
+            return Ok(());
+        }
+        move_file(path, &to)
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, MirrorError> {
+        Ok(Path::new(&self.get_path(key)).exists())
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<StorageObject>, MirrorError> {
+        let mut objects = vec![];
+        for entry in WalkDir::new(&self.root).into_iter().filter_map(|e| e.ok()) {
+            if !entry.path().is_file() {
+                continue;
+            }
+            let key = entry
+                .path()
+                .strip_prefix(&self.root)
+                .map_err(|err| MirrorError::Io(err.to_string()))?
+                .display()
+                .to_string();
+            if key.starts_with(prefix) {
+                objects.push(StorageObject {
+                    key,
+                    size: entry.metadata().map(|m| m.len()).unwrap_or_default(),
+                    etag: None,
+                });
+            }
+        }
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), MirrorError> {
+        let path = self.get_path(key);
+        if Path::new(&path).exists() {
+            fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+
+    async fn fetch(&self, key: &str) -> Result<String, MirrorError> {
+        let path = self.get_path(key);
+        if !Path::new(&path).exists() {
+            return Err(MirrorError::Io(format!("{} not found", path)));
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    #[test]
+    fn local_storage_pass() {
+        let storage = LocalStorage::new("./test-artifacts/local-storage-test");
+        fs::create_dir_all("./test-artifacts/local-storage-test").expect("should create dir");
+        let tmp = String::from("./test-artifacts/local-storage-test/abcd.tmp");
+        fs::write(&tmp, b"test").expect("should write file");
+        aw!(storage.write_file("blobs-store/ab/abcd", &tmp)).unwrap();
+        // the file is moved into the workspace
+        assert!(!Path::new(&tmp).exists());
+        assert!(aw!(storage.exists("blobs-store/ab/abcd")).unwrap());
+        assert_eq!(
+            aw!(storage.list("blobs-store/")).unwrap(),
+            vec!["blobs-store/ab/abcd"]
+        );
+        assert_eq!(
+            aw!(storage.list_objects("blobs-store/")).unwrap(),
+            vec![StorageObject {
+                key: String::from("blobs-store/ab/abcd"),
+                size: 4,
+                etag: None,
+            }]
+        );
+        let path = aw!(storage.fetch("blobs-store/ab/abcd")).unwrap();
+        assert_eq!(
+            path,
+            "./test-artifacts/local-storage-test/blobs-store/ab/abcd"
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "test");
+        let res = aw!(storage.fetch("blobs-store/ab/nada"));
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::IOERR);
+        aw!(storage.remove("blobs-store/ab/abcd")).unwrap();
+        assert!(!aw!(storage.exists("blobs-store/ab/abcd")).unwrap());
+        rm_rf::remove("./test-artifacts/local-storage-test").expect("should delete test dir");
+    }
+}
@@ -0,0 +1,259 @@
+pub mod local;
+pub mod s3;
+
+use async_trait::async_trait;
+use custom_logger::*;
+use md5::{Digest, Md5};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use walkdir::WalkDir;
+
+use crate::error::handler::MirrorError;
+use crate::storage::local::LocalStorage;
+use crate::storage::s3::S3Storage;
+use crate::verify::blobs::get_blob_path;
+
+// an object in the backend, etag is the md5 of the content (if known)
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageObject {
+    pub key: String,
+    pub size: u64,
+    pub etag: Option<String>,
+}
+
+// backend for the working-dir (manifests, caches and blobs-store)
+// keys are relative to the workspace root i.e. blobs-store/ab/ab23...
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // workspace root used in messages i.e. ./working-dir/ or s3://bucket/prefix/
+    fn root(&self) -> String;
+
+    // local directory the collectors work in
+    fn local_dir(&self) -> String;
+
+    // store the file at path (streamed), afterwards it is found below local_dir
+    async fn write_file(&self, key: &str, path: &str) -> Result<(), MirrorError>;
+
+    async fn exists(&self, key: &str) -> Result<bool, MirrorError>;
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<StorageObject>, MirrorError>;
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, MirrorError> {
+        let objects = self.list_objects(prefix).await?;
+        Ok(objects.into_iter().map(|object| object.key).collect())
+    }
+
+    // a missing key is not an error
+    async fn remove(&self, key: &str) -> Result<(), MirrorError>;
+
+    // make the object available below local_dir and return its path
+    async fn fetch(&self, key: &str) -> Result<String, MirrorError>;
+}
+
+// parse the --workspace flag i.e. file://./working-dir/, /data/mirror/ or s3://bucket/prefix/
+pub fn get_storage(workspace: &str) -> Result<Arc<dyn Storage>, MirrorError> {
+    if let Some(path) = workspace.strip_prefix("s3://") {
+        let (bucket, prefix) = path.split_once("/").unwrap_or((path, ""));
+        if bucket.is_empty() {
+            return Err(MirrorError::Config(format!(
+                "workspace {} has no bucket",
+                workspace
+            )));
+        }
+        let cache_dir = get_cache_dir(bucket, prefix);
+        return Ok(Arc::new(S3Storage::from_env(bucket, prefix, cache_dir)?));
+    }
+    let root = workspace.trim_start_matches("file://");
+    if root.is_empty() {
+        return Err(MirrorError::Config(String::from("workspace is empty")));
+    }
+    Ok(Arc::new(LocalStorage::new(root)))
+}
+
+// local cache of a remote workspace i.e. ./s3-mirror-prefix/ for s3://mirror/prefix/
+// a single directory like the default working-dir, diff archives keep the component
+// directories below it
+fn get_cache_dir(bucket: &str, prefix: &str) -> String {
+    let name = [bucket, prefix.trim_matches('/')]
+        .iter()
+        .filter(|part| !part.is_empty())
+        .map(|part| part.replace("/", "-"))
+        .collect::<Vec<String>>()
+        .join("-");
+    String::from("./s3-") + &name + "/"
+}
+
+// blobs are content addressed, everything else can change between runs
+pub fn is_blob(key: &str) -> bool {
+    key.starts_with("blobs-store/")
+}
+
+// the key of a blob in the workspace i.e. blobs-store/ab/ab23...
+pub fn get_blob_key(digest: &str) -> String {
+    get_blob_path("blobs-store", digest)
+}
+
+// move a file, rename fails across filesystems
+pub fn move_file(from: &str, to: &str) -> Result<(), MirrorError> {
+    if let Some(parent) = Path::new(to).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+// a file that did not change is not uploaded or downloaded again
+// the etag of an object written in a single request is the md5 of its content
+fn is_unchanged(object: &StorageObject, path: &Path) -> Result<bool, MirrorError> {
+    if !path.is_file() || fs::metadata(path)?.len() != object.size {
+        return Ok(false);
+    }
+    match &object.etag {
+        Some(etag) => Ok(*etag == hex::encode(Md5::digest(fs::read(path)?))),
+        None => Ok(false),
+    }
+}
+
+async fn get_stored_objects(
+    storage: &dyn Storage,
+) -> Result<HashMap<String, StorageObject>, MirrorError> {
+    let objects = storage.list_objects("").await?;
+    Ok(objects
+        .into_iter()
+        .map(|object| (object.key.clone(), object))
+        .collect())
+}
+
+// upload the files in the local dir the backend does not have or that changed
+pub async fn sync_to_storage(log: &Logging, storage: &dyn Storage) -> Result<usize, MirrorError> {
+    let local_dir = storage.local_dir();
+    // nothing to sync when the collectors work in the workspace itself
+    if local_dir == storage.root() {
+        return Ok(0);
+    }
+    let stored = get_stored_objects(storage).await?;
+    let mut count = 0;
+    for entry in WalkDir::new(&local_dir).into_iter().filter_map(|e| e.ok()) {
+        if !entry.path().is_file() {
+            continue;
+        }
+        let key = entry
+            .path()
+            .strip_prefix(&local_dir)
+            .map_err(|err| MirrorError::Io(err.to_string()))?
+            .display()
+            .to_string();
+        // partial downloads of an interrupted run
+        if key.ends_with(".tmp") {
+            continue;
+        }
+        if let Some(object) = stored.get(&key) {
+            // blobs are content addressed
+            if is_blob(&key) || is_unchanged(object, entry.path())? {
+                continue;
+            }
+        }
+        let path = entry.path().display().to_string();
+        storage.write_file(&key, &path).await?;
+        count += 1;
+    }
+    log.debug(&format!("synced {} files to {}", count, storage.root()));
+    Ok(count)
+}
+
+// remove the keys from the backend that were removed from the local dir
+// the local dir must have been synced with blobs before
+pub async fn prune_storage(log: &Logging, storage: &dyn Storage) -> Result<usize, MirrorError> {
+    let local_dir = storage.local_dir();
+    if local_dir == storage.root() {
+        return Ok(0);
+    }
+    let mut count = 0;
+    for key in storage.list("").await? {
+        if Path::new(&(local_dir.clone() + &key)).exists() {
+            continue;
+        }
+        storage.remove(&key).await?;
+        count += 1;
+    }
+    log.debug(&format!("removed {} files from {}", count, storage.root()));
+    Ok(count)
+}
+
+// download the files from the backend missing in the local dir or that changed
+// without blobs only the metadata is synced, blobs are fetched when needed
+pub async fn sync_from_storage(
+    log: &Logging,
+    storage: &dyn Storage,
+    blobs: bool,
+) -> Result<usize, MirrorError> {
+    if storage.local_dir() == storage.root() {
+        return Ok(0);
+    }
+    let mut count = 0;
+    for object in storage.list_objects("").await? {
+        if is_blob(&object.key) && !blobs {
+            continue;
+        }
+        let file = storage.local_dir() + &object.key;
+        if is_blob(&object.key) && Path::new(&file).exists() {
+            continue;
+        }
+        if is_unchanged(&object, Path::new(&file))? {
+            continue;
+        }
+        storage.fetch(&object.key).await?;
+        count += 1;
+    }
+    log.debug(&format!("synced {} files from {}", count, storage.root()));
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+
+    #[test]
+    fn get_storage_pass() {
+        let storage = get_storage("file://./working-dir").unwrap();
+        assert_eq!(storage.local_dir(), "./working-dir/");
+        let storage = get_storage("/data/mirror/").unwrap();
+        assert_eq!(storage.root(), "/data/mirror/");
+        let res = get_storage("s3:///prefix");
+        assert_eq!(res.err().unwrap().exit_code(), exitcode::CONFIG);
+        assert_eq!(get_cache_dir("mirror", ""), "./s3-mirror/");
+        assert_eq!(
+            get_cache_dir("mirror", "ocp/4.14/"),
+            "./s3-mirror-ocp-4.14/"
+        );
+        assert_eq!(get_blob_key("sha256:ab23d850"), "blobs-store/ab/ab23d850");
+    }
+
+    #[test]
+    fn is_unchanged_pass() {
+        let dir = String::from("./test-artifacts/storage-sync-test/");
+        fs::create_dir_all(&dir).expect("should create test dir");
+        let file = dir.clone() + "history.json";
+        fs::write(&file, "test").expect("should write file");
+        let mut object = StorageObject {
+            key: String::from("history.json"),
+            size: 4,
+            etag: Some(String::from("098f6bcd4621d373cade4e832627b4f6")),
+        };
+        assert!(is_unchanged(&object, Path::new(&file)).unwrap());
+        // the etag of a multipart upload is not the md5 of the content
+        object.etag = Some(String::from("098f6bcd4621d373cade4e832627b4f6-2"));
+        assert!(!is_unchanged(&object, Path::new(&file)).unwrap());
+        object.etag = None;
+        assert!(!is_unchanged(&object, Path::new(&file)).unwrap());
+        let missing = dir.clone() + "nada.json";
+        assert!(!is_unchanged(&object, Path::new(&missing)).unwrap());
+        rm_rf::remove(&dir).expect("should delete test dir");
+    }
+}
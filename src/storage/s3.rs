@@ -0,0 +1,692 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::StreamExt;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::error::handler::MirrorError;
+use crate::storage::{is_blob, move_file, Storage, StorageObject};
+
+// files larger than a part are uploaded in parts (s3 allows 5MiB to 5GiB per part)
+const PART_SIZE: u64 = 16 * 1024 * 1024;
+
+// ListObjectsV2 response
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
+    #[serde(default)]
+    contents: Vec<ListContents>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ListContents {
+    key: String,
+    #[serde(default)]
+    size: u64,
+    #[serde(rename = "ETag")]
+    etag: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct InitiateMultipartUploadResult {
+    upload_id: String,
+}
+
+#[derive(Serialize, Debug)]
+struct CompleteMultipartUpload {
+    #[serde(rename = "Part")]
+    parts: Vec<CompletedPart>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct CompletedPart {
+    part_number: usize,
+    #[serde(rename = "ETag")]
+    etag: String,
+}
+
+// error body, CompleteMultipartUpload can fail after it returned 200
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct S3Error {
+    code: String,
+    #[serde(default)]
+    message: String,
+}
+
+// workspace in an s3 compatible bucket (aws, minio ...), requests use path style urls
+// objects are cached in cache_dir for the collectors and the registry client
+#[derive(Debug, Clone)]
+pub struct S3Storage {
+    pub endpoint: String,
+    pub bucket: String,
+    pub prefix: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    pub cache_dir: String,
+    pub part_size: u64,
+}
+
+impl S3Storage {
+    pub fn new(
+        endpoint: &str,
+        bucket: &str,
+        prefix: &str,
+        region: &str,
+        access_key: &str,
+        secret_key: &str,
+        cache_dir: String,
+    ) -> S3Storage {
+        let prefix = prefix.trim_matches('/');
+        S3Storage {
+            endpoint: endpoint.trim_end_matches("/").to_string(),
+            bucket: bucket.to_string(),
+            prefix: if prefix.is_empty() {
+                String::from("")
+            } else {
+                prefix.to_string() + "/"
+            },
+            region: region.to_string(),
+            access_key: access_key.to_string(),
+            secret_key: secret_key.to_string(),
+            cache_dir: cache_dir.trim_end_matches("/").to_string() + "/",
+            part_size: PART_SIZE,
+        }
+    }
+
+    // endpoint and credentials from the AWS_* environment variables
+    pub fn from_env(
+        bucket: &str,
+        prefix: &str,
+        cache_dir: String,
+    ) -> Result<S3Storage, MirrorError> {
+        let get = |name: &str| {
+            env::var(name).map_err(|_| {
+                MirrorError::Config(format!("{} is not set for the s3 workspace", name))
+            })
+        };
+        Ok(S3Storage::new(
+            &env::var("AWS_ENDPOINT_URL")
+                .unwrap_or_else(|_| String::from("https://s3.amazonaws.com")),
+            bucket,
+            prefix,
+            &env::var("AWS_REGION").unwrap_or_else(|_| String::from("us-east-1")),
+            &get("AWS_ACCESS_KEY_ID")?,
+            &get("AWS_SECRET_ACCESS_KEY")?,
+            cache_dir,
+        ))
+    }
+
+    fn get_host(&self) -> String {
+        let host = self.endpoint.split("://").last().unwrap_or_default();
+        host.split("/").next().unwrap_or_default().to_string()
+    }
+
+    // /bucket/prefix/key with every path segment uri encoded
+    fn get_uri(&self, key: Option<&str>) -> String {
+        let mut uri = String::from("/") + &self.bucket;
+        if let Some(key) = key {
+            let object = self.prefix.clone() + key.trim_start_matches("/");
+            for segment in object.split("/") {
+                uri.push('/');
+                uri.push_str(&urlencoding::encode(segment));
+            }
+        }
+        uri
+    }
+
+    fn get_cache_path(&self, key: &str) -> String {
+        self.cache_dir.clone() + key.trim_start_matches("/")
+    }
+
+    // sign and send a request, only a missing object (404) is not an error
+    async fn send(
+        &self,
+        method: Method,
+        key: Option<&str>,
+        query: Vec<(&str, String)>,
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response, MirrorError> {
+        let uri = self.get_uri(key);
+        let mut params: Vec<String> = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", urlencoding::encode(k), urlencoding::encode(v)))
+            .collect();
+        params.sort();
+        let query = params.join("&");
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let headers = vec![
+            (String::from("host"), self.get_host()),
+            (String::from("x-amz-content-sha256"), payload_hash.clone()),
+            (String::from("x-amz-date"), amz_date.clone()),
+        ];
+        let (canonical_request, signed_headers) =
+            get_canonical_request(method.as_str(), &uri, &query, &headers, &payload_hash);
+        let signature = get_signature(
+            &self.secret_key,
+            &self.region,
+            &amz_date,
+            &canonical_request,
+        );
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}/{}/s3/aws4_request, SignedHeaders={}, Signature={}",
+            self.access_key,
+            &amz_date[..8],
+            self.region,
+            signed_headers,
+            signature
+        );
+
+        let mut url = self.endpoint.clone() + &uri;
+        if !query.is_empty() {
+            url = url + "?" + &query;
+        }
+        let res = reqwest::Client::new()
+            .request(method, &url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header("Authorization", authorization)
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| MirrorError::RegistryHttp(format!("{} {}", url, err)))?;
+        match res.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(MirrorError::Auth(format!(
+                "{} returned {}",
+                url,
+                res.status()
+            ))),
+            status if status.is_success() || status == StatusCode::NOT_FOUND => Ok(res),
+            status => Err(MirrorError::RegistryHttp(format!(
+                "{} returned {}",
+                url, status
+            ))),
+        }
+    }
+
+    // send a request that writes the object, a missing bucket or upload is an error
+    async fn send_write(
+        &self,
+        method: Method,
+        key: &str,
+        query: Vec<(&str, String)>,
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response, MirrorError> {
+        let res = self.send(method, Some(key), query, body).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(MirrorError::Io(format!(
+                "unable to write {}{} ({})",
+                self.root(),
+                key,
+                res.status()
+            )));
+        }
+        Ok(res)
+    }
+
+    // multipart upload, only one part of the file is in memory
+    // a failed upload is aborted so the bucket does not keep its parts
+    async fn write_parts(&self, key: &str, path: &str) -> Result<(), MirrorError> {
+        let query = vec![("uploads", String::new())];
+        let res = self.send_write(Method::POST, key, query, vec![]).await?;
+        let upload: InitiateMultipartUploadResult = get_xml(res).await?;
+        let res = self.upload_parts(key, path, &upload.upload_id).await;
+        if res.is_err() {
+            let query = vec![("uploadId", upload.upload_id.clone())];
+            let _ = self.send(Method::DELETE, Some(key), query, vec![]).await;
+        }
+        res
+    }
+
+    async fn upload_parts(
+        &self,
+        key: &str,
+        path: &str,
+        upload_id: &str,
+    ) -> Result<(), MirrorError> {
+        let mut file = fs::File::open(path)?;
+        let mut parts = vec![];
+        loop {
+            let mut data = vec![];
+            (&mut file).take(self.part_size).read_to_end(&mut data)?;
+            if data.is_empty() {
+                break;
+            }
+            let part_number = parts.len() + 1;
+            let query = vec![
+                ("partNumber", part_number.to_string()),
+                ("uploadId", upload_id.to_string()),
+            ];
+            let res = self.send_write(Method::PUT, key, query, data).await?;
+            let etag = res
+                .headers()
+                .get("etag")
+                .and_then(|etag| etag.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            parts.push(CompletedPart { part_number, etag });
+        }
+        let body = quick_xml::se::to_string(&CompleteMultipartUpload { parts })
+            .map_err(|err| MirrorError::Io(format!("{}{} {}", self.root(), key, err)))?;
+        let query = vec![("uploadId", upload_id.to_string())];
+        let res = self
+            .send_write(Method::POST, key, query, body.into_bytes())
+            .await?;
+        if let Ok(err) = get_xml::<S3Error>(res).await {
+            return Err(MirrorError::RegistryHttp(format!(
+                "{}{} {} {}",
+                self.root(),
+                key,
+                err.code,
+                err.message
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    fn root(&self) -> String {
+        String::from("s3://") + &self.bucket + "/" + &self.prefix
+    }
+
+    fn local_dir(&self) -> String {
+        self.cache_dir.clone()
+    }
+
+    // the file is kept in the cache for the collectors
+    async fn write_file(&self, key: &str, path: &str) -> Result<(), MirrorError> {
+        if fs::metadata(path)?.len() > self.part_size {
+            self.write_parts(key, path).await?;
+        } else {
+            self.send_write(Method::PUT, key, vec![], fs::read(path)?)
+                .await?;
+        }
+        let to = self.get_cache_path(key);
+        if to != path {
+            move_file(path, &to)?;
+        }
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, MirrorError> {
+        let res = self.send(Method::HEAD, Some(key), vec![], vec![]).await?;
+        Ok(res.status() != StatusCode::NOT_FOUND)
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<StorageObject>, MirrorError> {
+        let mut objects = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("list-type", String::from("2")),
+                ("prefix", self.prefix.clone() + prefix),
+            ];
+            if let Some(t) = token.take() {
+                query.push(("continuation-token", t));
+            }
+            let res = self.send(Method::GET, None, query, vec![]).await?;
+            if res.status() == StatusCode::NOT_FOUND {
+                return Err(MirrorError::Config(format!(
+                    "bucket {} not found",
+                    self.bucket
+                )));
+            }
+            let list: ListBucketResult = get_xml(res).await?;
+            for object in list.contents {
+                objects.push(StorageObject {
+                    key: object
+                        .key
+                        .strip_prefix(&self.prefix)
+                        .unwrap_or(&object.key)
+                        .to_string(),
+                    size: object.size,
+                    etag: object.etag.map(|etag| etag.trim_matches('"').to_string()),
+                });
+            }
+            token = list.next_continuation_token;
+            if !list.is_truncated || token.is_none() {
+                break;
+            }
+        }
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), MirrorError> {
+        self.send(Method::DELETE, Some(key), vec![], vec![]).await?;
+        Ok(())
+    }
+
+    // blobs in the cache are not downloaded again, objects are streamed to the cache
+    async fn fetch(&self, key: &str) -> Result<String, MirrorError> {
+        let path = self.get_cache_path(key);
+        if is_blob(key) && Path::new(&path).exists() {
+            return Ok(path);
+        }
+        let res = self.send(Method::GET, Some(key), vec![], vec![]).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(MirrorError::Io(format!("{}{} not found", self.root(), key)));
+        }
+        if let Some(parent) = Path::new(&path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.clone() + ".tmp";
+        let mut file = fs::File::create(&tmp)?;
+        let mut stream = res.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    let _ = fs::remove_file(&tmp);
+                    return Err(MirrorError::RegistryHttp(format!("{} {}", key, err)));
+                }
+            };
+            file.write_all(&chunk)?;
+        }
+        file.flush()?;
+        fs::rename(&tmp, &path)?;
+        Ok(path)
+    }
+}
+
+// parse an xml response body
+async fn get_xml<T: DeserializeOwned>(res: reqwest::Response) -> Result<T, MirrorError> {
+    let url = res.url().to_string();
+    let xml = res
+        .text()
+        .await
+        .map_err(|err| MirrorError::RegistryHttp(format!("{} {}", url, err)))?;
+    quick_xml::de::from_str(&xml)
+        .map_err(|err| MirrorError::RegistryHttp(format!("{} invalid response {}", url, err)))
+}
+
+// aws signature version 4 canonical request, returns the request and the signed headers
+pub fn get_canonical_request(
+    method: &str,
+    uri: &str,
+    query: &str,
+    headers: &[(String, String)],
+    payload_hash: &str,
+) -> (String, String) {
+    let mut headers: Vec<(String, String)> = headers
+        .iter()
+        .map(|(k, v)| (k.to_lowercase(), v.trim().to_string()))
+        .collect();
+    headers.sort();
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(k, v)| k.clone() + ":" + v + "\n")
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(k, _)| k.clone())
+        .collect::<Vec<String>>()
+        .join(";");
+    let request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method, uri, query, canonical_headers, signed_headers, payload_hash
+    );
+    (request, signed_headers)
+}
+
+// aws signature version 4 for the s3 service
+pub fn get_signature(
+    secret_key: &str,
+    region: &str,
+    amz_date: &str,
+    canonical_request: &str,
+) -> String {
+    let date = &amz_date[..8];
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}/{}/s3/aws4_request\n{}",
+        amz_date,
+        date,
+        region,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut key = hmac_sha256(
+        (String::from("AWS4") + secret_key).as_bytes(),
+        date.as_bytes(),
+    );
+    for part in [region, "s3", "aws4_request"] {
+        key = hmac_sha256(&key, part.as_bytes());
+    }
+    hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // hmac takes keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use mockito::Matcher;
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    #[test]
+    fn get_signature_pass() {
+        // rfc 4231 test case 2
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // GET object example from the aws signature version 4 documentation
+        let payload_hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let headers = vec![
+            (
+                String::from("Host"),
+                String::from("examplebucket.s3.amazonaws.com"),
+            ),
+            (String::from("Range"), String::from("bytes=0-9")),
+            (
+                String::from("x-amz-content-sha256"),
+                String::from(payload_hash),
+            ),
+            (String::from("x-amz-date"), String::from("20130524T000000Z")),
+        ];
+        let (request, signed_headers) =
+            get_canonical_request("GET", "/test.txt", "", &headers, payload_hash);
+        assert_eq!(signed_headers, "host;range;x-amz-content-sha256;x-amz-date");
+        assert_eq!(
+            get_signature(
+                "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY",
+                "us-east-1",
+                "20130524T000000Z",
+                &request
+            ),
+            "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+        );
+    }
+
+    // the mock server stands in for minio
+    #[test]
+    fn s3_storage_pass() {
+        let mut server = mockito::Server::new();
+        let cache_dir = String::from("./test-artifacts/s3-cache/");
+        let mut storage = S3Storage::new(
+            &server.url(),
+            "mirror",
+            "/workspace/",
+            "us-east-1",
+            "minioadmin",
+            "minioadmin",
+            cache_dir.clone(),
+        );
+        let auth = Matcher::Regex(String::from(
+            "^AWS4-HMAC-SHA256 Credential=minioadmin/[0-9]{8}/us-east-1/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature=[0-9a-f]{64}$",
+        ));
+        let put = server
+            .mock("PUT", "/mirror/workspace/blobs-store/ab/abcd")
+            .match_header("authorization", auth.clone())
+            .match_body("test")
+            .with_status(200)
+            .create();
+        server
+            .mock("HEAD", "/mirror/workspace/blobs-store/ab/abcd")
+            .with_status(200)
+            .create();
+        server
+            .mock("HEAD", "/mirror/workspace/blobs-store/ab/nada")
+            .with_status(404)
+            .create();
+        server
+            .mock("GET", "/mirror/workspace/blobs-store/ab/abcd")
+            .match_header("authorization", auth)
+            .with_status(200)
+            .with_body("test")
+            .create();
+        server
+            .mock("GET", "/mirror")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("list-type".into(), "2".into()),
+                Matcher::UrlEncoded("prefix".into(), "workspace/blobs-store/".into()),
+            ]))
+            .with_status(200)
+            .with_body(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                 <ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+                 <Name>mirror</Name><IsTruncated>false</IsTruncated>\
+                 <Contents><Key>workspace/blobs-store/ab/abcd</Key><Size>4</Size>\
+                 <ETag>&quot;098f6bcd4621d373cade4e832627b4f6&quot;</ETag></Contents>\
+                 <Contents><Key>workspace/blobs-store/cd/cdef</Key><Size>6</Size>\
+                 <ETag>&quot;3c3b2bd2ae8a4ae0f0f8fce8a1cb0e6b-3&quot;</ETag></Contents>\
+                 </ListBucketResult>",
+            )
+            .create();
+        // multipart upload of a file larger than a part
+        server
+            .mock("POST", "/mirror/workspace/blobs-store/ef/efgh")
+            .match_query(Matcher::UrlEncoded("uploads".into(), "".into()))
+            .with_status(200)
+            .with_body(
+                "<InitiateMultipartUploadResult><Bucket>mirror</Bucket>\
+                 <Key>workspace/blobs-store/ef/efgh</Key><UploadId>upload-1</UploadId>\
+                 </InitiateMultipartUploadResult>",
+            )
+            .create();
+        let mut parts = vec![];
+        for (number, data) in [("1", "te"), ("2", "st"), ("3", "!")] {
+            parts.push(
+                server
+                    .mock("PUT", "/mirror/workspace/blobs-store/ef/efgh")
+                    .match_query(Matcher::AllOf(vec![
+                        Matcher::UrlEncoded("partNumber".into(), number.into()),
+                        Matcher::UrlEncoded("uploadId".into(), "upload-1".into()),
+                    ]))
+                    .match_body(data)
+                    .with_status(200)
+                    .with_header("etag", &format!("\"etag-{}\"", number))
+                    .expect(1)
+                    .create(),
+            );
+        }
+        let complete = server
+            .mock("POST", "/mirror/workspace/blobs-store/ef/efgh")
+            .match_query(Matcher::UrlEncoded("uploadId".into(), "upload-1".into()))
+            .match_body(Matcher::Regex(String::from(
+                "^<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>&quot;etag-1&quot;</ETag></Part>.*<PartNumber>3</PartNumber>",
+            )))
+            .with_status(200)
+            .with_body("<CompleteMultipartUploadResult><Key>workspace/blobs-store/ef/efgh</Key></CompleteMultipartUploadResult>")
+            .expect(1)
+            .create();
+        server
+            .mock("GET", "/mirror/workspace/denied")
+            .with_status(403)
+            .create();
+
+        assert_eq!(storage.root(), "s3://mirror/workspace/");
+        fs::create_dir_all(&cache_dir).expect("should create cache dir");
+        let tmp = cache_dir.clone() + "abcd.tmp";
+        fs::write(&tmp, "test").expect("should write file");
+        aw!(storage.write_file("blobs-store/ab/abcd", &tmp)).unwrap();
+        put.assert();
+        assert!(aw!(storage.exists("blobs-store/ab/abcd")).unwrap());
+        assert!(!aw!(storage.exists("blobs-store/ab/nada")).unwrap());
+        assert_eq!(
+            aw!(storage.list("blobs-store/")).unwrap(),
+            vec!["blobs-store/ab/abcd", "blobs-store/cd/cdef"]
+        );
+        let objects = aw!(storage.list_objects("blobs-store/")).unwrap();
+        assert_eq!(objects[0].size, 4);
+        assert_eq!(
+            objects[0].etag,
+            Some(String::from("098f6bcd4621d373cade4e832627b4f6"))
+        );
+        // the uploaded file is kept in the cache
+        let path = cache_dir.clone() + "blobs-store/ab/abcd";
+        assert!(!Path::new(&tmp).exists());
+        fs::remove_file(&path).expect("should remove cached blob");
+        let path = aw!(storage.fetch("blobs-store/ab/abcd")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "test");
+        // the blob is in the cache, it is not downloaded again
+        fs::write(&path, "cached").expect("should write cached blob");
+        let path = aw!(storage.fetch("blobs-store/ab/abcd")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "cached");
+
+        storage.part_size = 2;
+        let tmp = cache_dir.clone() + "efgh.tmp";
+        fs::write(&tmp, "test!").expect("should write file");
+        aw!(storage.write_file("blobs-store/ef/efgh", &tmp)).unwrap();
+        for part in parts.iter() {
+            part.assert();
+        }
+        complete.assert();
+        assert!(!Path::new(&tmp).exists());
+        assert_eq!(
+            fs::read_to_string(cache_dir.clone() + "blobs-store/ef/efgh").unwrap(),
+            "test!"
+        );
+        let res = aw!(storage.fetch("denied"));
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::NOPERM);
+        rm_rf::remove(&cache_dir).expect("should delete cache dir");
+    }
+
+    // run against minio with cargo test -- --ignored
+    // i.e. AWS_ENDPOINT_URL=http://localhost:9000 AWS_ACCESS_KEY_ID=minioadmin
+    // AWS_SECRET_ACCESS_KEY=minioadmin and an existing bucket named mirror
+    #[test]
+    #[ignore]
+    fn s3_storage_minio_pass() {
+        let cache_dir = String::from("./test-artifacts/minio-cache/");
+        let storage = S3Storage::from_env("mirror", "test", cache_dir.clone()).unwrap();
+        fs::create_dir_all(&cache_dir).expect("should create cache dir");
+        let tmp = cache_dir.clone() + "abcd.tmp";
+        fs::write(&tmp, "test").expect("should write file");
+        aw!(storage.write_file("blobs-store/ab/abcd", &tmp)).unwrap();
+        assert!(aw!(storage.exists("blobs-store/ab/abcd")).unwrap());
+        assert!(aw!(storage.list("blobs-store/"))
+            .unwrap()
+            .contains(&String::from("blobs-store/ab/abcd")));
+        let path = aw!(storage.fetch("blobs-store/ab/abcd")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "test");
+        rm_rf::remove(&cache_dir).expect("should delete cache dir");
+    }
+}
@@ -0,0 +1,672 @@
+use custom_logger::*;
+use reqwest::{Body, Client, RequestBuilder, Response, StatusCode};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::api::schema::Report;
+use crate::auth::token_cache::{DELETE, PUSH};
+use crate::cluster::resources::{get_manifest_digests, get_mirror_repo, get_mirrored_images};
+use crate::config::load::Mirror;
+use crate::error::handler::{registry_error, MirrorError, RequestError};
+use crate::reference::parser::Reference;
+use crate::scheduler::registry::{get_retry_delay, RegistryScheduler};
+use crate::verify::blobs::get_blob_path;
+
+const INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+// a repository in a registry i.e. quay.io/ns/name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repository {
+    pub registry: String,
+    pub name: String,
+    pub registry_url: String,
+    pub url: String,
+}
+
+impl Repository {
+    // the tag or digest of repo is ignored, repositories without a registry are on docker hub
+    pub fn new(repo: &str) -> Result<Repository, MirrorError> {
+        let reference = Reference::parse(repo)?;
+        let registry_url = get_registry_url(&reference.registry);
+        Ok(Repository {
+            url: registry_url.clone() + "/v2/" + &reference.repository,
+            registry: reference.registry,
+            name: reference.repository,
+            registry_url,
+        })
+    }
+}
+
+// local registries are served over http
+pub fn get_registry_url(registry: &str) -> String {
+    let scheme = if registry.starts_with("localhost") || registry.starts_with("127.0.0.1") {
+        "http://"
+    } else {
+        "https://"
+    };
+    scheme.to_string() + registry
+}
+
+// copies images between registries, blobs are streamed without writing them to the blobs-store
+// every request goes through the scheduler, sources get pull and destinations push tokens
+pub struct StreamCopy<'a> {
+    client: Client,
+    scheduler: &'a RegistryScheduler,
+    blobs_dir: String,
+    // digest -> destination repository that has the blob (for cross repository mounts)
+    pushed: HashMap<String, String>,
+    pub report: Report,
+}
+
+impl<'a> StreamCopy<'a> {
+    pub fn new(blobs_dir: &str, scheduler: &'a RegistryScheduler) -> StreamCopy<'a> {
+        StreamCopy {
+            client: Client::new(),
+            scheduler,
+            blobs_dir: blobs_dir.to_string(),
+            pushed: HashMap::new(),
+            report: Report::default(),
+        }
+    }
+
+    // send a request to a destination with the token for actions, the response is
+    // returned for every status the scheduler does not retry
+    async fn send<F>(
+        &self,
+        log: &Logging,
+        url: &str,
+        actions: &str,
+        request: F,
+    ) -> Result<Response, MirrorError>
+    where
+        F: Fn(&str) -> RequestBuilder,
+    {
+        self.scheduler
+            .run_destination(log, url, actions, |url, token| {
+                send_request(request(&url), url, token)
+            })
+            .await
+            .map_err(|err| registry_error(url, err))
+    }
+
+    async fn blob_exists(
+        &self,
+        log: &Logging,
+        repo: &Repository,
+        digest: &str,
+    ) -> Result<bool, MirrorError> {
+        let url = repo.url.clone() + "/blobs/" + digest;
+        let res = self
+            .send(log, &url, PUSH, |url| self.client.head(url))
+            .await?;
+        match res.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(MirrorError::RegistryHttp(format!(
+                "{} returned {}",
+                url, status
+            ))),
+        }
+    }
+
+    // start an upload, returns None when the blob pushed to another destination repository
+    // was mounted, otherwise the url the blob is uploaded to
+    async fn start_upload(
+        &self,
+        log: &Logging,
+        dest: &Repository,
+        digest: &str,
+    ) -> Result<Option<String>, MirrorError> {
+        let mut url = dest.url.clone() + "/blobs/uploads/";
+        if let Some(from) = self.pushed.get(digest) {
+            url = format!(
+                "{}?mount={}&from={}",
+                url,
+                urlencoding::encode(digest),
+                urlencoding::encode(from)
+            );
+        }
+        let res = self
+            .send(log, &url, PUSH, |url| self.client.post(url))
+            .await?;
+        // a registry that can not mount the blob starts an upload instead
+        if res.status() == StatusCode::CREATED {
+            log.debug(&format!("mounted blob {} in {}", digest, dest.name));
+            return Ok(None);
+        }
+        if res.status() != StatusCode::ACCEPTED {
+            return Err(MirrorError::RegistryHttp(format!(
+                "{} returned {}",
+                url,
+                res.status()
+            )));
+        }
+        let location = res
+            .headers()
+            .get("Location")
+            .and_then(|l| l.to_str().ok())
+            .ok_or_else(|| {
+                MirrorError::RegistryHttp(format!("{} returned no upload location", url))
+            })?;
+        let location = match location.starts_with("/") {
+            true => dest.registry_url.clone() + location,
+            false => location.to_string(),
+        };
+        let separator = if location.contains("?") { "&" } else { "?" };
+        Ok(Some(format!(
+            "{}{}digest={}",
+            location,
+            separator,
+            urlencoding::encode(digest)
+        )))
+    }
+
+    // the blob from the blobs-store (built catalogs) or the response body of the source
+    async fn open_blob(
+        &self,
+        log: &Logging,
+        source: &Repository,
+        digest: &str,
+    ) -> Result<(Body, u64), MirrorError> {
+        let local = get_blob_path(&self.blobs_dir, digest);
+        if Path::new(&local).exists() {
+            let data = fs::read(&local)?;
+            let size = data.len() as u64;
+            return Ok((Body::from(data), size));
+        }
+        // a source mirror that does not have the blob falls back to the next one
+        let src_url = source.url.clone() + "/blobs/" + digest;
+        let res = self
+            .scheduler
+            .run(log, &src_url, |url, token| {
+                let req = self.client.get(&url);
+                async move {
+                    let res = send_request(req, url.clone(), token).await?;
+                    if !res.status().is_success() {
+                        return Err(RequestError::from_response(&url, &res).into());
+                    }
+                    Ok(res)
+                }
+            })
+            .await
+            .map_err(|err| registry_error(&src_url, err))?;
+        let size = res.content_length().unwrap_or(0);
+        Ok((Body::wrap_stream(res.bytes_stream()), size))
+    }
+
+    // copy a blob the destination does not have yet, a blob pushed to another destination
+    // repository is mounted, otherwise it is read from the blobs-store or streamed from
+    // the source
+    // a streamed upload can not be sent again, a transient error starts the copy over
+    pub async fn copy_blob(
+        &mut self,
+        log: &Logging,
+        source: &Repository,
+        dest: &Repository,
+        digest: &str,
+    ) -> Result<(), MirrorError> {
+        if self.blob_exists(log, dest, digest).await? {
+            log.trace(&format!("blob {} exists in {}", digest, dest.name));
+            self.pushed
+                .entry(digest.to_string())
+                .or_insert_with(|| dest.name.clone());
+            return Ok(());
+        }
+        let max_retries = self.scheduler.get_config(&dest.registry).max_retries;
+        let mut attempt = 0;
+        let size = loop {
+            let upload_url = match self.start_upload(log, dest, digest).await? {
+                Some(url) => url,
+                None => return Ok(()),
+            };
+            let (body, size) = self.open_blob(log, source, digest).await?;
+            let body = Mutex::new(Some(body));
+            let res = self
+                .scheduler
+                .run_once(log, &upload_url, PUSH, |url, token| {
+                    let body = body
+                        .lock()
+                        .unwrap()
+                        .take()
+                        .unwrap_or_else(|| Body::from(""));
+                    let mut req = self
+                        .client
+                        .put(&url)
+                        .header("Content-Type", "application/octet-stream");
+                    if size > 0 {
+                        req = req.header("Content-Length", size);
+                    }
+                    send_request(req.body(body), url, token)
+                })
+                .await;
+            let err = match res {
+                Ok(res) if res.status() == StatusCode::CREATED => break size,
+                Ok(res) => {
+                    return Err(MirrorError::RegistryHttp(format!(
+                        "{} returned {}",
+                        upload_url,
+                        res.status()
+                    )))
+                }
+                Err(err) => err,
+            };
+            let delay = err
+                .downcast_ref::<RequestError>()
+                .and_then(|failed| get_retry_delay(failed, attempt));
+            match delay {
+                Some(delay) if attempt < max_retries => {
+                    log.debug(&format!(
+                        "upload of {} to {} failed ({}) retrying in {:?}",
+                        digest, dest.name, err, delay
+                    ));
+                    attempt += 1;
+                    tokio::time::sleep(delay).await;
+                }
+                _ => return Err(registry_error(&upload_url, err)),
+            }
+        };
+        log.debug(&format!("pushed blob {} to {}", digest, dest.name));
+        self.pushed.insert(digest.to_string(), dest.name.clone());
+        self.report.blobs += 1;
+        self.report.bytes += size as i64;
+        Ok(())
+    }
+
+    async fn push_manifest(
+        &self,
+        log: &Logging,
+        dest: &Repository,
+        reference: &str,
+        media_type: &str,
+        content: &str,
+    ) -> Result<(), MirrorError> {
+        let url = dest.url.clone() + "/manifests/" + reference;
+        let res = self
+            .send(log, &url, PUSH, |url| {
+                self.client
+                    .put(url)
+                    .header("Content-Type", media_type)
+                    .body(content.to_string())
+            })
+            .await?;
+        if !res.status().is_success() {
+            return Err(MirrorError::RegistryHttp(format!(
+                "{} returned {}",
+                url,
+                res.status()
+            )));
+        }
+        Ok(())
+    }
+
+    // delete a manifest by digest, returns false if the destination does not have it
+    pub async fn delete_manifest(
+        &self,
+        log: &Logging,
+        dest: &Repository,
+        digest: &str,
+    ) -> Result<bool, MirrorError> {
+        let url = dest.url.clone() + "/manifests/" + digest;
+        let res = self
+            .send(log, &url, DELETE, |url| self.client.delete(url))
+            .await?;
+        match res.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(MirrorError::RegistryHttp(format!(
+                "{} returned {}",
+                url, status
+            ))),
+        }
+    }
+
+    // copy the config and layer blobs of a manifest, then push the manifest
+    async fn copy_manifest(
+        &mut self,
+        log: &Logging,
+        source: &Repository,
+        dest: &Repository,
+        value: &Value,
+        content: &str,
+        reference: &str,
+    ) -> Result<(), MirrorError> {
+        let mut digests = vec![];
+        if let Some(digest) = value["config"]["digest"].as_str() {
+            digests.push(digest.to_string());
+        }
+        for layer in value["layers"].as_array().cloned().unwrap_or_default() {
+            if let Some(digest) = layer["digest"].as_str() {
+                digests.push(digest.to_string());
+            }
+        }
+        for digest in digests.iter() {
+            self.copy_blob(log, source, dest, digest).await?;
+        }
+        let media_type = value["mediaType"].as_str().unwrap_or(MANIFEST_MEDIA_TYPE);
+        self.push_manifest(log, dest, reference, media_type, content)
+            .await
+    }
+
+    // copy an image by tag or (without tag) by digest, returns the digest of the pushed manifest
+    // for a manifest list the child manifests found in manifests (digest -> file) are copied
+    // and the children of architectures not mirrored are dropped from the list
+    pub async fn copy_image(
+        &mut self,
+        log: &Logging,
+        source: &Repository,
+        dest: &Repository,
+        data: &str,
+        tag: Option<&str>,
+        manifests: &HashMap<String, String>,
+    ) -> Result<String, MirrorError> {
+        let content = get_pushed_content(data, manifests)?;
+        let value: Value = serde_json::from_str(&content)?;
+        let children = value["manifests"].as_array().cloned();
+        for child in children.iter().flatten() {
+            let digest = child["digest"].as_str().unwrap_or_default();
+            if let Some(file) = manifests.get(digest) {
+                let data = fs::read_to_string(file)?;
+                let child_value: Value = serde_json::from_str(&data)?;
+                self.copy_manifest(log, source, dest, &child_value, &data, digest)
+                    .await?;
+            }
+        }
+        if children.is_some() {
+            return self
+                .push_manifest_list(log, dest, data, tag, manifests)
+                .await;
+        }
+        let digest = get_content_digest(&content);
+        let reference = tag.unwrap_or(&digest).to_string();
+        self.copy_manifest(log, source, dest, &value, &content, &reference)
+            .await?;
+        self.report.images += 1;
+        Ok(digest)
+    }
+
+    // push a manifest list once its child manifests were pushed, by tag or (without tag)
+    // by digest, the children not found in manifests (digest -> file) are dropped
+    pub async fn push_manifest_list(
+        &mut self,
+        log: &Logging,
+        dest: &Repository,
+        data: &str,
+        tag: Option<&str>,
+        manifests: &HashMap<String, String>,
+    ) -> Result<String, MirrorError> {
+        let content = get_pushed_content(data, manifests)?;
+        let value: Value = serde_json::from_str(&content)?;
+        let digest = get_content_digest(&content);
+        let reference = tag.unwrap_or(&digest).to_string();
+        let media_type = value["mediaType"].as_str().unwrap_or(INDEX_MEDIA_TYPE);
+        self.push_manifest(log, dest, &reference, media_type, &content)
+            .await?;
+        self.report.images += 1;
+        Ok(digest)
+    }
+}
+
+// the content pushed for a manifest, the children of a manifest list that are not in
+// manifests (digest -> file) are dropped, the original digest is kept when all
+// architectures were mirrored
+pub fn get_pushed_content(
+    data: &str,
+    manifests: &HashMap<String, String>,
+) -> Result<String, MirrorError> {
+    let mut value: Value = serde_json::from_str(data)?;
+    let children = value["manifests"].as_array().cloned();
+    if let Some(children) = children {
+        let kept: Vec<Value> = children
+            .iter()
+            .filter(|c| manifests.contains_key(c["digest"].as_str().unwrap_or_default()))
+            .cloned()
+            .collect();
+        if kept.is_empty() {
+            return Err(MirrorError::ManifestParse(String::from(
+                "manifest list without mirrored manifests",
+            )));
+        }
+        if kept.len() != children.len() {
+            value["manifests"] = Value::Array(kept);
+            return Ok(serde_json::to_string(&value)?);
+        }
+    }
+    Ok(data.to_string())
+}
+
+pub fn get_content_digest(content: &str) -> String {
+    String::from("sha256:") + &hex::encode(Sha256::digest(content.as_bytes()))
+}
+
+// send a request with the token, statuses the scheduler refreshes the token for (401) or
+// retries are returned as RequestError
+async fn send_request(
+    req: RequestBuilder,
+    url: String,
+    token: String,
+) -> Result<Response, Box<dyn Error>> {
+    let req = if token.is_empty() {
+        req
+    } else {
+        req.bearer_auth(&token)
+    };
+    let res = req
+        .send()
+        .await
+        .map_err(|err| RequestError::from_reqwest(&url, err))?;
+    if !res.status().is_success() {
+        let err = RequestError::from_response(&url, &res);
+        if err.is_unauthorized() || err.is_transient() || res.status() == StatusCode::FORBIDDEN {
+            return Err(err.into());
+        }
+    }
+    Ok(res)
+}
+
+// copy the images resolved by the mirrorToDisk collectors directly from the source
+// registries to the destination, only the metadata is kept in the working-dir
+pub async fn mirror_to_mirror(
+    log: &Logging,
+    dir: String,
+    destination: String,
+    mirror: &Mirror,
+    scheduler: &RegistryScheduler,
+) -> Result<Report, MirrorError> {
+    log.hi("mirrorToMirror: streaming images to destination");
+    let manifests = get_manifest_digests(&dir)?;
+    let images = get_mirrored_images(log, dir.clone(), mirror, &manifests).await?;
+    let mut copy = StreamCopy::new(&(dir.clone() + "blobs-store/"), scheduler);
+    for (image, mi) in images.iter() {
+        let reference = Reference::parse(image)?;
+        let dest_repo = get_mirror_repo(&destination, &mi.sub_component);
+        // images with a digest are pushed by digest only
+        let tag = match reference.digest {
+            Some(_) => None,
+            None => Some(reference.version()),
+        };
+        // the scheduler streams the blobs from the source mirrors of the repository
+        let source = Repository::new(&reference.repo())?;
+        let dest = Repository::new(&dest_repo)?;
+        log.info(&format!("copying {} to {}", image, dest_repo));
+        copy.copy_image(
+            log,
+            &source,
+            &dest,
+            &fs::read_to_string(&mi.file)?,
+            tag.as_deref(),
+            &manifests,
+        )
+        .await
+        .map_err(|err| match err {
+            MirrorError::ManifestParse(msg) => {
+                MirrorError::ManifestParse(format!("{} {}", image, msg))
+            }
+            err => err,
+        })?;
+    }
+    Ok(copy.report)
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+    use crate::auth::token_cache::TokenCache;
+    use crate::config::registries::SourceMirrors;
+    use crate::scheduler::registry::SchedulerConfig;
+    use mockito::Matcher;
+
+    macro_rules! aw {
+        ($e:expr) => {
+            tokio_test::block_on($e)
+        };
+    }
+
+    fn get_scheduler() -> RegistryScheduler {
+        RegistryScheduler::new(
+            SchedulerConfig::default(),
+            &[],
+            SourceMirrors::default(),
+            TokenCache::anonymous(),
+        )
+    }
+
+    fn get_digest(data: &str) -> String {
+        String::from("sha256:") + &hex::encode(Sha256::digest(data.as_bytes()))
+    }
+
+    #[test]
+    fn copy_image_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let mut server = mockito::Server::new();
+        let host = server.host_with_port();
+        let config = get_digest("config");
+        let layer = get_digest("layer");
+        let manifest = format!(
+            r#"{{"schemaVersion": 2, "mediaType": "application/vnd.oci.image.manifest.v1+json", "config": {{"mediaType": "application/vnd.oci.image.config.v1+json", "digest": "{}", "size": 6}}, "layers": [{{"mediaType": "application/vnd.oci.image.layer.v1.tar+gzip", "digest": "{}", "size": 5}}]}}"#,
+            config, layer
+        );
+
+        // the destination already has the config
+        server
+            .mock(
+                "HEAD",
+                format!("/v2/test/ns/name/blobs/{}", config).as_str(),
+            )
+            .with_status(200)
+            .create();
+        server
+            .mock("HEAD", format!("/v2/test/ns/name/blobs/{}", layer).as_str())
+            .with_status(404)
+            .create();
+        server
+            .mock("POST", "/v2/test/ns/name/blobs/uploads/")
+            .with_status(202)
+            .with_header("Location", "/v2/test/ns/name/blobs/uploads/1234")
+            .create();
+        let source_blob = server
+            .mock("GET", format!("/v2/ns/name/blobs/{}", layer).as_str())
+            .with_status(200)
+            .with_body("layer")
+            .expect(1)
+            .create();
+        server
+            .mock("PUT", "/v2/test/ns/name/blobs/uploads/1234")
+            .match_query(Matcher::UrlEncoded("digest".into(), layer.clone()))
+            .match_body("layer")
+            .with_status(201)
+            .create();
+        let pushed = server
+            .mock("PUT", "/v2/test/ns/name/manifests/v1")
+            .match_body(manifest.as_str())
+            .with_status(201)
+            .create();
+
+        // the second repository mounts the blobs pushed to the first one
+        for digest in [&config, &layer] {
+            server
+                .mock(
+                    "HEAD",
+                    format!("/v2/test/ns/other/blobs/{}", digest).as_str(),
+                )
+                .with_status(404)
+                .create();
+        }
+        let mounted = server
+            .mock("POST", "/v2/test/ns/other/blobs/uploads/")
+            .match_query(Matcher::UrlEncoded("from".into(), "test/ns/name".into()))
+            .with_status(201)
+            .expect(2)
+            .create();
+        server
+            .mock(
+                "PUT",
+                format!("/v2/test/ns/other/manifests/{}", get_digest(&manifest)).as_str(),
+            )
+            .with_status(201)
+            .create();
+
+        let scheduler = get_scheduler();
+        let mut copy = StreamCopy::new("./test-artifacts/stream-test/blobs-store/", &scheduler);
+        let source = Repository::new(&(host.clone() + "/ns/name")).unwrap();
+        let dest = Repository::new(&(host.clone() + "/test/ns/name")).unwrap();
+        let res = aw!(copy.copy_image(log, &source, &dest, &manifest, Some("v1"), &HashMap::new()));
+        assert_eq!(res.unwrap(), get_digest(&manifest));
+        let dest = Repository::new(&(host + "/test/ns/other")).unwrap();
+        let res = aw!(copy.copy_image(log, &source, &dest, &manifest, None, &HashMap::new()));
+        assert!(res.is_ok());
+        source_blob.assert();
+        pushed.assert();
+        mounted.assert();
+        assert_eq!(
+            copy.report,
+            Report {
+                images: 2,
+                blobs: 1,
+                bytes: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn copy_image_fail() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let mut server = mockito::Server::new();
+        let host = server.host_with_port();
+        server
+            .mock(
+                "HEAD",
+                Matcher::Regex(String::from("^/v2/test/ns/name/blobs/")),
+            )
+            .with_status(401)
+            .create();
+        let manifest = r#"{"schemaVersion": 2, "layers": [{"digest": "sha256:1234", "size": 5}]}"#;
+        let scheduler = get_scheduler();
+        let mut copy = StreamCopy::new("./test-artifacts/stream-test/blobs-store/", &scheduler);
+        let source = Repository::new(&(host.clone() + "/ns/name")).unwrap();
+        let dest = Repository::new(&(host + "/test/ns/name")).unwrap();
+        let res = aw!(copy.copy_image(log, &source, &dest, manifest, None, &HashMap::new()));
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::NOPERM);
+        assert_eq!(
+            Repository::new("quay.io/ns/name").unwrap().url,
+            "https://quay.io/v2/ns/name"
+        );
+        // the tag and digest are not part of the repository
+        let sha = "sha256:5e03f571c5993f0853a910b7c0cab44ec0e451b94a9677ed82e921b54a4b735a";
+        let repo = Repository::new(&format!("quay.io/ns/name:v1@{}", sha)).unwrap();
+        assert_eq!(repo.name, "ns/name");
+        let repo = Repository::new("ubuntu:22.04").unwrap();
+        assert_eq!(repo.url, "https://docker.io/v2/library/ubuntu");
+        assert!(Repository::new("quay.io/NS/name").is_err());
+    }
+}
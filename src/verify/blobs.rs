@@ -0,0 +1,273 @@
+use custom_logger::*;
+use mirror_copy::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::error::handler::MirrorError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlobStatus {
+    Valid,
+    Missing,
+    Truncated,
+    Corrupt,
+}
+
+// result of walking all manifests in the working-dir
+#[derive(Debug, Default, Clone)]
+pub struct VerifyReport {
+    pub manifests: usize,
+    pub valid: usize,
+    pub missing: Vec<String>,
+    pub truncated: Vec<String>,
+    pub corrupt: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.truncated.is_empty() && self.corrupt.is_empty()
+    }
+}
+
+// get the on disk location of a blob i.e. blobs-store/ab/abcdef...
+pub fn get_blob_path(blobs_dir: &str, digest: &str) -> String {
+    let hash = digest.split(":").last().unwrap_or_default();
+    let prefix = if hash.len() > 2 { &hash[..2] } else { hash };
+    blobs_dir.trim_end_matches("/").to_string() + "/" + prefix + "/" + hash
+}
+
+// stream the file through sha256, returns the hex digest and the number of bytes read
+pub fn compute_sha256(path: &str) -> Result<(String, u64), MirrorError> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut total: u64 = 0;
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        total += n as u64;
+    }
+    Ok((hex::encode(hasher.finalize()), total))
+}
+
+// check a single blob against its digest (and expected size if known)
+pub fn check_blob(
+    blobs_dir: &str,
+    digest: &str,
+    size: Option<i64>,
+) -> Result<BlobStatus, MirrorError> {
+    let path = get_blob_path(blobs_dir, digest);
+    if !Path::new(&path).exists() {
+        return Ok(BlobStatus::Missing);
+    }
+    // only sha256 digests can be verified
+    if !digest.starts_with("sha256:") {
+        return Ok(BlobStatus::Valid);
+    }
+    let (hash, len) = compute_sha256(&path)?;
+    if hash == digest.trim_start_matches("sha256:") {
+        return Ok(BlobStatus::Valid);
+    }
+    match size {
+        Some(expected) if (len as i64) < expected => Ok(BlobStatus::Truncated),
+        _ => Ok(BlobStatus::Corrupt),
+    }
+}
+
+// verify a blob right after it was downloaded, a missing blob is an error
+// a blob that does not match its digest is removed so it will be fetched again
+pub fn verify_blob_digest(blobs_dir: &str, digest: &str) -> Result<(), MirrorError> {
+    let path = get_blob_path(blobs_dir, digest);
+    if !Path::new(&path).exists() {
+        return Err(MirrorError::Io(format!(
+            "blob {} not found in {}",
+            digest, blobs_dir
+        )));
+    }
+    if !digest.starts_with("sha256:") {
+        return Ok(());
+    }
+    let (hash, _) = compute_sha256(&path)?;
+    if hash != digest.trim_start_matches("sha256:") {
+        fs::remove_file(&path)?;
+        return Err(MirrorError::BlobDigestMismatch {
+            expected: digest.to_string(),
+            actual: String::from("sha256:") + &hash,
+        });
+    }
+    Ok(())
+}
+
+// walk every image manifest in the working-dir and check each referenced blob
+pub fn verify_working_dir(
+    log: &Logging,
+    dir: String,
+    blobs_dir: String,
+) -> Result<VerifyReport, MirrorError> {
+    let mut report = VerifyReport::default();
+    let mut checked: HashSet<String> = HashSet::new();
+    for e in WalkDir::new(&dir).into_iter().filter_map(|e| e.ok()) {
+        let path = e.path().display().to_string();
+        let file_name = e.file_name().to_string_lossy().to_string();
+        if !e.path().is_file()
+            || !file_name.starts_with("manifest")
+            || !file_name.ends_with(".json")
+            || file_name.contains("list")
+            || !(path.contains("/operators/")
+                || path.contains("/release/")
+                || path.contains("/additional/")
+                || path.contains("/catalog/"))
+        {
+            continue;
+        }
+        let data = fs::read_to_string(&path)?;
+        // the blobs of a manifest that can't be parsed can't be verified
+        let manifest: Manifest = serde_json::from_str(&data)
+            .map_err(|err| MirrorError::ManifestParse(format!("{} {}", path, err)))?;
+        report.manifests += 1;
+        let mut layers = manifest.layers.unwrap_or_default();
+        if let Some(config) = manifest.config {
+            layers.push(config);
+        }
+        for layer in layers.iter() {
+            if !checked.insert(layer.digest.clone()) {
+                continue;
+            }
+            match check_blob(&blobs_dir, &layer.digest, Some(layer.size))? {
+                BlobStatus::Valid => report.valid += 1,
+                BlobStatus::Missing => {
+                    log.error(&format!("missing blob {} ({})", layer.digest, path));
+                    report.missing.push(layer.digest.clone());
+                }
+                BlobStatus::Truncated => {
+                    log.error(&format!("truncated blob {} ({})", layer.digest, path));
+                    report.truncated.push(layer.digest.clone());
+                }
+                BlobStatus::Corrupt => {
+                    log.error(&format!("corrupt blob {} ({})", layer.digest, path));
+                    report.corrupt.push(layer.digest.clone());
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+// remove truncated and corrupt blobs so that the collectors fetch them again
+pub fn remove_invalid_blobs(
+    log: &Logging,
+    blobs_dir: String,
+    report: &VerifyReport,
+) -> Result<(), MirrorError> {
+    for digest in report.truncated.iter().chain(report.corrupt.iter()) {
+        let path = get_blob_path(&blobs_dir, digest);
+        log.debug(&format!("removing invalid blob {}", path));
+        fs::remove_file(&path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    // this brings everything from parent's scope into this scope
+    use super::*;
+
+    #[test]
+    fn get_blob_path_pass() {
+        let res = get_blob_path(
+            "working-dir/blobs-store/",
+            "sha256:a3ed95caeb02ffe68cdd9fd84406680ae93d633cb16422d00e8a7c22955b46d4",
+        );
+        assert_eq!(
+            res,
+            "working-dir/blobs-store/a3/a3ed95caeb02ffe68cdd9fd84406680ae93d633cb16422d00e8a7c22955b46d4"
+        );
+    }
+
+    #[test]
+    fn check_blob_pass() {
+        let blobs_dir = "test-artifacts/blobs-store";
+        let res = check_blob(
+            blobs_dir,
+            "sha256:a3ed95caeb02ffe68cdd9fd84406680ae93d633cb16422d00e8a7c22955b46d4",
+            Some(32),
+        );
+        assert_eq!(res.unwrap(), BlobStatus::Valid);
+        let res = check_blob(
+            blobs_dir,
+            "sha256:1b594048db9380f9a8dd2e45e16a2e12d39df51f6f61d9be4c9a2986cbc2828b",
+            Some(28793570),
+        );
+        assert_eq!(res.unwrap(), BlobStatus::Truncated);
+        let res = check_blob(
+            blobs_dir,
+            "sha256:1b594048db9380f9a8dd2e45e16a2e12d39df51f6f61d9be4c9a2986cbc2828b",
+            Some(18),
+        );
+        assert_eq!(res.unwrap(), BlobStatus::Corrupt);
+        let res = check_blob(blobs_dir, "sha256:0000000000", None);
+        assert_eq!(res.unwrap(), BlobStatus::Missing);
+    }
+
+    #[test]
+    fn verify_blob_digest_fail() {
+        let blobs_dir = "test-artifacts/verify-test";
+        let digest = "sha256:ab23d850616c11ac4041387983b3dd271728cdb601faef00e8c500b17e306077";
+        let path = get_blob_path(blobs_dir, digest);
+        fs::create_dir_all(blobs_dir.to_string() + "/ab").expect("should create test dir");
+        fs::write(&path, "corrupt blob data").expect("should write test blob");
+        let res = verify_blob_digest(blobs_dir, digest);
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::PROTOCOL);
+        assert!(!Path::new(&path).exists());
+        // the removed blob is reported as missing
+        let res = verify_blob_digest(blobs_dir, digest);
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::IOERR);
+        rm_rf::remove(blobs_dir).expect("should delete test dir");
+    }
+
+    #[test]
+    fn verify_working_dir_pass() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let res = verify_working_dir(
+            log,
+            String::from("test-artifacts/test-index-operator"),
+            String::from("test-artifacts/blobs-store"),
+        )
+        .unwrap();
+        assert!(res.manifests > 0);
+        assert!(!res.is_clean());
+        assert!(res.truncated.contains(&String::from(
+            "sha256:28ff5ee6facbc15dc879cb26daf949072ec01118d3463efd1f991d9b92e175ef"
+        )));
+    }
+
+    #[test]
+    fn verify_working_dir_fail() {
+        let log = &Logging {
+            log_level: Level::INFO,
+        };
+        let dir = String::from("test-artifacts/verify-truncated-test/");
+        let mnfst_dir = dir.clone() + "test-index/v1.0/operators/albo/stable-v1/";
+        fs::create_dir_all(&mnfst_dir).expect("should create test dir");
+        let data = fs::read_to_string(
+            "test-artifacts/test-index-operator/v1.0/operators/albo/aws-load-balancer-rhel8-operator/stable-v1/manifest.json",
+        )
+        .expect("should read test manifest");
+        fs::write(mnfst_dir.clone() + "manifest.json", &data[..data.len() / 2])
+            .expect("should write truncated manifest");
+        let res = verify_working_dir(log, dir.clone(), dir.clone() + "blobs-store/");
+        rm_rf::remove(&dir).expect("should delete test dir");
+        assert_eq!(res.unwrap_err().exit_code(), exitcode::DATAERR);
+    }
+}